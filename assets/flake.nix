@@ -66,13 +66,14 @@
             pkgs.buildPackages.dtc
           ];
           buildPhase = ''
-            ls . -all
-            dtc virt.dts -I dts -O dtb -o virt.dtb
+            for f in *.dts; do
+                dtc $f -I dts -O dtb -o $(basename $f .dts).dtb
+            done
           '';
 
           installPhase = ''
             mkdir -p $out
-            mv virt.dtb $out/
+            mv *.dtb $out/
           '';
         };
 
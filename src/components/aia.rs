@@ -0,0 +1,155 @@
+use crate::{
+    components::{
+        devices::{
+            aplic::{Aplic, Msi},
+            imsic::{Imsic, InterruptFile},
+        },
+        mmu::Size,
+        system_bus::{
+            APLIC_M_BASE, APLIC_M_END, APLIC_S_BASE, APLIC_S_END, IMSIC_M_BASE, IMSIC_M_END,
+            IMSIC_S_BASE, IMSIC_S_END,
+        },
+        trap::Exception,
+    },
+    config::AiaMode,
+    cpu::PrivilegeMode,
+};
+
+/// The Advanced Interrupt Architecture interrupt controllers of the platform.
+/// The M-level APLIC domain is the root, delegating sources to the S-level one,
+/// both either signaling the hart directly or forwarding MSIs to the IMSIC.
+pub struct Aia {
+    pub aplic_m: Aplic,
+    pub aplic_s: Aplic,
+    pub imsic: Option<Imsic>,
+}
+
+impl Aia {
+    pub fn new(mode: AiaMode) -> Option<Self> {
+        let msi = match mode {
+            AiaMode::None => return None,
+            AiaMode::Aplic => false,
+            AiaMode::AplicImsic => true,
+        };
+        Some(Self {
+            aplic_m: Aplic::new(true, msi),
+            aplic_s: Aplic::new(false, msi),
+            imsic: msi.then(Imsic::new),
+        })
+    }
+
+    /// Drive a wired interrupt source, into the domain the source is delegated to.
+    pub fn set_source(&mut self, irq: u32, level: bool) {
+        if self.aplic_m.is_delegated(irq) {
+            self.aplic_s.set_input(irq, level);
+        } else {
+            self.aplic_m.set_input(irq, level);
+        }
+    }
+
+    /// Interrupt file of the hart receiving the external interrupts of the privilege level.
+    pub fn file(&mut self, level: PrivilegeMode) -> Option<&mut InterruptFile> {
+        let imsic = self.imsic.as_mut()?;
+        match level {
+            PrivilegeMode::Machine => Some(&mut imsic.m_file),
+            _ => Some(&mut imsic.s_file),
+        }
+    }
+
+    /// Forward the pending MSIs and return the external interrupt lines as (MEIP, SEIP).
+    pub fn update(&mut self) -> (bool, bool) {
+        while let Some(msi) = self.aplic_m.next_msi() {
+            let address = self.aplic_m.msi_address(&msi, true);
+            self.deliver_msi(address, &msi);
+        }
+        while let Some(msi) = self.aplic_s.next_msi() {
+            let address = self.aplic_m.msi_address(&msi, false);
+            self.deliver_msi(address, &msi);
+        }
+
+        match &self.imsic {
+            Some(imsic) => (
+                imsic.m_file.is_interrupting(),
+                imsic.s_file.is_interrupting(),
+            ),
+            None => (
+                self.aplic_m.is_interrupting(),
+                self.aplic_s.is_interrupting(),
+            ),
+        }
+    }
+
+    fn deliver_msi(&mut self, address: u64, msi: &Msi) {
+        // The only MSI targets on the platform are the interrupt files,
+        // other addresses are dropped instead of being written to memory.
+        if let Some(imsic) = self.imsic.as_mut() {
+            match address {
+                IMSIC_M_BASE..IMSIC_M_END => imsic.m_file.set_pending(msi.eiid),
+                IMSIC_S_BASE..IMSIC_S_END => imsic.s_file.set_pending(msi.eiid),
+                _ => {}
+            }
+        }
+    }
+
+    /// Keep the sources of the S-level domain in sync with the delegation of the root domain.
+    fn update_delegation(&mut self) {
+        let delegated = self.aplic_m.delegated();
+        self.aplic_s.set_owned(delegated);
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        match address {
+            APLIC_M_BASE..APLIC_M_END | APLIC_S_BASE..APLIC_S_END => true,
+            IMSIC_M_BASE..IMSIC_M_END | IMSIC_S_BASE..IMSIC_S_END => self.imsic.is_some(),
+            _ => false,
+        }
+    }
+
+    /// `None` when the address doesn't belong to the AIA.
+    pub fn read(&mut self, address: u64, size: Size) -> Option<Result<u64, Exception>> {
+        if !self.contains(address) {
+            return None;
+        }
+        if size != Size::WORD {
+            return Some(Err(Exception::LoadAccessFault(address)));
+        }
+        let value = match address {
+            APLIC_M_BASE..APLIC_M_END => self.aplic_m.read(address - APLIC_M_BASE),
+            APLIC_S_BASE..APLIC_S_END => self.aplic_s.read(address - APLIC_S_BASE),
+            IMSIC_M_BASE..IMSIC_M_END => self
+                .file(PrivilegeMode::Machine)?
+                .read(address - IMSIC_M_BASE, size),
+            _ => self
+                .file(PrivilegeMode::Supervisor)?
+                .read(address - IMSIC_S_BASE, size),
+        };
+        Some(value.map(|v| v as u64))
+    }
+
+    /// `None` when the address doesn't belong to the AIA.
+    pub fn write(&mut self, address: u64, size: Size, value: u64) -> Option<Result<(), Exception>> {
+        if !self.contains(address) {
+            return None;
+        }
+        if size != Size::WORD {
+            return Some(Err(Exception::StoreAccessFault(address)));
+        }
+        let value = value as u32;
+        let result = match address {
+            APLIC_M_BASE..APLIC_M_END => {
+                let result = self.aplic_m.write(address - APLIC_M_BASE, value);
+                self.update_delegation();
+                result
+            }
+            APLIC_S_BASE..APLIC_S_END => self.aplic_s.write(address - APLIC_S_BASE, value),
+            IMSIC_M_BASE..IMSIC_M_END => {
+                self.file(PrivilegeMode::Machine)?
+                    .write(address - IMSIC_M_BASE, size, value)
+            }
+            _ => self
+                .file(PrivilegeMode::Supervisor)?
+                .write(address - IMSIC_S_BASE, size, value),
+        };
+        Some(result)
+    }
+}
@@ -19,6 +19,21 @@ pub const MIDELEG: usize = 0x303;
 pub const MIE: usize = 0x304;
 /// Machine trap-handler base address.
 pub const MTVEC: usize = 0x305;
+/// Machine virtual interrupt enables.
+pub const MVIEN: usize = 0x308;
+/// Machine virtual interrupt-pending bits.
+pub const MVIP: usize = 0x309;
+// Machine Configuration
+/// Machine environment configuration register.
+pub const MENVCFG: usize = 0x30a;
+/// Machine State Enable 0 Register.
+pub const MSTATEEN0: usize = 0x30c;
+/// Machine State Enable 3 Register.
+pub const MSTATEEN3: usize = 0x30f;
+/// Upper 32 bits of Machine State Enable 0 Register, RV32 only.
+pub const MSTATEEN0H: usize = 0x31c;
+/// Upper 32 bits of Machine State Enable 3 Register, RV32 only.
+pub const MSTATEEN3H: usize = 0x31f;
 // Machine Trap Handling
 /// Machine exception program counter.
 pub const MEPC: usize = 0x341;
@@ -28,6 +43,16 @@ pub const MCAUSE: usize = 0x342;
 pub const MTVAL: usize = 0x343;
 /// Machine interrupt pending.
 pub const MIP: usize = 0x344;
+// Machine-Level Window to Indirectly Accessed Registers
+/// Machine indirect register select.
+pub const MISELECT: usize = 0x350;
+/// Machine indirect register alias.
+pub const MIREG: usize = 0x351;
+// Machine-Level Interrupts
+/// Machine top external interrupt.
+pub const MTOPEI: usize = 0x35c;
+/// Machine top interrupt.
+pub const MTOPI: usize = 0xfb0;
 
 // Machine information registers
 /// Vendor ID.
@@ -43,9 +68,21 @@ const MCONFIGPTR: usize = 0xf15;
 
 /* --Supervisor-level CSR-- */
 // Supervisor Trap Setup
+/// Supervisor status register.
+pub const SSTATUS: usize = 0x100;
+/// Supervisor interrupt-enable register.
+pub const SIE: usize = 0x104;
 /// Supervisor trap handler base address.
 pub const STVEC: usize = 0x105;
 
+// Supervisor Configuration
+/// Supervisor environment configuration register.
+pub const SENVCFG: usize = 0x10a;
+/// Supervisor State Enable 0 Register.
+pub const SSTATEEN0: usize = 0x10c;
+/// Supervisor State Enable 3 Register.
+pub const SSTATEEN3: usize = 0x10f;
+
 /// Supervisor address translation and protection.
 pub const SAPT: usize = 0x180;
 // Supervisor Trap Handling
@@ -57,6 +94,35 @@ pub const SCAUSE: usize = 0x142;
 pub const STVAL: usize = 0x143;
 /// Supervisor interrupt pending.
 pub const SIP: usize = 0x144;
+// Supervisor-Level Window to Indirectly Accessed Registers
+/// Supervisor indirect register select.
+pub const SISELECT: usize = 0x150;
+/// Supervisor indirect register alias.
+pub const SIREG: usize = 0x151;
+// Supervisor-Level Interrupts
+/// Supervisor top external interrupt.
+pub const STOPEI: usize = 0x15c;
+/// Supervisor top interrupt.
+pub const STOPI: usize = 0xdb0;
+// Debug/Trace Registers
+/// Supervisor-mode context register.
+pub const SCONTEXT: usize = 0x5a8;
+
+/* mstateen0 bits, the state they control access to from less privileged modes */
+/// sstateen0
+pub const STATEEN0_SE0: u64 = 1 << 63;
+/// senvcfg
+pub const STATEEN0_ENVCFG: u64 = 1 << 62;
+/// siselect and sireg
+pub const STATEEN0_CSRIND: u64 = 1 << 60;
+/// The AIA state not controlled by CSRIND and IMSIC, which is stopi
+pub const STATEEN0_AIA: u64 = 1 << 59;
+/// stopei
+pub const STATEEN0_IMSIC: u64 = 1 << 58;
+/// scontext
+pub const STATEEN0_CONTEXT: u64 = 1 << 57;
+/// sstateen1-3, bit 63 of mstateen1-3
+pub const STATEEN_SE: u64 = 1 << 63;
 
 #[bitfield(u64)]
 pub struct MStatus {
@@ -131,6 +197,11 @@ pub struct MStatus {
     sie: u1,
 }
 
+/// satp.MODE without translation
+pub const SATP_MODE_BARE: u8 = 0;
+/// satp.MODE of the page-based 39-bit virtual addressing
+pub const SATP_MODE_SV39: u8 = 8;
+
 #[bitfield(u64)]
 pub struct Sapt {
     #[bits(60..=63, r)]
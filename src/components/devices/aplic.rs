@@ -0,0 +1,613 @@
+use arbitrary_int::{u1, u3, u4, u5, u6, u10, u11, u12, u14};
+use bitbybit::bitfield;
+
+use crate::{
+    components::trap::Exception,
+    util::{F, T},
+};
+
+/// Size of the addressable region of one interrupt domain
+pub const APLIC_SIZE: u64 = 0x8000;
+/// Number of implemented interrupt sources, 0 is never a valid source
+pub const APLIC_NUM_SOURCES: u32 = 96;
+const WORDS: usize = (APLIC_NUM_SOURCES as usize) / 32 + 1;
+
+/* Internal memory map addresses */
+// https://github.com/riscv/riscv-aia/blob/main/src/AdvPLIC.adoc
+const DOMAINCFG: u64 = 0x0000;
+const SOURCECFG_BASE: u64 = 0x0004;
+const SOURCECFG_END: u64 = 0x0ffc;
+/* MSI address configuration, only present in the root domain */
+const MMSIADDRCFG: u64 = 0x1bc0;
+const MMSIADDRCFGH: u64 = 0x1bc4;
+const SMSIADDRCFG: u64 = 0x1bc8;
+const SMSIADDRCFGH: u64 = 0x1bcc;
+const SETIP_BASE: u64 = 0x1c00;
+const SETIP_END: u64 = 0x1c7c;
+const SETIPNUM: u64 = 0x1cdc;
+const IN_CLRIP_BASE: u64 = 0x1d00;
+const IN_CLRIP_END: u64 = 0x1d7c;
+const CLRIPNUM: u64 = 0x1ddc;
+const SETIE_BASE: u64 = 0x1e00;
+const SETIE_END: u64 = 0x1e7c;
+const SETIENUM: u64 = 0x1edc;
+const CLRIE_BASE: u64 = 0x1f00;
+const CLRIE_END: u64 = 0x1f7c;
+const CLRIENUM: u64 = 0x1fdc;
+const SETIPNUM_LE: u64 = 0x2000;
+const SETIPNUM_BE: u64 = 0x2004;
+const GENMSI: u64 = 0x3000;
+const TARGET_BASE: u64 = 0x3004;
+const TARGET_END: u64 = 0x3ffc;
+/* Interrupt delivery control structure of hart 0, occupies 32 bytes */
+const IDC_BASE: u64 = 0x4000;
+const IDC_END: u64 = 0x401f;
+const IDELIVERY: u64 = 0x00;
+const IFORCE: u64 = 0x04;
+const ITHRESHOLD: u64 = 0x08;
+const TOPI: u64 = 0x18;
+const CLAIMI: u64 = 0x1c;
+
+/* Source modes */
+const INACTIVE: u8 = 0;
+const DETACHED: u8 = 1;
+const EDGE1: u8 = 4;
+const EDGE0: u8 = 5;
+const LEVEL1: u8 = 6;
+const LEVEL0: u8 = 7;
+
+#[bitfield(u32)]
+struct DomainCfg {
+    /// Interrupt Enable
+    #[bit(8, rw)]
+    ie: u1,
+    /// Delivery Mode, 0 = direct, 1 = MSI
+    #[bit(2, rw)]
+    dm: u1,
+    /// Big-Endian, only little-endian is supported
+    #[bit(0, r)]
+    be: u1,
+}
+
+#[bitfield(u32)]
+struct SourceCfg {
+    /// Delegate
+    #[bit(10, rw)]
+    d: u1,
+    /// Index of the child domain, when delegated
+    #[bits(0..=9, rw)]
+    child_index: u10,
+    /// Source Mode, when not delegated
+    #[bits(0..=2, rw)]
+    sm: u3,
+}
+
+#[bitfield(u32)]
+struct Target {
+    #[bits(18..=31, rw)]
+    hart_index: u14,
+    /// MSI delivery mode only
+    #[bits(12..=17, rw)]
+    guest_index: u6,
+    /// External Interrupt Identity, MSI delivery mode only
+    #[bits(0..=10, rw)]
+    eiid: u11,
+    /// Interrupt Priority, direct delivery mode only
+    #[bits(0..=7, rw)]
+    iprio: u8,
+}
+
+#[bitfield(u32)]
+struct MsiAddrCfgH {
+    /// Lock
+    #[bit(31, rw)]
+    l: u1,
+    /// Higher Hart Index Shift
+    #[bits(24..=28, rw)]
+    hhxs: u5,
+    /// Lower Hart Index Shift
+    #[bits(20..=22, rw)]
+    lhxs: u3,
+    /// Higher Hart Index Width
+    #[bits(16..=18, rw)]
+    hhxw: u3,
+    /// Lower Hart Index Width
+    #[bits(12..=15, rw)]
+    lhxw: u4,
+    /// High Base PPN
+    #[bits(0..=11, rw)]
+    ppn: u12,
+}
+
+/// A message signaled interrupt to be forwarded by the root domain.
+#[derive(Clone, Copy, Debug)]
+pub struct Msi {
+    pub hart: u32,
+    pub guest: u32,
+    pub eiid: u32,
+}
+
+/// Advanced Platform-Level Interrupt Controller, a single interrupt domain.
+/// The root (M-level) domain may delegate sources to its only child (S-level) domain.
+/// https://github.com/riscv/riscv-aia
+pub struct Aplic {
+    /// The root domain owns the msiaddrcfg registers and can delegate sources
+    root: bool,
+    domaincfg: DomainCfg,
+    sourcecfg: [u32; APLIC_NUM_SOURCES as usize + 1],
+    target: [u32; APLIC_NUM_SOURCES as usize + 1],
+    /// Sources this domain has been given by its parent
+    owned: [u32; WORDS],
+    /// Raw level of the interrupt wires
+    input: [u32; WORDS],
+    pending: [u32; WORDS],
+    enabled: [u32; WORDS],
+
+    mmsiaddrcfg: u32,
+    mmsiaddrcfgh: MsiAddrCfgH,
+    smsiaddrcfg: u32,
+    smsiaddrcfgh: MsiAddrCfgH,
+    genmsi: u32,
+
+    idelivery: u32,
+    iforce: u32,
+    ithreshold: u32,
+}
+
+impl Aplic {
+    pub fn new(root: bool, msi: bool) -> Self {
+        let mut domaincfg = DomainCfg::ZERO;
+        //& domaincfg.DM is read-only when a single delivery mode is supported
+        domaincfg.set_dm(u1::new(msi as u8));
+        Self {
+            root,
+            domaincfg,
+            sourcecfg: [0; APLIC_NUM_SOURCES as usize + 1],
+            target: [0; APLIC_NUM_SOURCES as usize + 1],
+            owned: [if root { u32::MAX } else { 0 }; WORDS],
+            input: [0; WORDS],
+            pending: [0; WORDS],
+            enabled: [0; WORDS],
+
+            mmsiaddrcfg: 0,
+            mmsiaddrcfgh: MsiAddrCfgH::ZERO,
+            smsiaddrcfg: 0,
+            smsiaddrcfgh: MsiAddrCfgH::ZERO,
+            genmsi: 0,
+
+            idelivery: 0,
+            iforce: 0,
+            ithreshold: 0,
+        }
+    }
+
+    #[inline]
+    fn bit(id: u32) -> (usize, u32) {
+        ((id >> 5) as usize, 1 << (id & 0x1f))
+    }
+
+    fn is_msi(&self) -> bool {
+        self.domaincfg.dm() == T
+    }
+
+    fn source_mode(&self, id: u32) -> u8 {
+        if id == 0 || id > APLIC_NUM_SOURCES {
+            return INACTIVE;
+        }
+        let (word, bit) = Self::bit(id);
+        let cfg = SourceCfg::new_with_raw_value(self.sourcecfg[id as usize]);
+        //& A source delegated to a child domain is inactive in the parent
+        if self.owned[word] & bit == 0 || cfg.d() == T {
+            return INACTIVE;
+        }
+        cfg.sm().value()
+    }
+
+    /// The input value after being inverted for the active-low modes
+    fn rectified(&self, id: u32) -> bool {
+        let (word, bit) = Self::bit(id);
+        let level = self.input[word] & bit != 0;
+        match self.source_mode(id) {
+            EDGE1 | LEVEL1 => level,
+            EDGE0 | LEVEL0 => !level,
+            _ => false,
+        }
+    }
+
+    fn set_pending_bit(&mut self, id: u32, value: bool) {
+        let (word, bit) = Self::bit(id);
+        if value {
+            self.pending[word] |= bit;
+        } else {
+            self.pending[word] &= !bit;
+        }
+    }
+
+    /// Drive the interrupt wire of a source.
+    pub fn set_input(&mut self, id: u32, level: bool) {
+        if id == 0 || id > APLIC_NUM_SOURCES {
+            return;
+        }
+        let (word, bit) = Self::bit(id);
+        let old = self.rectified(id);
+        if level {
+            self.input[word] |= bit;
+        } else {
+            self.input[word] &= !bit;
+        }
+        let new = self.rectified(id);
+
+        match self.source_mode(id) {
+            EDGE1 | EDGE0 if !old && new => self.set_pending_bit(id, true),
+            LEVEL1 | LEVEL0 => {
+                if self.is_msi() {
+                    //& In MSI mode the pending bit is set on the rectified input becoming high,
+                    //& and cleared when it goes low.
+                    if new != old {
+                        self.set_pending_bit(id, new);
+                    }
+                } else {
+                    //& In direct mode the pending bit follows the rectified input.
+                    self.set_pending_bit(id, new);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Pending bit changes requested by software, through setip*/in_clrip*/clripnum.
+    fn write_pending(&mut self, id: u32, value: bool) {
+        match self.source_mode(id) {
+            DETACHED | EDGE1 | EDGE0 => self.set_pending_bit(id, value),
+            //& For level sensitive sources in MSI mode, the pending bit can be set only while the rectified input is high
+            LEVEL1 | LEVEL0 if self.is_msi() => {
+                self.set_pending_bit(id, value && self.rectified(id))
+            }
+            _ => {}
+        }
+    }
+
+    fn write_enable(&mut self, id: u32, value: bool) {
+        if self.source_mode(id) == INACTIVE {
+            return;
+        }
+        let (word, bit) = Self::bit(id);
+        if value {
+            self.enabled[word] |= bit;
+        } else {
+            self.enabled[word] &= !bit;
+        }
+    }
+
+    fn write_sourcecfg(&mut self, id: u32, value: u32) {
+        let (word, bit) = Self::bit(id);
+        if self.owned[word] & bit == 0 {
+            return;
+        }
+        let mut cfg = SourceCfg::new_with_raw_value(value);
+        if cfg.d() == T {
+            if !self.root {
+                // Leaf domain, no children to delegate to
+                cfg = SourceCfg::ZERO;
+            } else {
+                // A single child domain exists
+                cfg = SourceCfg::ZERO.with_d(T);
+            }
+        } else if !matches!(cfg.sm().value(), DETACHED | EDGE1 | EDGE0 | LEVEL1 | LEVEL0) {
+            //& Reserved source modes are treated as Inactive
+            cfg = SourceCfg::ZERO;
+        } else {
+            cfg = SourceCfg::ZERO.with_sm(cfg.sm());
+        }
+        self.sourcecfg[id as usize] = cfg.raw_value();
+
+        if self.source_mode(id) == INACTIVE {
+            //& When a source is inactive, its pending and enable bits are read-only zero and target is zero
+            self.pending[word] &= !bit;
+            self.enabled[word] &= !bit;
+            self.target[id as usize] = 0;
+        } else if !self.is_msi() && self.target[id as usize] == 0 {
+            //& A priority number of zero is not valid, the minimum being 1
+            self.target[id as usize] = 1;
+        }
+        // Re-evaluate the level sensitive pending bit with the new mode
+        if matches!(self.source_mode(id), LEVEL1 | LEVEL0) {
+            self.set_pending_bit(id, self.rectified(id));
+        }
+    }
+
+    fn write_target(&mut self, id: u32, value: u32) {
+        if self.source_mode(id) == INACTIVE {
+            return;
+        }
+        let val = Target::new_with_raw_value(value);
+        // Only hart 0 exists, so the hart index is read-only zero
+        let mut target = Target::ZERO;
+        if self.is_msi() {
+            // No guest interrupt files are implemented
+            target.set_eiid(val.eiid());
+        } else {
+            target.set_iprio(val.iprio().max(1));
+        }
+        self.target[id as usize] = target.raw_value();
+    }
+
+    /// Sources delegated to the child domain
+    pub fn delegated(&self) -> [u32; WORDS] {
+        let mut mask = [0; WORDS];
+        for id in 1..=APLIC_NUM_SOURCES {
+            let cfg = SourceCfg::new_with_raw_value(self.sourcecfg[id as usize]);
+            if cfg.d() == T {
+                let (word, bit) = Self::bit(id);
+                mask[word] |= bit;
+            }
+        }
+        mask
+    }
+
+    /// Set the sources handed over by the parent domain.
+    pub fn set_owned(&mut self, owned: [u32; WORDS]) {
+        for id in 1..=APLIC_NUM_SOURCES {
+            let (word, bit) = Self::bit(id);
+            if owned[word] & bit == 0 && self.owned[word] & bit != 0 {
+                //& Sources that are no longer delegated become inactive
+                self.owned[word] &= !bit;
+                self.write_sourcecfg_inactive(id);
+            }
+        }
+        self.owned = owned;
+    }
+
+    fn write_sourcecfg_inactive(&mut self, id: u32) {
+        let (word, bit) = Self::bit(id);
+        self.sourcecfg[id as usize] = 0;
+        self.target[id as usize] = 0;
+        self.pending[word] &= !bit;
+        self.enabled[word] &= !bit;
+    }
+
+    /// Whether the source is handled by the child domain
+    pub fn is_delegated(&self, id: u32) -> bool {
+        id != 0
+            && id <= APLIC_NUM_SOURCES
+            && SourceCfg::new_with_raw_value(self.sourcecfg[id as usize]).d() == T
+    }
+
+    /// Next MSI to be sent in MSI delivery mode.
+    /// The pending bit of the forwarded source is cleared.
+    pub fn next_msi(&mut self) -> Option<Msi> {
+        if !self.is_msi() {
+            return None;
+        }
+        //& genmsi.Busy is set while the write of the MSI is pending
+        if self.genmsi & (1 << 12) != 0 {
+            self.genmsi &= !(1 << 12);
+            return Some(Msi {
+                hart: self.genmsi >> 18,
+                guest: 0,
+                eiid: self.genmsi & 0x7ff,
+            });
+        }
+        if self.domaincfg.ie() == F {
+            return None;
+        }
+        for id in 1..=APLIC_NUM_SOURCES {
+            let (word, bit) = Self::bit(id);
+            if self.pending[word] & self.enabled[word] & bit == 0 {
+                continue;
+            }
+            self.pending[word] &= !bit;
+            let target = Target::new_with_raw_value(self.target[id as usize]);
+            return Some(Msi {
+                hart: target.hart_index().value() as u32,
+                guest: target.guest_index().value() as u32,
+                eiid: target.eiid().value() as u32,
+            });
+        }
+        None
+    }
+
+    /// Address of the interrupt file targeted by an MSI, computed by the root domain.
+    pub fn msi_address(&self, msi: &Msi, machine: bool) -> u64 {
+        let cfgh = self.mmsiaddrcfgh;
+        let (low, high, lhxs) = if machine {
+            (self.mmsiaddrcfg, cfgh.ppn(), cfgh.lhxs())
+        } else {
+            (
+                self.smsiaddrcfg,
+                self.smsiaddrcfgh.ppn(),
+                self.smsiaddrcfgh.lhxs(),
+            )
+        };
+        let base_ppn = (high.value() as u64) << 32 | low as u64;
+        let lhxw = cfgh.lhxw().value() as u64;
+        let hhxw = cfgh.hhxw().value() as u64;
+        let hart = msi.hart as u64;
+        //& g = (hart index >> LHXW) & (2^HHXW − 1), h = hart index & (2^LHXW − 1)
+        let g = (hart >> lhxw) & ((1 << hhxw) - 1);
+        let h = hart & ((1 << lhxw) - 1);
+        let ppn = base_ppn
+            | g << (cfgh.hhxs().value() as u64 + 12)
+            | h << lhxs.value()
+            | msi.guest as u64;
+        ppn << 12
+    }
+
+    /// The highest priority pending-and-enabled source for the hart, as topi reports it.
+    fn topi(&self) -> u32 {
+        let mut top: Option<(u32, u32)> = None;
+        for id in 1..=APLIC_NUM_SOURCES {
+            let (word, bit) = Self::bit(id);
+            if self.pending[word] & self.enabled[word] & bit == 0 {
+                continue;
+            }
+            let prio = Target::new_with_raw_value(self.target[id as usize]).iprio() as u32;
+            //& Only priorities lower than ithreshold can signal an interrupt, when ithreshold != 0
+            if self.ithreshold != 0 && prio >= self.ithreshold {
+                continue;
+            }
+            //& Ties are broken in favor of the lowest identity
+            if top.is_none_or(|(_, p)| prio < p) {
+                top = Some((id, prio));
+            }
+        }
+        top.map_or(0, |(id, prio)| id << 16 | prio)
+    }
+
+    fn claimi(&mut self) -> u32 {
+        let top = self.topi();
+        if top == 0 {
+            //& If topi is zero, reading claimi clears iforce
+            self.iforce = 0;
+            return 0;
+        }
+        let id = top >> 16;
+        // Level sensitive sources are cleared only by their input
+        if matches!(self.source_mode(id), DETACHED | EDGE1 | EDGE0) {
+            self.set_pending_bit(id, false);
+        }
+        top
+    }
+
+    /// Interrupt output signal towards the hart, direct delivery mode only
+    pub fn is_interrupting(&self) -> bool {
+        !self.is_msi()
+            && self.domaincfg.ie() == T
+            && self.idelivery == 1
+            && (self.iforce == 1 || self.topi() != 0)
+    }
+
+    fn read_word<F: Fn(u32) -> bool>(&self, word: usize, f: F) -> u32 {
+        (0..32)
+            .filter(|b| f((word as u32) << 5 | b))
+            .fold(0, |mask, b| mask | 1 << b)
+    }
+
+    pub fn read(&mut self, offset: u64) -> Result<u32, Exception> {
+        // offset only applied to words
+        let word = |base: u64| ((offset - base) >> 2) as usize;
+        let source = |base: u64| ((offset - base) >> 2) as u32 + 1;
+        let value = match offset {
+            //& Bits 31:24 of domaincfg are read-only 0x80
+            DOMAINCFG => 0x8000_0000 | self.domaincfg.raw_value(),
+            SOURCECFG_BASE..=SOURCECFG_END => {
+                let id = source(SOURCECFG_BASE);
+                if id <= APLIC_NUM_SOURCES && self.owned[Self::bit(id).0] & Self::bit(id).1 != 0 {
+                    self.sourcecfg[id as usize]
+                } else {
+                    0
+                }
+            }
+            MMSIADDRCFG if self.root => self.mmsiaddrcfg,
+            MMSIADDRCFGH if self.root => self.mmsiaddrcfgh.raw_value(),
+            SMSIADDRCFG if self.root => self.smsiaddrcfg,
+            SMSIADDRCFGH if self.root => self.smsiaddrcfgh.raw_value(),
+            SETIP_BASE..=SETIP_END => self.pending.get(word(SETIP_BASE)).copied().unwrap_or(0),
+            //& Reading in_clrip returns the rectified input values
+            IN_CLRIP_BASE..=IN_CLRIP_END => {
+                let w = word(IN_CLRIP_BASE);
+                if w < WORDS {
+                    self.read_word(w, |id| self.rectified(id))
+                } else {
+                    0
+                }
+            }
+            SETIE_BASE..=SETIE_END => self.enabled.get(word(SETIE_BASE)).copied().unwrap_or(0),
+            GENMSI if self.is_msi() => self.genmsi,
+            TARGET_BASE..=TARGET_END => {
+                let id = source(TARGET_BASE);
+                if id <= APLIC_NUM_SOURCES {
+                    self.target[id as usize]
+                } else {
+                    0
+                }
+            }
+            IDC_BASE..=IDC_END if !self.is_msi() => match offset - IDC_BASE {
+                IDELIVERY => self.idelivery,
+                IFORCE => self.iforce,
+                ITHRESHOLD => self.ithreshold,
+                TOPI => self.topi(),
+                CLAIMI => self.claimi(),
+                _ => 0,
+            },
+            //& setipnum, clripnum, setienum, clrienum, clrie and setipnum_le/be read as zero
+            _ => 0,
+        };
+        Ok(value)
+    }
+
+    pub fn write(&mut self, offset: u64, value: u32) -> Result<(), Exception> {
+        let word = |base: u64| ((offset - base) >> 2) as u32;
+        let source = |base: u64| ((offset - base) >> 2) as u32 + 1;
+        let locked = self.mmsiaddrcfgh.l() == T;
+        match offset {
+            DOMAINCFG => {
+                let val = DomainCfg::new_with_raw_value(value);
+                self.domaincfg.set_ie(val.ie());
+            }
+            SOURCECFG_BASE..=SOURCECFG_END => {
+                let id = source(SOURCECFG_BASE);
+                if id <= APLIC_NUM_SOURCES {
+                    self.write_sourcecfg(id, value);
+                }
+            }
+            MMSIADDRCFG if self.root && !locked => self.mmsiaddrcfg = value,
+            MMSIADDRCFGH if self.root && !locked => {
+                self.mmsiaddrcfgh = MsiAddrCfgH::new_with_raw_value(value & 0x9f77_ffff)
+            }
+            SMSIADDRCFG if self.root && !locked => self.smsiaddrcfg = value,
+            SMSIADDRCFGH if self.root && !locked => {
+                // Only LHXS and the PPN are held by smsiaddrcfgh
+                self.smsiaddrcfgh = MsiAddrCfgH::new_with_raw_value(value & 0x0070_0fff)
+            }
+            SETIP_BASE..=SETIP_END => {
+                let base = word(SETIP_BASE) << 5;
+                (0..32)
+                    .filter(|b| value & (1 << b) != 0)
+                    .for_each(|b| self.write_pending(base | b, true));
+            }
+            SETIPNUM | SETIPNUM_LE => self.write_pending(value, true),
+            SETIPNUM_BE => self.write_pending(value.swap_bytes(), true),
+            IN_CLRIP_BASE..=IN_CLRIP_END => {
+                let base = word(IN_CLRIP_BASE) << 5;
+                (0..32)
+                    .filter(|b| value & (1 << b) != 0)
+                    .for_each(|b| self.write_pending(base | b, false));
+            }
+            CLRIPNUM => self.write_pending(value, false),
+            SETIE_BASE..=SETIE_END => {
+                let base = word(SETIE_BASE) << 5;
+                (0..32)
+                    .filter(|b| value & (1 << b) != 0)
+                    .for_each(|b| self.write_enable(base | b, true));
+            }
+            SETIENUM => self.write_enable(value, true),
+            CLRIE_BASE..=CLRIE_END => {
+                let base = word(CLRIE_BASE) << 5;
+                (0..32)
+                    .filter(|b| value & (1 << b) != 0)
+                    .for_each(|b| self.write_enable(base | b, false));
+            }
+            CLRIENUM => self.write_enable(value, false),
+            GENMSI if self.is_msi() => {
+                // Hart index and EIID, with the Busy bit set until the MSI is sent
+                self.genmsi = (value & 0xfffc_07ff) | (1 << 12);
+            }
+            TARGET_BASE..=TARGET_END => {
+                let id = source(TARGET_BASE);
+                if id <= APLIC_NUM_SOURCES {
+                    self.write_target(id, value);
+                }
+            }
+            IDC_BASE..=IDC_END if !self.is_msi() => match offset - IDC_BASE {
+                IDELIVERY => self.idelivery = value & 1,
+                IFORCE => self.iforce = value & 1,
+                // 8 bits of priority are implemented
+                ITHRESHOLD => self.ithreshold = value & 0xff,
+                _ => {}
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+}
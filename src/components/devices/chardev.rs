@@ -0,0 +1,193 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, LineWriter, Read, Write},
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+};
+
+use crate::{components::devices::console::Console, config::SerialBackend};
+
+/// Host side of a serial line, exchanging the bytes of a UART without ever blocking the machine.
+pub trait CharBackend {
+    /// Send a byte to the host, dropped when nobody is there to receive it.
+    fn write(&mut self, byte: u8);
+
+    /// The next byte from the host, if one is available.
+    fn read(&mut self) -> Option<u8>;
+
+    /// The user asked to quit the emulator through the backend.
+    fn quit_requested(&self) -> bool {
+        false
+    }
+}
+
+/// Open the backend described by the configuration.
+/// `console_input` lets the stdio backend read the host terminal.
+pub fn open(backend: &SerialBackend, console_input: bool) -> io::Result<Box<dyn CharBackend>> {
+    Ok(match backend {
+        SerialBackend::Stdio => Box::new(Console::new(console_input)),
+        SerialBackend::Null => Box::new(Null),
+        SerialBackend::File(path) => Box::new(LogFile::new(path)?),
+        SerialBackend::Unix(path) => Box::new(Socket::new(path)?),
+        SerialBackend::Pty => Box::new(Pty::new()?),
+    })
+}
+
+/// Discards the output, with no input.
+pub struct Null;
+
+impl CharBackend for Null {
+    fn write(&mut self, _: u8) {}
+
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Appends the output to a file, with no input.
+pub struct LogFile {
+    // Flushed at each line, so that the log can be followed while running
+    file: LineWriter<File>,
+}
+
+impl LogFile {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: LineWriter::new(file),
+        })
+    }
+}
+
+impl CharBackend for LogFile {
+    fn write(&mut self, byte: u8) {
+        let _ = self.file.write_all(&[byte]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Listening Unix domain socket, serving a client at a time.
+/// Without a client, the output is dropped.
+pub struct Socket {
+    listener: UnixListener,
+    client: Option<UnixStream>,
+}
+
+impl Socket {
+    /// Replaces a stale socket left at `path` by a previous run.
+    pub fn new(path: &Path) -> io::Result<Self> {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    fn accept(&mut self) {
+        if self.client.is_some() {
+            return;
+        }
+        if let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.client = Some(stream);
+            }
+        }
+    }
+}
+
+impl CharBackend for Socket {
+    fn write(&mut self, byte: u8) {
+        self.accept();
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        match client.write(&[byte]) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => self.client = None,
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.accept();
+        let client = self.client.as_mut()?;
+        let mut byte = [0];
+        match client.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+            // The client went away, wait for the next one
+            _ => {
+                self.client = None;
+                None
+            }
+        }
+    }
+}
+
+/// Host pseudo-terminal, for a terminal emulator to attach to its slave side.
+pub struct Pty {
+    master: File,
+}
+
+impl Pty {
+    /// Prints the path of the slave side, as qemu does.
+    pub fn new() -> io::Result<Self> {
+        // Safety: the descriptor is owned by the File once opened, and ptsname is read before any other call
+        let (master, name) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from(OwnedFd::from_raw_fd(fd));
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let name = std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned();
+            // Raw mode, the bytes pass through untouched
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(fd, libc::TCSANOW, &termios);
+            }
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            (master, name)
+        };
+        eprintln!("char device redirected to {name}");
+        Ok(Self { master })
+    }
+}
+
+impl CharBackend for Pty {
+    fn write(&mut self, byte: u8) {
+        // Dropped when the slave side isn't open or is full
+        let _ = self.master.write(&[byte]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.master.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
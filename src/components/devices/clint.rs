@@ -0,0 +1,93 @@
+use std::time::Instant;
+
+use crate::components::{mmu::Size, trap::Exception};
+
+/// Size of the addressable region
+pub const CLINT_SIZE: u64 = 0x1_0000;
+/// Frequency of mtime by default, the one of qemu `virt`
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/* Internal memory map addresses, of the single hart */
+// https://github.com/riscv/riscv-aclint/blob/main/riscv-aclint.adoc
+const MSIP: u64 = 0x0000;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+/// Core Local Interruptor, the machine timer and software interrupts of the hart.
+/// mtime follows the host clock, counting at the timebase frequency,
+/// which has to match the `timebase-frequency` given to the software in the device tree.
+pub struct Clint {
+    frequency: u64,
+    /// Value of mtime at `epoch`
+    mtime: u64,
+    epoch: Instant,
+    mtimecmp: u64,
+    msip: bool,
+}
+
+impl Clint {
+    pub fn new(frequency: u64) -> Self {
+        Self {
+            frequency,
+            mtime: 0,
+            epoch: Instant::now(),
+            //& mtimecmp isn't reset, it starts far in the future so that no timer interrupt is pending
+            mtimecmp: u64::MAX,
+            msip: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.frequency);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        let ticks = self.epoch.elapsed().as_nanos() * self.frequency as u128 / 1_000_000_000;
+        self.mtime.wrapping_add(ticks as u64)
+    }
+
+    fn set_mtime(&mut self, value: u64) {
+        self.mtime = value;
+        self.epoch = Instant::now();
+    }
+
+    /// Machine timer interrupt signal.
+    //& A machine timer interrupt becomes pending whenever mtime contains a value greater than or equal to mtimecmp.
+    pub fn mtip(&self) -> bool {
+        self.mtime() >= self.mtimecmp
+    }
+
+    /// Machine software interrupt signal.
+    pub fn msip(&self) -> bool {
+        self.msip
+    }
+
+    // mtime and mtimecmp are also reached a half at a time, as on RV32
+    pub fn read(&self, offset: u64, size: Size) -> Result<u64, Exception> {
+        let value = match (offset & !7, size) {
+            (MSIP, Size::WORD) if offset == MSIP => self.msip as u64,
+            (MTIMECMP, _) => self.mtimecmp,
+            (MTIME, _) => self.mtime(),
+            _ => return Err(Exception::LoadAccessFault(offset)),
+        };
+        Ok(match size {
+            Size::DWORD => value,
+            _ => (value >> ((offset & 4) * 8)) & 0xffff_ffff,
+        })
+    }
+
+    pub fn write(&mut self, offset: u64, size: Size, value: u64) -> Result<(), Exception> {
+        let shift = (offset & 4) * 8;
+        let (mask, value) = match size {
+            Size::DWORD => (!0, value),
+            _ => (0xffff_ffff << shift, (value & 0xffff_ffff) << shift),
+        };
+        match (offset & !7, size) {
+            (MSIP, Size::WORD) if offset == MSIP => self.msip = value & 1 != 0,
+            (MTIMECMP, _) => self.mtimecmp = (self.mtimecmp & !mask) | (value & mask),
+            (MTIME, _) => self.set_mtime((self.mtime() & !mask) | (value & mask)),
+            _ => return Err(Exception::StoreAccessFault(offset)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    io::{self, Write},
+    mem,
+};
+
+use crate::components::devices::chardev::CharBackend;
+
+/// Ctrl-A, introducing the commands of the console
+pub const ESCAPE: u8 = 0x01;
+
+/// What a key typed on the console amounts to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    /// A byte for the guest.
+    Send(u8),
+    /// Ctrl-A x, quit the emulator.
+    Quit,
+    /// Ctrl-A h, list the commands.
+    Help,
+}
+
+/// Splits the keys typed on the console into the bytes for the guest and the Ctrl-A commands.
+#[derive(Default)]
+pub struct EscapeDecoder {
+    /// A Ctrl-A was typed, the next key is a command
+    escaped: bool,
+}
+
+impl EscapeDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The action of the key, none for the Ctrl-A introducing a command and for the unknown commands.
+    pub fn decode(&mut self, byte: u8) -> Option<Key> {
+        if !self.escaped {
+            self.escaped = byte == ESCAPE;
+            return (!self.escaped).then_some(Key::Send(byte));
+        }
+        self.escaped = false;
+        match byte {
+            b'x' => Some(Key::Quit),
+            b'h' => Some(Key::Help),
+            ESCAPE => Some(Key::Send(ESCAPE)),
+            // Unknown commands are dropped
+            _ => None,
+        }
+    }
+}
+
+/// The host terminal, as the stdio backend of a UART.
+/// Output goes to stdout, input is read from stdin without blocking, with the terminal in raw mode
+/// so that every key reaches the guest, restored when the console is dropped.
+/// Ctrl-A x quits the emulator, Ctrl-A Ctrl-A sends a Ctrl-A.
+pub struct Console {
+    input: bool,
+    /// Settings of the terminal before entering raw mode
+    saved: Option<libc::termios>,
+    escape: EscapeDecoder,
+    quit: bool,
+}
+
+impl Console {
+    /// With `input`, stdin is read into the guest.
+    pub fn new(input: bool) -> Self {
+        Self {
+            input,
+            saved: input.then(enter_raw_mode).flatten(),
+            escape: EscapeDecoder::new(),
+            quit: false,
+        }
+    }
+
+    /// A byte from stdin when one is available, without waiting.
+    fn read_host(&mut self) -> Option<u8> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Safety: a single valid pollfd, and a one-byte buffer for the read
+        let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+        if ready <= 0 || fd.revents & (libc::POLLIN | libc::POLLHUP) == 0 {
+            return None;
+        }
+        let mut byte = 0u8;
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match read {
+            1 => Some(byte),
+            // The end of the input, stdin isn't polled anymore
+            0 => {
+                self.input = false;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl CharBackend for Console {
+    fn write(&mut self, byte: u8) {
+        let mut stdout = io::stdout().lock();
+        // Nothing to report to the guest when the host output is gone
+        let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+    }
+
+    /// The next byte typed, if any, after the handling of the commands.
+    fn read(&mut self) -> Option<u8> {
+        if !self.input {
+            return None;
+        }
+        while let Some(byte) = self.read_host() {
+            match self.escape.decode(byte) {
+                Some(Key::Send(byte)) => return Some(byte),
+                Some(Key::Quit) => {
+                    self.quit = true;
+                    return None;
+                }
+                Some(Key::Help) => print!("\r\nC-a x    exit\r\nC-a C-a  send C-a\r\n"),
+                None => {}
+            }
+        }
+        None
+    }
+
+    /// Ctrl-A x was typed.
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            // Safety: restoring the settings read from the same terminal
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+        }
+    }
+}
+
+/// Put the terminal of stdin in raw mode, returning its previous settings.
+/// None when stdin isn't a terminal.
+fn enter_raw_mode() -> Option<libc::termios> {
+    // Safety: termios is plain data, filled in by tcgetattr before use
+    unsafe {
+        let mut saved: libc::termios = mem::zeroed();
+        if libc::isatty(libc::STDIN_FILENO) == 0
+            || libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0
+        {
+            return None;
+        }
+        let mut raw = saved;
+        // As qemu's stdio: no line editing, echo or signals, and the output still post-processed
+        raw.c_iflag &= !(libc::IGNBRK
+            | libc::BRKINT
+            | libc::PARMRK
+            | libc::ISTRIP
+            | libc::INLCR
+            | libc::IGNCR
+            | libc::ICRNL
+            | libc::IXON);
+        raw.c_oflag |= libc::OPOST;
+        raw.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+        Some(saved)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::components::{
+    devices::{Device, DeviceEvent},
+    dma::Dma,
+    mmu::Size,
+    pma::Pma,
+    trap::Exception,
+};
+
+/// Size of the addressable region
+pub const DMAC_SIZE: u64 = 0x1000;
+pub const IRQ_DMAC: u32 = 0x0c;
+/// Requester id of the DMA, in the device directory of the IOMMU
+pub const DMAC_DEVICE_ID: u32 = 1;
+
+/* Registers, 64 bits wide */
+/// Source physical address
+const SRC: u64 = 0x00;
+/// Destination physical address
+const DST: u64 = 0x08;
+/// Bytes to copy
+const LEN: u64 = 0x10;
+const CTRL: u64 = 0x18;
+const STATUS: u64 = 0x20;
+
+/// Write 1 to start a transfer, ignored while one is in progress
+const CTRL_START: u64 = 1 << 0;
+/// Interrupt on the completion or failure of a transfer
+const CTRL_IE: u64 = 1 << 1;
+
+const STATUS_BUSY: u64 = 1 << 0;
+/// Write 1 to clear
+const STATUS_DONE: u64 = 1 << 1;
+/// Write 1 to clear, the transfer stopped with SRC and DST at the burst that faulted
+const STATUS_ERROR: u64 = 1 << 2;
+
+/// Bytes copied on each cycle
+const BURST: usize = 64;
+
+/// Memory-to-memory DMA controller, copying LEN bytes from SRC to DST a burst per cycle.
+/// SRC, DST and LEN advance with the transfer.
+/// Described to the software by the device-tree node:
+/// ```text
+/// dma-controller@4000000 {
+///     compatible = "risc-v,dmac";
+///     reg = <0x0 0x4000000 0x0 0x1000>;
+///     interrupts = <12>;
+/// };
+/// ```
+pub struct DmaController {
+    src: u64,
+    dst: u64,
+    len: u64,
+    ctrl: u64,
+    status: u64,
+}
+
+impl DmaController {
+    pub fn new() -> Self {
+        Self {
+            src: 0,
+            dst: 0,
+            len: 0,
+            ctrl: 0,
+            status: 0,
+        }
+    }
+
+    fn register(&mut self, offset: u64) -> Option<&mut u64> {
+        match offset {
+            SRC => Some(&mut self.src),
+            DST => Some(&mut self.dst),
+            LEN => Some(&mut self.len),
+            CTRL => Some(&mut self.ctrl),
+            STATUS => Some(&mut self.status),
+            _ => None,
+        }
+    }
+
+    /// Copy the next burst of the transfer.
+    fn burst(&mut self, dma: &mut Dma) {
+        let len = (self.len as usize).min(BURST);
+        let mut buffer = [0; BURST];
+        let buffer = &mut buffer[..len];
+        let result = dma
+            .read(self.src, buffer)
+            .and_then(|_| dma.write(self.dst, buffer));
+        // The failure is reported to the software through the error bit and the interrupt
+        if result.is_err() {
+            self.status = (self.status & !STATUS_BUSY) | STATUS_ERROR;
+            return;
+        }
+        self.src = self.src.wrapping_add(len as u64);
+        self.dst = self.dst.wrapping_add(len as u64);
+        self.len -= len as u64;
+        if self.len == 0 {
+            self.status = (self.status & !STATUS_BUSY) | STATUS_DONE;
+        }
+    }
+}
+
+impl Device for DmaController {
+    // 32-bit accesses reach either half of the registers
+    fn read(&mut self, offset: u64, size: Size) -> Result<u64, Exception> {
+        let shift = (offset & 7) * 8;
+        let value = self
+            .register(offset & !7)
+            .map(|r| *r)
+            .ok_or(Exception::LoadAccessFault(offset))?;
+        Ok(match size {
+            Size::DWORD => value,
+            _ => (value >> shift) & 0xffff_ffff,
+        })
+    }
+
+    fn write(&mut self, offset: u64, size: Size, value: u64) -> Result<(), Exception> {
+        let shift = (offset & 7) * 8;
+        let (mask, value) = match size {
+            Size::DWORD => (!0, value),
+            _ => (0xffff_ffff << shift, (value & 0xffff_ffff) << shift),
+        };
+        match offset & !7 {
+            CTRL => {
+                self.ctrl = (self.ctrl & !mask) | (value & mask & CTRL_IE);
+                if value & mask & CTRL_START != 0 && self.status & STATUS_BUSY == 0 {
+                    self.status = STATUS_BUSY;
+                    if self.len == 0 {
+                        self.status = STATUS_DONE;
+                    }
+                }
+            }
+            STATUS => self.status &= !(value & mask & (STATUS_DONE | STATUS_ERROR)),
+            // The addresses and length are fixed during a transfer
+            _ if self.status & STATUS_BUSY != 0 => {}
+            reg => {
+                let register = self
+                    .register(reg)
+                    .ok_or(Exception::StoreAccessFault(offset))?;
+                *register = (*register & !mask) | (value & mask);
+            }
+        }
+        Ok(())
+    }
+
+    fn pma(&self) -> Pma {
+        Pma::io(Size::WORD as u8 | Size::DWORD as u8)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn tick(&mut self, dma: &mut Dma) -> Option<DeviceEvent> {
+        if self.status & STATUS_BUSY != 0 {
+            self.burst(dma);
+        }
+        None
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.ctrl & CTRL_IE != 0 && self.status & (STATUS_DONE | STATUS_ERROR) != 0
+    }
+}
@@ -1,55 +1,164 @@
-use crate::components::{
-    mmu::Size::{self, *},
-    trap::Exception,
+use std::{fs::OpenOptions, io, mem, path::Path};
+
+use memmap2::{MmapMut, MmapOptions};
+
+use crate::{
+    components::{mmu::Size, trap::Exception},
+    config::{RamBacking, RamConfig},
 };
 
+/// Default size of the RAM
 pub const DRAM_SIZE: u64 = 512 << 20;
 
+/// Granule of the allocations
+const PAGE_SIZE: u64 = 4096;
+/// Pages covered by an entry of the directory, 2MiB
+const CHUNK_PAGES: u64 = 512;
+const CHUNK_SIZE: u64 = PAGE_SIZE * CHUNK_PAGES;
+
+type Page = [u8; PAGE_SIZE as usize];
+type Chunk = [Option<Box<Page>>; CHUNK_PAGES as usize];
+
+/// Host memory holding the contents of the RAM.
+enum Backing {
+    /// Pages allocated on the first write to them, untouched memory reads as zero.
+    /// Two-level table of the pages, so that even a large RAM costs only a small directory.
+    Sparse(Vec<Option<Box<Chunk>>>),
+    /// Mapping of a host file, shared with the other processes mapping it.
+    Mapped(MmapMut),
+}
+
 pub struct Dram {
-    memory: Vec<u8>,
+    size: u64,
+    backing: Backing,
 }
 
 impl Dram {
-    pub fn new() -> Self {
-        Self {
-            memory: vec![0; DRAM_SIZE as usize],
-        }
+    pub fn new(config: &RamConfig) -> io::Result<Self> {
+        let size = config.size;
+        let backing = match &config.backing {
+            RamBacking::Sparse => {
+                let chunks = size.div_ceil(CHUNK_SIZE) as usize;
+                Backing::Sparse((0..chunks).map(|_| None).collect())
+            }
+            RamBacking::File(path) => Backing::Mapped(map_file(path, size)?),
+            // POSIX shared memory objects live in a tmpfs on Linux
+            RamBacking::Shm(name) => {
+                Backing::Mapped(map_file(&Path::new("/dev/shm").join(name), size)?)
+            }
+        };
+        Ok(Self { size, backing })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline(always)]
+    fn in_bounds(&self, index: u64, len: u64) -> bool {
+        index.checked_add(len).is_some_and(|end| end <= self.size)
     }
 
     pub fn read(&self, index: u64, size: Size) -> Result<u64, Exception> {
-        let index = index as usize;
-        let bytes = self
-            .memory
-            .get(index..index + (size as usize))
-            .ok_or(Exception::LoadAccessFault)?;
-
-        let data = match size {
-            BYTE => bytes[0] as u64,
-            HWORD => u16::from_le_bytes(bytes.try_into().unwrap()) as u64,
-            WORD => u32::from_le_bytes(bytes.try_into().unwrap()) as u64,
-            DWORD => u64::from_le_bytes(bytes.try_into().unwrap()),
-        };
-        Ok(data)
+        let size = size as usize;
+        if !self.in_bounds(index, size as u64) {
+            return Err(Exception::LoadAccessFault(index));
+        }
+        let mut bytes = [0; 8];
+        self.read_bytes(index, &mut bytes[..size]);
+        Ok(u64::from_le_bytes(bytes))
     }
 
     pub fn write(&mut self, index: u64, size: Size, value: u64) -> Result<(), Exception> {
-        let index = index as usize;
         let size = size as usize;
-        let slice = self
-            .memory
-            .get_mut(index..index + (size))
-            .ok_or(Exception::StoreAccessFault)?;
-
-        let bytes = value.to_le_bytes();
-        slice.copy_from_slice(&bytes[..size]);
-
+        if !self.in_bounds(index, size as u64) {
+            return Err(Exception::StoreAccessFault(index));
+        }
+        self.write_bytes(index, &value.to_le_bytes()[..size]);
         Ok(())
     }
 
+    /// Copy out the memory from `index`, a page at a time.
+    pub fn read_bytes(&self, index: u64, bytes: &mut [u8]) {
+        assert!(
+            self.in_bounds(index, bytes.len() as u64),
+            "Out of bounds DRAM read"
+        );
+        let chunks = match &self.backing {
+            Backing::Mapped(map) => {
+                let index = index as usize;
+                bytes.copy_from_slice(&map[index..index + bytes.len()]);
+                return;
+            }
+            Backing::Sparse(chunks) => chunks,
+        };
+        let mut index = index;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let offset = (index % PAGE_SIZE) as usize;
+            let len = bytes.len().min(PAGE_SIZE as usize - offset);
+            let (head, tail) = mem::take(&mut bytes).split_at_mut(len);
+            match page(chunks, index) {
+                Some(page) => head.copy_from_slice(&page[offset..offset + len]),
+                None => head.fill(0),
+            }
+            index += len as u64;
+            bytes = tail;
+        }
+    }
+
+    /// Copy in the memory from `index`, allocating the pages written.
     pub fn write_bytes(&mut self, index: u64, bytes: &[u8]) {
-        let start = index as usize;
-        let end = start + bytes.len();
-        assert!(end <= self.memory.len(), "Out of bounds DRAM write");
-        self.memory[index as usize..end].copy_from_slice(bytes);
+        assert!(
+            self.in_bounds(index, bytes.len() as u64),
+            "Out of bounds DRAM write"
+        );
+        let chunks = match &mut self.backing {
+            Backing::Mapped(map) => {
+                let index = index as usize;
+                map[index..index + bytes.len()].copy_from_slice(bytes);
+                return;
+            }
+            Backing::Sparse(chunks) => chunks,
+        };
+        let mut index = index;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let offset = (index % PAGE_SIZE) as usize;
+            let len = bytes.len().min(PAGE_SIZE as usize - offset);
+            let (head, tail) = bytes.split_at(len);
+            page_mut(chunks, index)[offset..offset + len].copy_from_slice(head);
+            index += len as u64;
+            bytes = tail;
+        }
+    }
+}
+
+fn page(chunks: &[Option<Box<Chunk>>], index: u64) -> Option<&Page> {
+    let chunk = chunks[(index / CHUNK_SIZE) as usize].as_ref()?;
+    chunk[(index % CHUNK_SIZE / PAGE_SIZE) as usize].as_deref()
+}
+
+fn page_mut(chunks: &mut [Option<Box<Chunk>>], index: u64) -> &mut Page {
+    let chunk = chunks[(index / CHUNK_SIZE) as usize]
+        .get_or_insert_with(|| Box::new([const { None }; CHUNK_PAGES as usize]));
+    chunk[(index % CHUNK_SIZE / PAGE_SIZE) as usize]
+        .get_or_insert_with(|| Box::new([0; PAGE_SIZE as usize]))
+}
+
+/// Map the file shared, creating it or growing it to `size`.
+/// The contents already there are kept, so that the RAM persists across runs.
+fn map_file(path: &Path, size: u64) -> io::Result<MmapMut> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    if file.metadata()?.len() < size {
+        file.set_len(size)?;
     }
+    // Safety: the file may be changed by other processes while mapped, which is the point of sharing it.
+    // Every access goes through a copy, no reference to the mapping outlives a single read or write.
+    unsafe { MmapOptions::new().len(size as usize).map_mut(&file) }
 }
@@ -0,0 +1,176 @@
+use crate::components::{mmu::Size, trap::Exception};
+
+/// Size of the addressable region of one interrupt file
+pub const IMSIC_FILE_SIZE: u64 = 0x1000;
+/// Number of implemented interrupt identities, 0 is never a valid identity
+pub const IMSIC_NUM_IDS: u32 = 255;
+/// 64 identities per eip/eie register, as XLEN=64
+const EI_WORDS: usize = (IMSIC_NUM_IDS as usize + 1) / 64;
+
+/* Interrupt file memory-mapped registers */
+const SETEIPNUM_LE: u64 = 0x000;
+const SETEIPNUM_BE: u64 = 0x004;
+
+/* Registers accessed indirectly through the xiselect/xireg CSRs */
+/// Major interrupt priorities, implemented as read-only zero
+pub const IPRIO_BASE: u64 = 0x30;
+pub const IPRIO_END: u64 = 0x3f;
+const EIDELIVERY: u64 = 0x70;
+const EITHRESHOLD: u64 = 0x72;
+const EIP_BASE: u64 = 0x80;
+const EIP_END: u64 = 0xbf;
+const EIE_BASE: u64 = 0xc0;
+const EIE_END: u64 = 0xff;
+
+/// One interrupt file of an IMSIC, receiving the MSIs for a single privilege level.
+pub struct InterruptFile {
+    eidelivery: u64,
+    eithreshold: u64,
+    eip: [u64; EI_WORDS],
+    eie: [u64; EI_WORDS],
+}
+
+impl InterruptFile {
+    pub fn new() -> Self {
+        Self {
+            eidelivery: 0,
+            eithreshold: 0,
+            eip: [0; EI_WORDS],
+            eie: [0; EI_WORDS],
+        }
+    }
+
+    /// Mark the interrupt identity as pending, as if an MSI was received.
+    pub fn set_pending(&mut self, id: u32) {
+        //& A write of an unimplemented or zero identity is ignored.
+        if id == 0 || id > IMSIC_NUM_IDS {
+            return;
+        }
+        self.eip[(id >> 6) as usize] |= 1 << (id & 0x3f);
+    }
+
+    /// The highest priority pending-and-enabled identity that is under the threshold, 0 if none.
+    pub fn top(&self) -> u32 {
+        //& Smaller identity numbers have higher priority.
+        for (word, (eip, eie)) in self.eip.iter().zip(self.eie.iter()).enumerate() {
+            let active = eip & eie;
+            if active == 0 {
+                continue;
+            }
+            let id = (word as u32) * 64 + active.trailing_zeros();
+            //& When eithreshold is a nonzero value P, interrupt identities P and higher do not contribute.
+            if self.eithreshold != 0 && id as u64 >= self.eithreshold {
+                return 0;
+            }
+            return id;
+        }
+        0
+    }
+
+    /// Value read from *topei, identity in both the id and priority fields.
+    pub fn topei(&self) -> u64 {
+        let id = self.top() as u64;
+        id << 16 | id
+    }
+
+    /// A write to *topei claims the top interrupt, clearing its pending bit.
+    pub fn claim(&mut self) {
+        let id = self.top();
+        if id != 0 {
+            self.eip[(id >> 6) as usize] &= !(1 << (id & 0x3f));
+        }
+    }
+
+    /// Interrupt output signal towards the hart
+    pub fn is_interrupting(&self) -> bool {
+        self.eidelivery == 1 && self.top() != 0
+    }
+
+    /// Read a register through *ireg, `None` when the selected register doesn't exist.
+    pub fn read_ireg(&self, iselect: u64) -> Option<u64> {
+        match iselect {
+            IPRIO_BASE..=IPRIO_END => Some(0),
+            EIDELIVERY => Some(self.eidelivery),
+            EITHRESHOLD => Some(self.eithreshold),
+            EIP_BASE..=EIP_END => Self::ei_index(iselect - EIP_BASE)
+                .map(|index| self.eip.get(index).copied().unwrap_or(0)),
+            EIE_BASE..=EIE_END => Self::ei_index(iselect - EIE_BASE)
+                .map(|index| self.eie.get(index).copied().unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    /// Write a register through *ireg, `None` when the selected register doesn't exist.
+    pub fn write_ireg(&mut self, iselect: u64, value: u64) -> Option<()> {
+        match iselect {
+            IPRIO_BASE..=IPRIO_END => {}
+            //& eidelivery: 0 = Interrupt delivery is disabled, 1 = Interrupt delivery is enabled
+            EIDELIVERY => self.eidelivery = value & 1,
+            EITHRESHOLD => {
+                //& eithreshold must be able to hold all integers in the range 0 to N
+                if value <= IMSIC_NUM_IDS as u64 {
+                    self.eithreshold = value;
+                }
+            }
+            EIP_BASE..=EIP_END => {
+                let index = Self::ei_index(iselect - EIP_BASE)?;
+                if let Some(eip) = self.eip.get_mut(index) {
+                    *eip = value;
+                }
+                //& Bit 0 of eip0 is read-only zero
+                self.eip[0] &= !1;
+            }
+            EIE_BASE..=EIE_END => {
+                let index = Self::ei_index(iselect - EIE_BASE)?;
+                if let Some(eie) = self.eie.get_mut(index) {
+                    *eie = value;
+                }
+                self.eie[0] &= !1;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    //& When XLEN = 64, only the even-numbered registers exist.
+    fn ei_index(offset: u64) -> Option<usize> {
+        if offset & 1 != 0 {
+            return None;
+        }
+        Some((offset >> 1) as usize)
+    }
+
+    pub fn read(&self, offset: u64, size: Size) -> Result<u32, Exception> {
+        match offset {
+            //& seteipnum registers always read as zero
+            SETEIPNUM_LE | SETEIPNUM_BE if size == Size::WORD => Ok(0),
+            _ => Err(Exception::LoadAccessFault(offset)),
+        }
+    }
+
+    pub fn write(&mut self, offset: u64, size: Size, value: u32) -> Result<(), Exception> {
+        match offset {
+            SETEIPNUM_LE if size == Size::WORD => self.set_pending(value),
+            SETEIPNUM_BE if size == Size::WORD => self.set_pending(value.swap_bytes()),
+            _ => return Err(Exception::StoreAccessFault(offset)),
+        }
+        Ok(())
+    }
+}
+
+/// Incoming MSI Controller.
+/// Holds the M-level and S-level interrupt files of the single hart, guest files are not implemented.
+/// https://github.com/riscv/riscv-aia
+pub struct Imsic {
+    pub m_file: InterruptFile,
+    pub s_file: InterruptFile,
+}
+
+impl Imsic {
+    pub fn new() -> Self {
+        Self {
+            m_file: InterruptFile::new(),
+            s_file: InterruptFile::new(),
+        }
+    }
+}
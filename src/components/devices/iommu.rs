@@ -0,0 +1,854 @@
+use arbitrary_int::u1;
+
+use crate::components::{
+    devices::{Device, DeviceEvent},
+    dma::{Dma, DmaError, Iommu, Memory},
+    mmu::{Leaf, PageTables, Size, WalkFault, walk_sv39},
+    pma::Pma,
+    trap::Exception,
+};
+
+/* RISC-V IOMMU Architecture Specification */
+// Model of the register interface and of the in-memory structures of the IOMMU,
+// translating the DMA of the devices by their device id.
+// Nothing is cached: every translation walks the directories and page tables,
+// so the invalidation commands have nothing to do.
+// No device issues ATS or PRI requests, so the page-request queue stays empty.
+
+/// Size of the addressable region
+pub const IOMMU_SIZE: u64 = 0x1000;
+pub const IRQ_IOMMU: u32 = 0x24;
+
+const PAGE_SIZE: u64 = 4096;
+
+/* Registers */
+const CAPABILITIES: u64 = 0x00;
+const FCTL: u64 = 0x08;
+const DDTP: u64 = 0x10;
+const CQB: u64 = 0x18;
+const CQH: u64 = 0x20;
+const CQT: u64 = 0x24;
+const FQB: u64 = 0x28;
+const FQH: u64 = 0x30;
+const FQT: u64 = 0x34;
+const PQB: u64 = 0x38;
+const PQH: u64 = 0x40;
+const PQT: u64 = 0x44;
+const CQCSR: u64 = 0x48;
+const FQCSR: u64 = 0x4c;
+const PQCSR: u64 = 0x50;
+const IPSR: u64 = 0x54;
+
+/* Capabilities */
+const CAP_VERSION_1_0: u64 = 0x10;
+const CAP_SV39: u64 = 1 << 9;
+const CAP_SV39X4: u64 = 1 << 17;
+const CAP_MSI_FLAT: u64 = 1 << 22;
+/// A and D are updated by the IOMMU when enabled by the device context
+const CAP_AMO_HWAD: u64 = 1 << 24;
+/// Wired interrupts only
+const CAP_IGS_WSI: u64 = 1 << 28;
+const CAP_PAS: u64 = 56 << 32;
+const CAP_PD8: u64 = 1 << 38;
+const CAP_PD17: u64 = 1 << 39;
+const CAP_PD20: u64 = 1 << 40;
+const CAPS: u64 = CAP_VERSION_1_0
+    | CAP_SV39
+    | CAP_SV39X4
+    | CAP_MSI_FLAT
+    | CAP_AMO_HWAD
+    | CAP_IGS_WSI
+    | CAP_PAS
+    | CAP_PD8
+    | CAP_PD17
+    | CAP_PD20;
+
+/// Little-endian structures, interrupts signaled by wires
+const FCTL_WSI: u64 = 1 << 1;
+
+/* Directory table pointer modes */
+const DDTP_OFF: u64 = 0;
+const DDTP_BARE: u64 = 1;
+const DDTP_1LVL: u64 = 2;
+const DDTP_3LVL: u64 = 4;
+/// PPN field of the table pointers and of the queue bases
+const PPN_MASK: u64 = ((1 << 44) - 1) << 10;
+/// LOG2SZ-1 field of the queue bases
+const LOG2SZ_MASK: u64 = 0x1f;
+
+/* Queue control and status, the same layout for the three queues */
+const QCSR_EN: u64 = 1 << 0;
+const QCSR_IE: u64 = 1 << 1;
+/// Memory fault on an access to the queue
+const QCSR_MF: u64 = 1 << 8;
+/// Fault and page-request queues: a record was dropped as the queue was full
+const QCSR_OF: u64 = 1 << 9;
+const CQCSR_CMD_TO: u64 = 1 << 9;
+const CQCSR_CMD_ILL: u64 = 1 << 10;
+const CQCSR_FENCE_W_IP: u64 = 1 << 11;
+const QCSR_ON: u64 = 1 << 16;
+/// Errors stopping the queue until cleared by software
+const CQCSR_ERRORS: u64 = QCSR_MF | CQCSR_CMD_TO | CQCSR_CMD_ILL;
+/// Write 1 to clear
+const CQCSR_W1C: u64 = CQCSR_ERRORS | CQCSR_FENCE_W_IP;
+const XQCSR_ERRORS: u64 = QCSR_MF | QCSR_OF;
+
+/* Interrupt pending status, write 1 to clear */
+const IPSR_CIP: u64 = 1 << 0;
+const IPSR_FIP: u64 = 1 << 1;
+const IPSR_PIP: u64 = 1 << 3;
+
+/* Commands */
+const IOTINVAL: u64 = 1;
+const IOTINVAL_VMA: u64 = 0;
+const IOTINVAL_GVMA: u64 = 1;
+const IOFENCE: u64 = 2;
+const IOFENCE_C: u64 = 0;
+const IOFENCE_AV: u64 = 1 << 10;
+const IOFENCE_WSI: u64 = 1 << 11;
+const IODIR: u64 = 3;
+const IODIR_INVAL_DDT: u64 = 0;
+const IODIR_INVAL_PDT: u64 = 1;
+const COMMAND_SIZE: u64 = 16;
+
+const FAULT_RECORD_SIZE: u64 = 32;
+
+/* Fault causes */
+const READ_ACCESS_FAULT: u16 = 5;
+const WRITE_ACCESS_FAULT: u16 = 7;
+const READ_PAGE_FAULT: u16 = 13;
+const WRITE_PAGE_FAULT: u16 = 15;
+const READ_GUEST_PAGE_FAULT: u16 = 21;
+const WRITE_GUEST_PAGE_FAULT: u16 = 23;
+const ALL_DISALLOWED: u16 = 256;
+const DDT_LOAD_FAULT: u16 = 257;
+const DDT_INVALID: u16 = 258;
+const DDT_MISCONFIGURED: u16 = 259;
+const TTYP_DISALLOWED: u16 = 260;
+const MSI_LOAD_FAULT: u16 = 261;
+const MSI_INVALID: u16 = 262;
+const MSI_MISCONFIGURED: u16 = 263;
+const PDT_LOAD_FAULT: u16 = 265;
+const PDT_INVALID: u16 = 266;
+const PDT_MISCONFIGURED: u16 = 267;
+
+/* Transaction types */
+const TTYP_UNTRANSLATED_READ: u64 = 2;
+const TTYP_UNTRANSLATED_WRITE: u64 = 3;
+
+/* Device context */
+const DC_SIZE: u64 = 64;
+const TC_V: u64 = 1 << 0;
+const TC_PDTV: u64 = 1 << 5;
+const TC_DTF: u64 = 1 << 4;
+const TC_GADE: u64 = 1 << 7;
+const TC_SADE: u64 = 1 << 8;
+const TC_DPE: u64 = 1 << 9;
+/// EN_ATS, EN_PRI, T2GPA, PRPR, SBE, SXL and the reserved bits, none of them supported
+const TC_UNSUPPORTED: u64 = !(TC_V | TC_DTF | TC_PDTV | TC_GADE | TC_SADE | TC_DPE);
+
+/* Modes of the address translation and protection registers */
+const ATP_BARE: u64 = 0;
+const ATP_SV39: u64 = 8;
+const PDTP_PD8: u64 = 1;
+const PDTP_PD20: u64 = 3;
+const MSIPTP_FLAT: u64 = 1;
+
+/* Process context */
+const PC_SIZE: u64 = 16;
+const PC_V: u64 = 1 << 0;
+
+/* MSI page table entry */
+const MSI_PTE_SIZE: u64 = 16;
+const MSI_PTE_V: u64 = 1 << 0;
+const MSI_PTE_MODE_BASIC: u64 = 3;
+
+/// Leaf and non-leaf directory entries share the valid bit and the PPN field
+const DIR_V: u64 = 1 << 0;
+
+/// Mode field, bits 63:60, of the atp registers of the contexts.
+#[inline(always)]
+fn atp_mode(atp: u64) -> u64 {
+    atp >> 60
+}
+
+/// Address of the page pointed by the PPN field of an entry or register.
+#[inline(always)]
+fn ppn_address(value: u64) -> u64 {
+    ((value & PPN_MASK) >> 10) * PAGE_SIZE
+}
+
+/// The PPN field of the atp registers, bits 43:0.
+#[inline(always)]
+fn atp_address(atp: u64) -> u64 {
+    (atp & ((1 << 44) - 1)) * PAGE_SIZE
+}
+
+/// A fault of a translation, reported in the fault queue.
+struct Fault {
+    cause: u16,
+    iotval: u64,
+    iotval2: u64,
+}
+
+impl Fault {
+    fn new(cause: u16) -> Self {
+        Self {
+            cause,
+            iotval: 0,
+            iotval2: 0,
+        }
+    }
+
+    /// The fault of the page-table walk of `iova`, as by the original access type.
+    /// Guest page faults on an implicit access of the first-stage walk set bit 0 of iotval2.
+    fn walk(fault: WalkFault, iova: u64, write: bool, implicit: bool) -> Self {
+        let (cause, iotval2) = match (fault, write) {
+            (WalkFault::Access, false) => (READ_ACCESS_FAULT, 0),
+            (WalkFault::Access, true) => (WRITE_ACCESS_FAULT, 0),
+            (WalkFault::Page, false) => (READ_PAGE_FAULT, 0),
+            (WalkFault::Page, true) => (WRITE_PAGE_FAULT, 0),
+            (WalkFault::GuestPage(gpa), false) => (READ_GUEST_PAGE_FAULT, gpa),
+            (WalkFault::GuestPage(gpa), true) => (WRITE_GUEST_PAGE_FAULT, gpa),
+        };
+        let implicit = match implicit && matches!(fault, WalkFault::GuestPage(_)) {
+            true => 1,
+            false => 0,
+        };
+        Self {
+            cause,
+            iotval: iova,
+            iotval2: (iotval2 & !3) | implicit,
+        }
+    }
+}
+
+/// The extended format of the device context, with the MSI translation fields.
+struct DeviceContext {
+    tc: u64,
+    iohgatp: u64,
+    /// Only PSCID, unused as nothing is cached
+    _ta: u64,
+    /// The iosatp, or the pdtp when tc.PDTV is set
+    fsc: u64,
+    msiptp: u64,
+    msi_addr_mask: u64,
+    msi_addr_pattern: u64,
+}
+
+impl DeviceContext {
+    fn load(memory: &Memory, address: u64) -> Result<Self, DmaError> {
+        let field = |index: u64| memory.read_u64(address + index * 8);
+        Ok(Self {
+            tc: field(0)?,
+            iohgatp: field(1)?,
+            _ta: field(2)?,
+            fsc: field(3)?,
+            msiptp: field(4)?,
+            msi_addr_mask: field(5)?,
+            msi_addr_pattern: field(6)?,
+        })
+    }
+
+    /// Configurations using features that aren't implemented, or reserved encodings.
+    fn is_misconfigured(&self) -> bool {
+        let fsc_mode = atp_mode(self.fsc);
+        let fsc_valid = match self.tc & TC_PDTV != 0 {
+            true => fsc_mode <= PDTP_PD20,
+            false => fsc_mode == ATP_BARE || fsc_mode == ATP_SV39,
+        };
+        let iohgatp_valid = match atp_mode(self.iohgatp) {
+            ATP_BARE => true,
+            // The root table of Sv39x4 is 16KiB aligned
+            ATP_SV39 => self.iohgatp & 3 == 0,
+            _ => false,
+        };
+        self.tc & TC_UNSUPPORTED != 0
+            || (self.tc & TC_DPE != 0 && self.tc & TC_PDTV == 0)
+            || !fsc_valid
+            || !iohgatp_valid
+            || atp_mode(self.msiptp) > MSIPTP_FLAT
+    }
+
+    /// The interrupt file number of an address of a virtual interrupt file, by the MSI address mask and pattern.
+    fn interrupt_file(&self, gpa: u64) -> Option<u64> {
+        let mask = self.msi_addr_mask & ((1 << 52) - 1);
+        let pattern = self.msi_addr_pattern & ((1 << 52) - 1);
+        let page = gpa >> 12;
+        if atp_mode(self.msiptp) != MSIPTP_FLAT || (page & !mask) != (pattern & !mask) {
+            return None;
+        }
+        // The bits of the page number selected by the mask, packed
+        let (file, _) = (0..52)
+            .filter(|bit| (mask >> bit) & 1 != 0)
+            .fold((0, 0), |(file, count), bit| {
+                (file | (((page >> bit) & 1) << count), count + 1)
+            });
+        Some(file)
+    }
+}
+
+/// Page tables in the physical memory, as walked by the second stage.
+struct PhysicalTables<'a, 'b> {
+    memory: &'a mut Memory<'b>,
+}
+
+impl PageTables for PhysicalTables<'_, '_> {
+    fn read_pte(&mut self, address: u64) -> Result<u64, WalkFault> {
+        self.memory.read_u64(address).map_err(|_| WalkFault::Access)
+    }
+
+    fn write_pte(&mut self, address: u64, value: u64) -> Result<(), WalkFault> {
+        self.memory
+            .write_u64(address, value)
+            .map_err(|_| WalkFault::Access)
+    }
+}
+
+/// Second stage of a device context, translating guest-physical addresses.
+#[derive(Clone, Copy)]
+struct GStage {
+    iohgatp: u64,
+    /// Update A and D instead of faulting
+    gade: bool,
+}
+
+impl GStage {
+    fn translate(self, memory: &mut Memory, gpa: u64, write: bool) -> Result<u64, WalkFault> {
+        if atp_mode(self.iohgatp) == ATP_BARE {
+            return Ok(gpa);
+        }
+        //& Sv39x4 guest-physical addresses are 41 bits wide, the upper bits must be zero
+        if gpa >> 41 != 0 {
+            return Err(WalkFault::GuestPage(gpa));
+        }
+        let guest_fault = |fault| match fault {
+            WalkFault::Access => WalkFault::Access,
+            _ => WalkFault::GuestPage(gpa),
+        };
+        let mut tables = PhysicalTables { memory };
+        let leaf =
+            walk_sv39(&mut tables, atp_address(self.iohgatp), gpa, true).map_err(guest_fault)?;
+        //& The second-stage accesses are always treated as U-mode ones
+        if !permits(&leaf, true, write) {
+            return Err(WalkFault::GuestPage(gpa));
+        }
+        update_ad(&mut tables, &leaf, write, self.gade).map_err(guest_fault)?;
+        Ok(leaf.physical(gpa))
+    }
+}
+
+/// Page tables at guest-physical addresses, for the first stage under a second one.
+struct NestedTables<'a, 'b> {
+    memory: &'a mut Memory<'b>,
+    g_stage: GStage,
+}
+
+impl PageTables for NestedTables<'_, '_> {
+    fn read_pte(&mut self, address: u64) -> Result<u64, WalkFault> {
+        let address = self.g_stage.translate(self.memory, address, false)?;
+        self.memory.read_u64(address).map_err(|_| WalkFault::Access)
+    }
+
+    fn write_pte(&mut self, address: u64, value: u64) -> Result<(), WalkFault> {
+        let address = self.g_stage.translate(self.memory, address, true)?;
+        self.memory
+            .write_u64(address, value)
+            .map_err(|_| WalkFault::Access)
+    }
+}
+
+/// Permission checks of a leaf PTE, for a read or a write by the device.
+fn permits(leaf: &Leaf, user: bool, write: bool) -> bool {
+    let pte = leaf.pte;
+    let allowed = match write {
+        true => pte.w().value() != 0,
+        false => pte.r().value() != 0,
+    };
+    allowed && (pte.u().value() != 0) == user
+}
+
+/// Set A, and D on a write, when the hardware updating is enabled, fault otherwise.
+fn update_ad(
+    tables: &mut impl PageTables,
+    leaf: &Leaf,
+    write: bool,
+    enabled: bool,
+) -> Result<(), WalkFault> {
+    let mut pte = leaf.pte;
+    if pte.a().value() != 0 && (!write || pte.d().value() != 0) {
+        return Ok(());
+    }
+    if !enabled {
+        return Err(WalkFault::Page);
+    }
+    pte.set_a(u1::new(1));
+    if write {
+        pte.set_d(u1::new(1));
+    }
+    tables.write_pte(leaf.address, pte.raw_value())
+}
+
+/// IOMMU with the device directory in memory, translating the DMA in two stages.
+/// Only wired interrupts are signaled, every cause on the same line.
+pub struct RiscvIommu {
+    fctl: u64,
+    ddtp: u64,
+    cqb: u64,
+    cqh: u64,
+    cqt: u64,
+    fqb: u64,
+    fqh: u64,
+    fqt: u64,
+    pqb: u64,
+    pqh: u64,
+    pqt: u64,
+    cqcsr: u64,
+    fqcsr: u64,
+    pqcsr: u64,
+    ipsr: u64,
+}
+
+impl RiscvIommu {
+    pub fn new() -> Self {
+        Self {
+            fctl: FCTL_WSI,
+            ddtp: DDTP_OFF,
+            cqb: 0,
+            cqh: 0,
+            cqt: 0,
+            fqb: 0,
+            fqh: 0,
+            fqt: 0,
+            pqb: 0,
+            pqh: 0,
+            pqt: 0,
+            cqcsr: 0,
+            fqcsr: 0,
+            pqcsr: 0,
+            ipsr: 0,
+        }
+    }
+
+    /// Entries of the queue, from the LOG2SZ-1 field of its base.
+    #[inline(always)]
+    fn queue_entries(base: u64) -> u64 {
+        1 << ((base & LOG2SZ_MASK) + 1)
+    }
+
+    /// The 64-bit registers, and the pairs of 32-bit ones sharing a doubleword.
+    fn register(&self, offset: u64) -> u64 {
+        match offset {
+            CAPABILITIES => CAPS,
+            FCTL => self.fctl,
+            DDTP => self.ddtp,
+            CQB => self.cqb,
+            CQH => self.cqh | (self.cqt << 32),
+            FQB => self.fqb,
+            FQH => self.fqh | (self.fqt << 32),
+            PQB => self.pqb,
+            PQH => self.pqh | (self.pqt << 32),
+            CQCSR => self.cqcsr | (self.fqcsr << 32),
+            PQCSR => self.pqcsr | (self.ipsr << 32),
+            _ => 0,
+        }
+    }
+
+    /// Write the 32-bit word at `offset`, the half of a 64-bit register or a 32-bit register.
+    fn write_word(&mut self, offset: u64, value: u32) {
+        let shift = (offset & 4) * 8;
+        let merge = |old: u64, writable: u64| {
+            let mask = 0xffff_ffff << shift;
+            (old & !(mask & writable)) | (((value as u64) << shift) & mask & writable)
+        };
+        match offset {
+            // The mode is WARL, keeping the previous one on an unsupported value
+            DDTP | 0x14 => {
+                let ddtp = merge(self.ddtp, PPN_MASK | 0xf);
+                self.ddtp = match ddtp & 0xf {
+                    DDTP_OFF..=DDTP_3LVL => ddtp,
+                    _ => (ddtp & !0xf) | (self.ddtp & 0xf),
+                };
+            }
+            // The bases are fixed while their queue is on
+            CQB | 0x1c if self.cqcsr & QCSR_ON == 0 => {
+                self.cqb = merge(self.cqb, PPN_MASK | LOG2SZ_MASK)
+            }
+            FQB | 0x2c if self.fqcsr & QCSR_ON == 0 => {
+                self.fqb = merge(self.fqb, PPN_MASK | LOG2SZ_MASK)
+            }
+            PQB | 0x3c if self.pqcsr & QCSR_ON == 0 => {
+                self.pqb = merge(self.pqb, PPN_MASK | LOG2SZ_MASK)
+            }
+            CQT => self.cqt = value as u64 & (Self::queue_entries(self.cqb) - 1),
+            FQH => self.fqh = value as u64 & (Self::queue_entries(self.fqb) - 1),
+            PQH => self.pqh = value as u64 & (Self::queue_entries(self.pqb) - 1),
+            CQCSR => {
+                let value = value as u64;
+                self.cqcsr &= !(value & CQCSR_W1C);
+                match (value & QCSR_EN != 0, self.cqcsr & QCSR_ON != 0) {
+                    //& When cqen transitions from 0 to 1, cqh is reset to 0 and the errors are cleared
+                    (true, false) => {
+                        self.cqh = 0;
+                        self.cqcsr = QCSR_EN | QCSR_ON;
+                    }
+                    (false, true) => self.cqcsr &= !(QCSR_EN | QCSR_ON),
+                    _ => {}
+                }
+                self.cqcsr = (self.cqcsr & !QCSR_IE) | (value & QCSR_IE);
+            }
+            // Owned by the IOMMU
+            CQH | FQT | PQT => {}
+            FQCSR => Self::write_queue_csr(&mut self.fqcsr, &mut self.fqt, value as u64),
+            PQCSR => Self::write_queue_csr(&mut self.pqcsr, &mut self.pqt, value as u64),
+            IPSR => self.ipsr &= !(value as u64),
+            // Read-only or reserved
+            _ => {}
+        }
+    }
+
+    /// The fault and page-request queues, with the tail owned by the IOMMU.
+    fn write_queue_csr(csr: &mut u64, tail: &mut u64, value: u64) {
+        *csr &= !(value & XQCSR_ERRORS);
+        match (value & QCSR_EN != 0, *csr & QCSR_ON != 0) {
+            (true, false) => {
+                *tail = 0;
+                *csr = QCSR_EN | QCSR_ON;
+            }
+            (false, true) => *csr &= !(QCSR_EN | QCSR_ON),
+            _ => {}
+        }
+        *csr = (*csr & !QCSR_IE) | (value & QCSR_IE);
+    }
+
+    /// Execute the commands between the head and the tail of the command queue,
+    /// stopping at the first illegal one.
+    fn process_commands(&mut self, memory: &mut Memory) {
+        if self.cqcsr & QCSR_ON == 0 || self.cqcsr & CQCSR_ERRORS != 0 {
+            return;
+        }
+        let entries = Self::queue_entries(self.cqb);
+        while self.cqh != self.cqt {
+            let address = ppn_address(self.cqb) + self.cqh * COMMAND_SIZE;
+            let command = memory
+                .read_u64(address)
+                .and_then(|low| Ok((low, memory.read_u64(address + 8)?)));
+            let error = match command {
+                Ok((low, high)) => self.execute(memory, low, high),
+                Err(_) => Some(QCSR_MF),
+            };
+            if let Some(error) = error {
+                self.cqcsr |= error;
+                if self.cqcsr & QCSR_IE != 0 {
+                    self.ipsr |= IPSR_CIP;
+                }
+                return;
+            }
+            self.cqh = (self.cqh + 1) % entries;
+        }
+    }
+
+    /// The error stopping the queue, if any.
+    fn execute(&mut self, memory: &mut Memory, low: u64, high: u64) -> Option<u64> {
+        let opcode = low & 0x7f;
+        let func3 = (low >> 7) & 0x7;
+        match (opcode, func3) {
+            // Nothing is cached, so the invalidations complete at once
+            (IOTINVAL, IOTINVAL_VMA | IOTINVAL_GVMA) => None,
+            (IODIR, IODIR_INVAL_DDT | IODIR_INVAL_PDT) => None,
+            //& IOFENCE.C guarantees that all previous commands fetched from the CQ have been completed and committed.
+            (IOFENCE, IOFENCE_C) => {
+                if low & IOFENCE_AV != 0 {
+                    let data = (low >> 32) as u32;
+                    let address = high & !3;
+                    if memory.write(address, &data.to_le_bytes()).is_err() {
+                        return Some(QCSR_MF);
+                    }
+                }
+                //& If WSI is 1, the fence_w_ip bit is set in cqcsr, signaling an interrupt when enabled
+                if low & IOFENCE_WSI != 0 {
+                    if self.cqcsr & QCSR_IE != 0 {
+                        self.ipsr |= IPSR_CIP;
+                    }
+                    self.cqcsr |= CQCSR_FENCE_W_IP;
+                }
+                None
+            }
+            // No ATS capability
+            _ => Some(CQCSR_CMD_ILL),
+        }
+    }
+
+    /// Append a record to the fault queue, or flag the loss of the record.
+    fn report(&mut self, memory: &mut Memory, device_id: u32, write: bool, fault: &Fault) {
+        if self.fqcsr & QCSR_ON == 0 || self.fqcsr & XQCSR_ERRORS != 0 {
+            return;
+        }
+        let entries = Self::queue_entries(self.fqb);
+        let next = (self.fqt + 1) % entries;
+        if next == self.fqh {
+            self.fqcsr |= QCSR_OF;
+        } else {
+            let ttyp = match write {
+                true => TTYP_UNTRANSLATED_WRITE,
+                false => TTYP_UNTRANSLATED_READ,
+            };
+            let header = fault.cause as u64 | (ttyp << 34) | ((device_id as u64) << 40);
+            let address = ppn_address(self.fqb) + self.fqt * FAULT_RECORD_SIZE;
+            let record = [header, 0, fault.iotval, fault.iotval2];
+            let written = record
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, &dword)| memory.write_u64(address + i as u64 * 8, dword));
+            match written {
+                Ok(()) => self.fqt = next,
+                Err(_) => self.fqcsr |= QCSR_MF,
+            }
+        }
+        if self.fqcsr & QCSR_IE != 0 {
+            self.ipsr |= IPSR_FIP;
+        }
+    }
+
+    /// Walk the device directory table down to the device context of `device_id`.
+    fn device_context(&self, memory: &Memory, device_id: u32) -> Result<DeviceContext, Fault> {
+        // Device ids are split in 6, 9 and 9 bits, each indexing a level
+        let levels = (self.ddtp & 0xf) - DDTP_1LVL + 1;
+        let width = 6 + 9 * (levels - 1);
+        if device_id as u64 >> width != 0 {
+            return Err(Fault::new(TTYP_DISALLOWED));
+        }
+        let mut a = ppn_address(self.ddtp);
+        for level in (1..levels).rev() {
+            let index = (device_id as u64 >> (6 + 9 * (level - 1))) & 0x1ff;
+            let entry = memory
+                .read_u64(a + index * 8)
+                .map_err(|_| Fault::new(DDT_LOAD_FAULT))?;
+            if entry & DIR_V == 0 {
+                return Err(Fault::new(DDT_INVALID));
+            }
+            if entry & !(PPN_MASK | DIR_V) != 0 {
+                return Err(Fault::new(DDT_MISCONFIGURED));
+            }
+            a = ppn_address(entry);
+        }
+        let address = a + (device_id as u64 & 0x3f) * DC_SIZE;
+        let dc = DeviceContext::load(memory, address).map_err(|_| Fault::new(DDT_LOAD_FAULT))?;
+        if dc.tc & TC_V == 0 {
+            return Err(Fault::new(DDT_INVALID));
+        }
+        if dc.is_misconfigured() {
+            return Err(Fault::new(DDT_MISCONFIGURED));
+        }
+        Ok(dc)
+    }
+
+    /// Walk the process directory table of the device context, at guest-physical addresses,
+    /// down to the iosatp of the process 0, as the devices don't supply a process id.
+    fn process_iosatp(
+        dc: &DeviceContext,
+        memory: &mut Memory,
+        g_stage: GStage,
+        iova: u64,
+        write: bool,
+    ) -> Result<u64, Fault> {
+        // Process ids are split in 8, 9 and 3 bits, each indexing a level
+        let levels = atp_mode(dc.fsc) - PDTP_PD8 + 1;
+        let process_id = 0u64;
+        let pdt_fault = |fault, cause| match fault {
+            WalkFault::GuestPage(_) => Fault::walk(fault, iova, write, true),
+            _ => Fault::new(cause),
+        };
+        let mut a = atp_address(dc.fsc);
+        for level in (1..levels).rev() {
+            let index = (process_id >> (8 + 9 * (level - 1))) & 0x1ff;
+            let spa = g_stage
+                .translate(memory, a + index * 8, false)
+                .map_err(|f| pdt_fault(f, PDT_LOAD_FAULT))?;
+            let entry = memory
+                .read_u64(spa)
+                .map_err(|_| Fault::new(PDT_LOAD_FAULT))?;
+            if entry & DIR_V == 0 {
+                return Err(Fault::new(PDT_INVALID));
+            }
+            if entry & !(PPN_MASK | DIR_V) != 0 {
+                return Err(Fault::new(PDT_MISCONFIGURED));
+            }
+            a = ppn_address(entry);
+        }
+        let address = a + (process_id & 0xff) * PC_SIZE;
+        let spa = g_stage
+            .translate(memory, address, false)
+            .map_err(|f| pdt_fault(f, PDT_LOAD_FAULT))?;
+        let ta = memory
+            .read_u64(spa)
+            .map_err(|_| Fault::new(PDT_LOAD_FAULT))?;
+        let fsc = memory
+            .read_u64(spa + 8)
+            .map_err(|_| Fault::new(PDT_LOAD_FAULT))?;
+        if ta & PC_V == 0 {
+            return Err(Fault::new(PDT_INVALID));
+        }
+        match atp_mode(fsc) {
+            ATP_BARE | ATP_SV39 => Ok(fsc),
+            _ => Err(Fault::new(PDT_MISCONFIGURED)),
+        }
+    }
+
+    /// The supervisor-physical address of the MSI page of the interrupt file.
+    fn translate_msi(
+        dc: &DeviceContext,
+        memory: &Memory,
+        file: u64,
+        gpa: u64,
+        write: bool,
+    ) -> Result<u64, Fault> {
+        // Only the writes of the messages are translated
+        if !write {
+            return Err(Fault {
+                cause: READ_ACCESS_FAULT,
+                iotval: gpa,
+                iotval2: 0,
+            });
+        }
+        let address = atp_address(dc.msiptp) + file * MSI_PTE_SIZE;
+        let pte = memory
+            .read_u64(address)
+            .map_err(|_| Fault::new(MSI_LOAD_FAULT))?;
+        if pte & MSI_PTE_V == 0 {
+            return Err(Fault::new(MSI_INVALID));
+        }
+        // Memory-resident interrupt files aren't supported
+        match (pte >> 1) & 0x3 {
+            MSI_PTE_MODE_BASIC => Ok(ppn_address(pte) | (gpa & (PAGE_SIZE - 1))),
+            _ => Err(Fault::new(MSI_MISCONFIGURED)),
+        }
+    }
+
+    /// The translation of an untranslated request of the device, with whether its fault is to be reported.
+    fn translate_request(
+        &self,
+        device_id: u32,
+        iova: u64,
+        write: bool,
+        memory: &mut Memory,
+    ) -> Result<u64, (Fault, bool)> {
+        match self.ddtp & 0xf {
+            DDTP_OFF => return Err((Fault::new(ALL_DISALLOWED), true)),
+            DDTP_BARE => return Ok(iova),
+            _ => {}
+        }
+        let dc = self
+            .device_context(memory, device_id)
+            .map_err(|f| (f, true))?;
+        //& Faults not related to the device directory aren't reported when DTF is set
+        let report = dc.tc & TC_DTF == 0;
+        let g_stage = GStage {
+            iohgatp: dc.iohgatp,
+            gade: dc.tc & TC_GADE != 0,
+        };
+
+        // First stage, a bare one when the process directory has no entry for requests without a process id
+        let iosatp = match (dc.tc & TC_PDTV != 0, dc.tc & TC_DPE != 0) {
+            (false, _) => dc.fsc,
+            (true, true) => {
+                Self::process_iosatp(&dc, memory, g_stage, iova, write).map_err(|f| (f, report))?
+            }
+            (true, false) => ATP_BARE << 60,
+        };
+        let gpa = match atp_mode(iosatp) {
+            ATP_BARE => iova,
+            _ => {
+                // Bits 63:39 must all equal bit 38
+                let high = (iova as i64) >> 38;
+                if high != 0 && high != -1 {
+                    return Err((Fault::walk(WalkFault::Page, iova, write, false), report));
+                }
+                let mut tables = NestedTables {
+                    memory: &mut *memory,
+                    g_stage,
+                };
+                let leaf = walk_sv39(&mut tables, atp_address(iosatp), iova, false)
+                    .map_err(|f| (Fault::walk(f, iova, write, true), report))?;
+                // The requests are made with the U-mode privilege
+                if !permits(&leaf, true, write) {
+                    return Err((Fault::walk(WalkFault::Page, iova, write, false), report));
+                }
+                update_ad(&mut tables, &leaf, write, dc.tc & TC_SADE != 0)
+                    .map_err(|f| (Fault::walk(f, iova, write, true), report))?;
+                leaf.physical(iova)
+            }
+        };
+
+        // The writes to the virtual interrupt files go through the MSI page table instead of the second stage
+        if let Some(file) = dc.interrupt_file(gpa) {
+            return Self::translate_msi(&dc, memory, file, gpa, write).map_err(|f| (f, report));
+        }
+        g_stage.translate(memory, gpa, write).map_err(|f| {
+            let fault = match f {
+                WalkFault::GuestPage(_) => WalkFault::GuestPage(gpa),
+                f => f,
+            };
+            (Fault::walk(fault, iova, write, false), report)
+        })
+    }
+}
+
+impl Iommu for RiscvIommu {
+    fn translate(
+        &mut self,
+        device_id: u32,
+        iova: u64,
+        write: bool,
+        memory: &mut Memory,
+    ) -> Result<u64, DmaError> {
+        self.translate_request(device_id, iova, write, memory)
+            .map_err(|(fault, report)| {
+                if report {
+                    self.report(memory, device_id, write, &fault);
+                }
+                DmaError::TranslationFault(iova)
+            })
+    }
+}
+
+impl Device for RiscvIommu {
+    // 32-bit accesses reach either half of the 64-bit registers, and each of the 32-bit ones
+    fn read(&mut self, offset: u64, size: Size) -> Result<u64, Exception> {
+        // icvec reads as zero, every cause signaled on vector 0
+        let value = self.register(offset & !7);
+        Ok(match size {
+            Size::DWORD => value,
+            _ => (value >> ((offset & 4) * 8)) & 0xffff_ffff,
+        })
+    }
+
+    fn write(&mut self, offset: u64, size: Size, value: u64) -> Result<(), Exception> {
+        self.write_word(offset, value as u32);
+        if size == Size::DWORD {
+            self.write_word(offset + 4, (value >> 32) as u32);
+        }
+        Ok(())
+    }
+
+    fn pma(&self) -> Pma {
+        Pma::io(Size::WORD as u8 | Size::DWORD as u8)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn tick(&mut self, dma: &mut Dma) -> Option<DeviceEvent> {
+        self.process_commands(dma.memory());
+        None
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.ipsr & (IPSR_CIP | IPSR_FIP | IPSR_PIP) != 0
+    }
+
+    fn as_iommu(&mut self) -> Option<&mut dyn Iommu> {
+        Some(self)
+    }
+}
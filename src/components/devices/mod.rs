@@ -1,5 +1,84 @@
+use std::any::Any;
+
+use crate::components::{
+    dma::{Dma, Iommu},
+    mmu::Size,
+    pma::Pma,
+    trap::Exception,
+};
+
+pub mod aplic;
+pub mod chardev;
+pub mod clint;
+pub mod console;
+pub mod dmac;
 pub mod dram;
+pub mod imsic;
+pub mod iommu;
 pub mod plic;
 pub mod rom;
+pub mod rtc;
+pub mod syscon;
 pub mod test;
 pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+
+/// Request of a device towards the machine, returned by the tick hook.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeviceEvent {
+    /// Stop the execution, with the exit status of the emulator.
+    Exit(u64),
+    /// Reset the machine, as by a power cycle.
+    Reset,
+    /// Stop the execution on an error, already reported.
+    Stop,
+}
+
+/// Allows downcasting a `dyn Device` to the concrete type.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A memory-mapped device, attached to the `SystemBus` over a region of the physical address space.
+pub trait Device: AsAny {
+    /// Read a value of `size` at the `offset` within the region.
+    fn read(&mut self, offset: u64, size: Size) -> Result<u64, Exception>;
+
+    /// Write a value of `size` at the `offset` within the region.
+    fn write(&mut self, offset: u64, size: Size, value: u64) -> Result<(), Exception>;
+
+    /// Attributes of the region of the device, checked by the bus before any access reaches it.
+    fn pma(&self) -> Pma {
+        Pma::io(Pma::ALL_WIDTHS)
+    }
+
+    /// Bring the device back to its power-on state.
+    fn reset(&mut self) {}
+
+    /// Called once per cycle of the hart, lets the device advance and request an action from the machine.
+    /// Bus-mastering devices reach the memory through `dma`.
+    fn tick(&mut self, _dma: &mut Dma) -> Option<DeviceEvent> {
+        None
+    }
+
+    /// Interrupt output signal, routed to the interrupt controller when the region has an irq.
+    fn is_interrupting(&self) -> bool {
+        false
+    }
+
+    /// The device as an IOMMU, translating the DMA of the other devices.
+    fn as_iommu(&mut self) -> Option<&mut dyn Iommu> {
+        None
+    }
+}
@@ -8,65 +8,183 @@ const PENDING_BASE: u64 = 0x001000;
 const PENDING_END: u64 = 0x00107c;
 /* Enable bits region for contexts [0,1] */
 const ENABLE_BASE: u64 = 0x002000;
-const ENABLE_END: u64 = 0x0020fc;
+const ENABLE_END: u64 = ENABLE_BASE + ENABLE_STRIDE * CONTEXTS as u64 - 4;
+const ENABLE_STRIDE: u64 = 0x80;
 /* Threshold & Claim regions for contexts [0,1] */
 const THRESHOLD_CLAIM_BASE: u64 = 0x200000;
-const THRESHOLD_CLAIM_END: u64 = 0x201004;
+const THRESHOLD_CLAIM_END: u64 = THRESHOLD_CLAIM_BASE + CONTEXT_STRIDE * CONTEXTS as u64 - 4;
+const CONTEXT_STRIDE: u64 = 0x1000;
+/* Registers of a context, as offsets within its region */
+const THRESHOLD: u64 = 0x0;
+const CLAIM_COMPLETE: u64 = 0x4;
 
 /* base addresses for a word addressable array */
 const PENDING_W: usize = (PENDING_BASE >> 2) as usize;
-const ENABLE_W: usize = (ENABLE_BASE >> 2) as usize;
-const THRESHOLD_CLAIM_W: usize = (THRESHOLD_CLAIM_BASE >> 2) as usize;
+/// Context of the hart's M-mode external interrupt line
+pub const PLIC_M_CONTEXT: usize = 0;
+/// Context of the hart's S-mode external interrupt line
+pub const PLIC_S_CONTEXT: usize = 1;
+/// The M-mode and S-mode contexts of the single hart
+const CONTEXTS: usize = 2;
+/// Sources by default, the `riscv,ndev` of the device tree
+pub const PLIC_SOURCES: u32 = 0x35;
+/// Number of the sources the memory map has room for, source 0 included
+const MAX_SOURCES: usize = 1024;
+/// Highest number of sources a configuration may give
+pub const PLIC_MAX_SOURCES: u32 = MAX_SOURCES as u32 - 1;
+/// Priorities and thresholds are WARL, implementing 7 levels
+const PRIORITY_MASK: u32 = 0x7;
+
 /// Platform Level Interrupt Controller.
 /// https://wiki.osdev.org/PLIC.
 /// https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+/// The sources are level-triggered, a gateway forwarding a single request until its completion.
 pub struct Plic {
-    priority: [u32; 1024],
-    pending: [u32; 32],
-    enable: [u32; 64],
-    threshold: [u32; 2],
-    claim: [u32; 2],
+    /// Number of the sources, numbered from 1, the others being hardwired to zero
+    sources: u32,
+    priority: [u32; MAX_SOURCES],
+    pending: [u32; MAX_SOURCES / 32],
+    /// Claimed and not completed yet, the gateway holding any new request
+    in_service: [u32; MAX_SOURCES / 32],
+    enable: [[u32; MAX_SOURCES / 32]; CONTEXTS],
+    threshold: [u32; CONTEXTS],
 }
 impl Plic {
-    pub fn new() -> Self {
+    /// `sources` is at most `PLIC_MAX_SOURCES`, as checked by `Config::validate`.
+    pub fn new(sources: u32) -> Self {
         Self {
-            priority: [0; 1024],
-            pending: [0; 32],
-            enable: [0; 64],
-            threshold: [0; 2],
-            claim: [0; 2],
+            sources,
+            priority: [0; MAX_SOURCES],
+            pending: [0; MAX_SOURCES / 32],
+            in_service: [0; MAX_SOURCES / 32],
+            enable: [[0; MAX_SOURCES / 32]; CONTEXTS],
+            threshold: [0; CONTEXTS],
         }
     }
-    pub fn set_pending(&mut self, irq: u32, value: bool) {
+
+    /// Number of the sources, source 0 not existing.
+    pub fn sources(&self) -> u32 {
+        self.sources
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sources);
+    }
+
+    //& Interrupt source 0 does not exist
+    fn exists(&self, irq: u32) -> bool {
+        irq != 0 && irq <= self.sources
+    }
+
+    /// Bits of the word of sources that exist.
+    fn word_mask(&self, word: usize) -> u32 {
+        let first = word as u32 * 32;
+        let mut mask = match self.sources.checked_sub(first) {
+            Some(count) if count >= 31 => u32::MAX,
+            Some(count) => (1 << (count + 1)) - 1,
+            None => 0,
+        };
+        if word == 0 {
+            mask &= !1;
+        }
+        mask
+    }
+
+    /// Gateway of the source, driven by the level of its interrupt line.
+    pub fn set_source(&mut self, irq: u32, level: bool) {
+        if !self.exists(irq) {
+            return;
+        }
         // div by 32 to find the word the irq belongs to
         let word = (irq >> 5) as usize;
-        let bit = 1 << irq;
-        // make the value expand to 0x0.. or 0xf..
-        let value = -!(!(value as u32) as i32) as u32;
-        // clear the bit, then set it to the value
-        self.pending[word] = (self.pending[word] & !bit) | (value & bit);
+        let bit = 1 << (irq & 0x1f);
+        //& The gateway does not forward a new request until it receives the completion message.
+        if self.in_service[word] & bit != 0 {
+            return;
+        }
+        // A deasserted line withdraws its request
+        match level {
+            true => self.pending[word] |= bit,
+            false => self.pending[word] &= !bit,
+        }
     }
 
-    pub fn read(&self, offset: u64) -> Result<u32, Exception> {
+    /// The pending and enabled source with the highest priority over the threshold,
+    /// the lowest id first among equal priorities.
+    fn best(&self, context: usize) -> Option<u32> {
+        let mut best = None;
+        let mut max_priority = self.threshold[context];
+        for (word, (pending, enable)) in self.pending.iter().zip(&self.enable[context]).enumerate()
+        {
+            let mut active = pending & enable;
+            while active != 0 {
+                let irq = word as u32 * 32 + active.trailing_zeros();
+                if self.priority[irq as usize] > max_priority {
+                    max_priority = self.priority[irq as usize];
+                    best = Some(irq);
+                }
+                active &= active - 1;
+            }
+        }
+        best
+    }
+
+    /// Interrupt output signal of the context,
+    /// raised by a pending and enabled source with a priority over the threshold.
+    pub fn is_interrupting(&self, context: usize) -> bool {
+        self.best(context).is_some()
+    }
+
+    //& The PLIC core atomically clears the corresponding interrupt pending bit on the claim,
+    //& and returns the ID of the highest priority pending interrupt or zero if there is none.
+    fn claim(&mut self, context: usize) -> u32 {
+        let Some(irq) = self.best(context) else {
+            return 0;
+        };
+        let word = (irq >> 5) as usize;
+        let bit = 1 << (irq & 0x1f);
+        self.pending[word] &= !bit;
+        self.in_service[word] |= bit;
+        irq
+    }
+
+    //& If the completion ID does not match an interrupt source that is currently enabled for the target,
+    //& the completion is silently ignored.
+    fn complete(&mut self, context: usize, irq: u32) {
+        if !self.exists(irq) {
+            return;
+        }
+        let word = (irq >> 5) as usize;
+        let bit = 1 << (irq & 0x1f);
+        if self.enable[context][word] & bit != 0 {
+            self.in_service[word] &= !bit;
+        }
+    }
+
+    // The claim has to change the state, hence the mutable read
+    pub fn read(&mut self, offset: u64) -> Result<u32, Exception> {
         // offset only applied to words
         let index = (offset >> 2) as usize;
         match offset {
             SOURCE_PRIORITY_BASE..=SOURCE_PRIORITY_END => Ok(self.priority[index]),
-            PENDING_BASE..=PENDING_END => Ok(self.pending[index]),
-            ENABLE_BASE..=ENABLE_END => Ok(self.enable[index]),
+            PENDING_BASE..=PENDING_END => Ok(self.pending[index - PENDING_W]),
+            ENABLE_BASE..=ENABLE_END => {
+                let base = offset - ENABLE_BASE;
+                let context = (base / ENABLE_STRIDE) as usize;
+                let word = ((base % ENABLE_STRIDE) >> 2) as usize;
+                Ok(self.enable[context][word])
+            }
             THRESHOLD_CLAIM_BASE..=THRESHOLD_CLAIM_END => {
-                let base = index - THRESHOLD_CLAIM_W;
-                // Context occupies 4096 bytes, which is 2 + 10 shr
-                let context = base >> 10;
-                // Can be either first or second word
-                let offset = base & 1;
-                if offset == 0 {
-                    Ok(self.threshold[context])
-                } else {
-                    Ok(self.claim[context])
+                let base = offset - THRESHOLD_CLAIM_BASE;
+                let context = (base / CONTEXT_STRIDE) as usize;
+                match base % CONTEXT_STRIDE {
+                    THRESHOLD => Ok(self.threshold[context]),
+                    CLAIM_COMPLETE => Ok(self.claim(context)),
+                    // Reserved
+                    _ => Ok(0),
                 }
             }
-            _ => Err(Exception::LoadAccessFault),
+            _ => Err(Exception::LoadAccessFault(offset)),
         }
     }
 
@@ -75,27 +193,29 @@ impl Plic {
         let index = (offset >> 2) as usize;
         match offset {
             SOURCE_PRIORITY_BASE..=SOURCE_PRIORITY_END => {
-                self.priority[index] = value;
-            }
-            PENDING_BASE..=PENDING_END => {
-                self.pending[index - PENDING_W] = value;
+                if self.exists(index as u32) {
+                    self.priority[index] = value & PRIORITY_MASK;
+                }
             }
+            // The pending bits are only set and cleared by the gateways and the claims
+            PENDING_BASE..=PENDING_END => {}
             ENABLE_BASE..=ENABLE_END => {
-                self.enable[index - ENABLE_W] = value;
+                let base = offset - ENABLE_BASE;
+                let context = (base / ENABLE_STRIDE) as usize;
+                let word = ((base % ENABLE_STRIDE) >> 2) as usize;
+                self.enable[context][word] = value & self.word_mask(word);
             }
             THRESHOLD_CLAIM_BASE..=THRESHOLD_CLAIM_END => {
-                let base = index - THRESHOLD_CLAIM_W;
-                // Context occupies 4096 bytes, which is 2 + 10 shr
-                let context = base >> 10;
-                // Can be either first or second word
-                let offset = base & 1;
-                if offset == 0 {
-                    self.threshold[context] = value;
-                } else {
-                    self.claim[context] = value;
+                let base = offset - THRESHOLD_CLAIM_BASE;
+                let context = (base / CONTEXT_STRIDE) as usize;
+                match base % CONTEXT_STRIDE {
+                    THRESHOLD => self.threshold[context] = value & PRIORITY_MASK,
+                    CLAIM_COMPLETE => self.complete(context, value),
+                    // Reserved
+                    _ => {}
                 }
             }
-            _ => return Err(Exception::StoreAccessFault),
+            _ => return Err(Exception::StoreAccessFault(offset)),
         }
 
         Ok(())
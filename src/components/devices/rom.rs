@@ -2,8 +2,9 @@ use std::mem;
 
 use crate::{
     components::{
+        devices::Device,
         mmu::Size::{self, *},
-        system_bus::DRAM_BASE,
+        pma::Pma,
         trap::Exception,
     },
     cpu::PrivilegeMode,
@@ -26,9 +27,6 @@ struct FwDynamicInfo {
     boot_hart: u64,
 }
 
-//TODO: Set a start_pc
-const START_PC: u64 = DRAM_BASE;
-
 pub struct Mrom {
     memory: Vec<u8>,
 }
@@ -36,22 +34,23 @@ pub struct Mrom {
 //0x8000_0000 - 0x8010_0000 - OPENSBI - 1MB
 
 impl Mrom {
-    pub fn new() -> Self {
+    /// Firmware jumping to `start_pc`, the first stage loaded in RAM.
+    pub fn new(start_pc: u64) -> Self {
         let mut firmware: Vec<u32> = vec![0; 8];
         //auipc  t0, 0x0
         firmware[0] = 0x00000297;
         // addi  a1, t0, &dtb(0)
         firmware[1] = 0x00028593;
-        // csrrw  a0, mhartid
-        firmware[2] = 0xf1401573;
+        // csrr  a0, mhartid
+        firmware[2] = 0xf1402573;
         // ld  t0, 24(t0)
         firmware[3] = 0x0182b283;
         //jr t0
         firmware[4] = 0x00028067;
         //.data
-        // .dword START_PC
-        firmware[6] = START_PC as u32;
-        firmware[7] = (START_PC >> 32) as u32;
+        // .dword start_pc
+        firmware[6] = start_pc as u32;
+        firmware[7] = (start_pc >> 32) as u32;
 
         let d_info = FwDynamicInfo {
             magic: 0x4942534f,
@@ -74,13 +73,15 @@ impl Mrom {
         memory.extend_from_slice(d_info_bytes);
         Self { memory: memory }
     }
+}
 
-    pub fn read(&self, index: u64, size: Size) -> Result<u64, Exception> {
+impl Device for Mrom {
+    fn read(&mut self, index: u64, size: Size) -> Result<u64, Exception> {
         let index = index as usize;
         let bytes = self
             .memory
             .get(index..index + (size as usize))
-            .ok_or(Exception::LoadAccessFault)?;
+            .ok_or(Exception::LoadAccessFault(index as u64))?;
 
         let data = match size {
             BYTE => bytes[0] as u64,
@@ -91,4 +92,13 @@ impl Mrom {
 
         Ok(data)
     }
+
+    // The ROM isn't writable by design, the writes are ignored
+    fn write(&mut self, _: u64, _: Size, _: u64) -> Result<(), Exception> {
+        Ok(())
+    }
+
+    fn pma(&self) -> Pma {
+        Pma::ROM
+    }
 }
@@ -0,0 +1,180 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::components::{
+    devices::{Device, DeviceEvent},
+    dma::Dma,
+    mmu::Size,
+    pma::Pma,
+    trap::Exception,
+};
+
+/// Size of the addressable region
+pub const RTC_SIZE: u64 = 0x1000;
+pub const IRQ_RTC: u32 = 0x0b;
+
+/* Registers, 32 bits wide */
+// https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
+/// Reading the low half latches the high one
+const TIME_LOW: u64 = 0x00;
+const TIME_HIGH: u64 = 0x04;
+/// Writing the low half arms the alarm
+const ALARM_LOW: u64 = 0x08;
+const ALARM_HIGH: u64 = 0x0c;
+const IRQ_ENABLED: u64 = 0x10;
+const CLEAR_ALARM: u64 = 0x14;
+const ALARM_STATUS: u64 = 0x18;
+const CLEAR_INTERRUPT: u64 = 0x1c;
+
+/// Time passing on each cycle of the hart when the clock doesn't follow the host, as at 100MHz
+const TICK_NANOS: u64 = 10;
+
+/// Source of the passing time.
+enum Clock {
+    /// The host clock, from the instant.
+    Host(Instant),
+    /// The cycles of the hart, for runs not depending on the host.
+    Ticks(u64),
+}
+
+/// Goldfish real-time clock, counting the nanoseconds since the Unix epoch, with an alarm.
+/// Described to the software by the device-tree node:
+/// ```text
+/// rtc@101000 {
+///     compatible = "google,goldfish-rtc";
+///     reg = <0x0 0x101000 0x0 0x1000>;
+///     interrupts = <11>;
+/// };
+/// ```
+pub struct Rtc {
+    /// Time at the start of `clock`
+    time: u64,
+    clock: Clock,
+    time_high: u32,
+    alarm: u64,
+    alarm_running: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Rtc {
+    /// With `start` seconds since the Unix epoch as the initial time, the time then advancing with the cycles,
+    /// the host time when none.
+    pub fn new(start: Option<u64>) -> Self {
+        let (time, clock) = match start {
+            Some(seconds) => (seconds.saturating_mul(1_000_000_000), Clock::Ticks(0)),
+            None => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64);
+                (now, Clock::Host(Instant::now()))
+            }
+        };
+        Self {
+            time,
+            clock,
+            time_high: 0,
+            alarm: 0,
+            alarm_running: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn time(&self) -> u64 {
+        let elapsed = match self.clock {
+            Clock::Host(start) => start.elapsed().as_nanos() as u64,
+            Clock::Ticks(ticks) => ticks.wrapping_mul(TICK_NANOS),
+        };
+        self.time.wrapping_add(elapsed)
+    }
+
+    fn set_time(&mut self, time: u64) {
+        self.time = time;
+        self.clock = match self.clock {
+            Clock::Host(_) => Clock::Host(Instant::now()),
+            Clock::Ticks(_) => Clock::Ticks(0),
+        };
+    }
+
+    /// An alarm already due fires at once.
+    fn set_alarm(&mut self) {
+        self.alarm_running = true;
+        self.check_alarm();
+    }
+
+    fn check_alarm(&mut self) {
+        if self.alarm_running && self.time() >= self.alarm {
+            self.alarm_running = false;
+            self.irq_pending = true;
+        }
+    }
+}
+
+/// Replace the low or the high half of the value.
+fn deposit(value: u64, high: bool, half: u32) -> u64 {
+    match high {
+        true => (value & 0xffff_ffff) | ((half as u64) << 32),
+        false => (value & !0xffff_ffff) | half as u64,
+    }
+}
+
+impl Device for Rtc {
+    fn read(&mut self, offset: u64, _: Size) -> Result<u64, Exception> {
+        let value = match offset {
+            TIME_LOW => {
+                let time = self.time();
+                self.time_high = (time >> 32) as u32;
+                time as u32
+            }
+            TIME_HIGH => self.time_high,
+            ALARM_LOW => self.alarm as u32,
+            ALARM_HIGH => (self.alarm >> 32) as u32,
+            IRQ_ENABLED => self.irq_enabled as u32,
+            ALARM_STATUS => self.alarm_running as u32,
+            _ => 0,
+        };
+        Ok(value as u64)
+    }
+
+    fn write(&mut self, offset: u64, _: Size, value: u64) -> Result<(), Exception> {
+        let value = value as u32;
+        match offset {
+            TIME_LOW | TIME_HIGH => self.set_time(deposit(self.time(), offset == TIME_HIGH, value)),
+            ALARM_LOW => {
+                self.alarm = deposit(self.alarm, false, value);
+                self.set_alarm();
+            }
+            ALARM_HIGH => self.alarm = deposit(self.alarm, true, value),
+            IRQ_ENABLED => self.irq_enabled = value & 1 != 0,
+            CLEAR_ALARM => self.alarm_running = false,
+            CLEAR_INTERRUPT => self.irq_pending = false,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn pma(&self) -> Pma {
+        Pma::io(Size::WORD as u8)
+    }
+
+    // The clock keeps running across a reset
+    fn reset(&mut self) {
+        self.time_high = 0;
+        self.alarm = 0;
+        self.alarm_running = false;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    fn tick(&mut self, _: &mut Dma) -> Option<DeviceEvent> {
+        if let Clock::Ticks(ticks) = &mut self.clock {
+            *ticks += 1;
+        }
+        self.check_alarm();
+        None
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    components::{
+        devices::{Device, DeviceEvent},
+        dma::Dma,
+        mmu::Size,
+        pma::Pma,
+        trap::Exception,
+    },
+    config::{SysconAction, SysconConfig},
+};
+
+/// Size of the addressable region
+pub const SYSCON_SIZE: u64 = 0x1000;
+
+/// Block of 32-bit system control registers, powering off or rebooting the machine
+/// when one of them is updated with the value of an action, as by the `syscon-poweroff` and `syscon-reboot` drivers:
+/// ```text
+/// syscon: syscon@102000 {
+///     compatible = "syscon";
+///     reg = <0x0 0x102000 0x0 0x1000>;
+/// };
+/// poweroff {
+///     compatible = "syscon-poweroff";
+///     regmap = <&syscon>;
+///     offset = <OFFSET>;
+///     value = <VALUE>;
+///     mask = <MASK>;
+/// };
+/// ```
+/// with the same properties for the `syscon-reboot` node.
+pub struct Syscon {
+    registers: Vec<u32>,
+    poweroff: SysconAction,
+    reboot: SysconAction,
+    event: Option<DeviceEvent>,
+}
+
+impl Syscon {
+    pub fn new(config: &SysconConfig) -> Self {
+        Self {
+            registers: vec![0; (SYSCON_SIZE / 4) as usize],
+            poweroff: config.poweroff,
+            reboot: config.reboot,
+            event: None,
+        }
+    }
+}
+
+impl SysconAction {
+    /// The drivers update the bits of the mask to the value, the others are kept.
+    fn is_triggered(&self, offset: u64, register: u32) -> bool {
+        offset == self.offset && register & self.mask == self.value & self.mask
+    }
+}
+
+impl Device for Syscon {
+    fn read(&mut self, offset: u64, _: Size) -> Result<u64, Exception> {
+        Ok(self.registers[(offset / 4) as usize] as u64)
+    }
+
+    fn write(&mut self, offset: u64, _: Size, value: u64) -> Result<(), Exception> {
+        let register = value as u32;
+        self.registers[(offset / 4) as usize] = register;
+        if self.poweroff.is_triggered(offset, register) {
+            self.event = Some(DeviceEvent::Exit(0));
+        } else if self.reboot.is_triggered(offset, register) {
+            self.event = Some(DeviceEvent::Reset);
+        }
+        Ok(())
+    }
+
+    // Registers are 32 bits wide, as by the default reg-io-width of the syscon regmap
+    fn pma(&self) -> Pma {
+        Pma::io(Size::WORD as u8)
+    }
+
+    fn reset(&mut self) {
+        self.registers.fill(0);
+        self.event = None;
+    }
+
+    fn tick(&mut self, _: &mut Dma) -> Option<DeviceEvent> {
+        self.event.take()
+    }
+}
@@ -1,33 +1,60 @@
-use crate::components::mmu::Size;
+use crate::components::{
+    devices::{Device, DeviceEvent},
+    dma::Dma,
+    mmu::Size,
+    pma::Pma,
+    trap::Exception,
+};
 
+/// SiFive test finisher, as qemu's `sifive_test`.
+/// A write of the finisher register carries the status in bits 15:0 and the exit code of a failure in bits 31:16.
 pub struct Test {
-    pub exit: u64,
+    event: Option<DeviceEvent>,
 }
 
 impl Test {
+    const FINISHER: u64 = 0;
     const FINISHER_FAIL: u64 = 0x3333;
     const FINISHER_PASS: u64 = 0x5555;
     const FINISHER_RESET: u64 = 0x7777;
 
     pub fn new() -> Test {
-        Self { exit: 0 }
+        Self { event: None }
+    }
+}
+
+impl Device for Test {
+    // Nothing is readable, the register reads as zero
+    fn read(&mut self, _: u64, _: Size) -> Result<u64, Exception> {
+        Ok(0)
     }
 
-    pub fn write(&mut self, index: u64, _: Size, value: u64) {
-        match index {
-            0 => match value {
-                Test::FINISHER_FAIL => {
-                    self.exit = 1;
-                }
-                Test::FINISHER_PASS => {
-                    self.exit = 2;
-                }
-                Test::FINISHER_RESET => {
-                    self.exit = 3;
-                }
-                _ => {}
-            },
-            _ => {}
+    fn write(&mut self, index: u64, _: Size, value: u64) -> Result<(), Exception> {
+        if index != Test::FINISHER {
+            return Ok(());
         }
+        let status = value & 0xffff;
+        let code = (value >> 16) & 0xffff;
+        self.event = match status {
+            Test::FINISHER_FAIL => Some(DeviceEvent::Exit(code)),
+            Test::FINISHER_PASS => Some(DeviceEvent::Exit(0)),
+            Test::FINISHER_RESET => Some(DeviceEvent::Reset),
+            // Other statuses are ignored, as by qemu
+            _ => None,
+        };
+        Ok(())
+    }
+
+    // The finisher is written a halfword or a word at a time, as the sifive test device
+    fn pma(&self) -> Pma {
+        Pma::io(Size::HWORD as u8 | Size::WORD as u8)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn tick(&mut self, _: &mut Dma) -> Option<DeviceEvent> {
+        self.event.take()
     }
 }
@@ -1,17 +1,36 @@
 #![allow(unused_parens)]
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    mem,
+    time::{Duration, Instant},
+};
 
 use arbitrary_int::{u1, u2, u4};
 use bitbybit::{bitenum, bitfield};
 
 use crate::{
-    components::trap::Exception,
+    components::{
+        devices::{
+            Device, DeviceEvent,
+            chardev::{CharBackend, Null},
+        },
+        dma::Dma,
+        mmu::Size,
+        pma::Pma,
+        trap::Exception,
+    },
     util::{F, T},
 };
 
 /// Size of the addressable region
 pub const UART_SIZE: u64 = 0x100;
 pub const IRQ_UART: u32 = 0x0a;
+/// Depth of the FIFOs
+const FIFO_SIZE: usize = 16;
+/// Input clock, the `clock-frequency` of the device tree node
+pub const UART_CLOCK: u64 = 3_686_400;
+/// The backend is asked for input once every this many cycles, as each poll may be a system call
+const POLL_INTERVAL: u32 = 1024;
 
 /* uart register addresses*/
 /// Receiver Buffer (read)
@@ -37,13 +56,31 @@ const SCR: usize = 0b111;
 /// Divisor Latch (LSB)
 const DLL: (u8, usize) = (0b1, 0b000);
 /// Divisor Latch (MSB)
-const DLH: (u8, usize) = (0b1, 0b000);
+const DLH: (u8, usize) = (0b1, 0b001);
 
 #[bitfield(u8)]
 struct LCR {
     /// Divisor latch access bit
     #[bit(7, rw)]
     dlab: u1,
+    /// Set Break
+    #[bit(6, rw)]
+    bc: u1,
+    /// Stick Parity
+    #[bit(5, rw)]
+    sp: u1,
+    /// Even Parity Select
+    #[bit(4, rw)]
+    eps: u1,
+    /// Parity Enable
+    #[bit(3, rw)]
+    pen: u1,
+    /// Number of Stop Bits
+    #[bit(2, rw)]
+    stb: u1,
+    /// Word Length Select, 5 to 8 bits
+    #[bits(0..=1, rw)]
+    wls: u2,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
@@ -160,6 +197,37 @@ struct MCR {
     #[bit(0, rw)]
     dtr: u1,
 }
+#[bitfield(u8)]
+struct MSR {
+    /// Data Carrier Detect
+    #[bit(7, rw)]
+    dcd: u1,
+    /// Ring Indicator
+    #[bit(6, rw)]
+    ri: u1,
+    /// Data Set Ready
+    #[bit(5, rw)]
+    dsr: u1,
+    /// Clear to Send
+    #[bit(4, rw)]
+    cts: u1,
+    /// Delta Data Carrier Detect
+    #[bit(3, rw)]
+    ddcd: u1,
+    /// Trailing Edge Ring Indicator
+    #[bit(2, rw)]
+    teri: u1,
+    /// Delta Data Set Ready
+    #[bit(1, rw)]
+    ddsr: u1,
+    /// Delta Clear to Send
+    #[bit(0, rw)]
+    dcts: u1,
+    /// The changes of the inputs since the last read, MODEM Status Interrupt sources
+    #[bits(0..=3, rw)]
+    deltas: u4,
+}
+
 #[bitfield(u8)]
 struct IER {
     /// Enable MODEM Status Interrupt
@@ -183,11 +251,26 @@ struct IER {
 /// Qemu uses this one in it's virt machine.
 /// https://github.com/qemu/qemu/blob/master/hw/char/serial.c
 /// https://courses.grainger.illinois.edu/ece391/su2025/docs/NS16550A.pdf
+/// The characters are shifted out at the pace of the programmed baud rate and frame format,
+/// the line itself being error free, so parity and framing errors and breaks are never received.
+/// Each instance is described to the software by a device-tree node, from its base and interrupt:
+/// ```text
+/// serial@10000000 {
+///     compatible = "ns16550a";
+///     reg = <0x0 0x10000000 0x0 0x100>;
+///     clock-frequency = <0x384000>;
+///     interrupt-parent = <&plic>;
+///     interrupts = <0x0a>;
+/// };
+/// ```
 pub struct Uart {
     rbr: u8,
     thr: u8,
     recv_fifo: VecDeque<u8>,
     xmit_fifo: VecDeque<u8>,
+    /// Transmitter Shift Register, with the time its character is out
+    tsr: Option<(u8, Instant)>,
+    divisor: u16,
 
     ier: IER,
     iir: IIR,
@@ -195,44 +278,192 @@ pub struct Uart {
     lcr: LCR,
     mcr: MCR,
     lsr: LSR,
-    msr: u8,
+    msr: MSR,
     scr: u8,
 
     /// Interrupt output signal
     intr: bool,
     thr_ipending: bool,
     recv_fifo_itl: u8,
+    /// When the character timeout is reached, with characters in the RCVR FIFO
+    rx_timeout: Option<Instant>,
+    timeout_ipending: bool,
 
     reg: [u8; UART_SIZE as usize],
+
+    /// Host side of the serial line
+    backend: Box<dyn CharBackend>,
+    poll_countdown: u32,
 }
 
 impl Uart {
-    pub fn new() -> Uart {
+    pub fn new(backend: Box<dyn CharBackend>) -> Uart {
         Self {
             rbr: 0,
             thr: 0,
-            recv_fifo: VecDeque::with_capacity(16),
-            xmit_fifo: VecDeque::with_capacity(16),
+            recv_fifo: VecDeque::with_capacity(FIFO_SIZE),
+            xmit_fifo: VecDeque::with_capacity(FIFO_SIZE),
+            tsr: None,
+            divisor: 0,
 
             ier: IER::ZERO,
-            iir: IIR::ZERO,
+            iir: IIR::ZERO.with_id(IIR::ID_NONE),
             fcr: FCR::ZERO,
             lcr: LCR::ZERO,
             mcr: MCR::ZERO,
-            lsr: LSR::ZERO,
-            msr: 0,
+            // THRE and TEMT are set on reset, the transmitter being empty
+            lsr: LSR::ZERO.with_thre(T).with_temt(T),
+            // The host side of the line is always ready
+            msr: MSR::ZERO.with_dcd(T).with_dsr(T).with_cts(T),
             scr: 0,
 
             intr: false,
             thr_ipending: false,
-            recv_fifo_itl: 0,
+            recv_fifo_itl: 1,
+            rx_timeout: None,
+            timeout_ipending: false,
 
             reg: [0; UART_SIZE as usize],
+
+            backend,
+            poll_countdown: 0,
         }
     }
 
-    pub fn is_interrupting(&self) -> bool {
-        self.intr
+    /// Time to shift a character in or out, from the baud rate and the frame format.
+    fn char_time(&self) -> Duration {
+        let data = 5 + self.lcr.wls().value() as u64;
+        // In half bits, for the 1.5 stop bits of 5-bit characters
+        let stop = match (self.lcr.stb() == T, data) {
+            (false, _) => 2,
+            (true, 5) => 3,
+            (true, _) => 4,
+        };
+        let half_bits = 2 * (1 + data + self.lcr.pen().value() as u64) + stop;
+        //& The baud rate is the input clock divided by 16 times the divisor
+        // A divisor of 0 is taken as 1, the fastest rate
+        let divisor = self.divisor.max(1) as u64;
+        Duration::from_nanos(divisor * 16 * half_bits * 1_000_000_000 / (2 * UART_CLOCK))
+    }
+
+    /// The bits of a character in the selected word length.
+    fn word_mask(&self) -> u8 {
+        0xff >> (3 - self.lcr.wls().value())
+    }
+
+    /// Receive a byte from the host, when there is room for it.
+    fn receive(&mut self) {
+        // The serial input is disconnected in loopback mode
+        if self.mcr.lpb() == T {
+            return;
+        }
+        let full = match self.fcr.fe() == T {
+            true => self.recv_fifo.len() == FIFO_SIZE,
+            false => self.lsr.dr() == T,
+        };
+        if full {
+            return;
+        }
+        if self.poll_countdown > 0 {
+            self.poll_countdown -= 1;
+            return;
+        }
+        self.poll_countdown = POLL_INTERVAL;
+        if let Some(byte) = self.backend.read() {
+            self.push_rx(byte);
+        }
+    }
+
+    /// A character assembled by the receiver.
+    fn push_rx(&mut self, byte: u8) {
+        let byte = byte & self.word_mask();
+        if self.fcr.fe() == T {
+            //& The character in the shift register is overwritten, but it is not transferred to the FIFO.
+            if self.recv_fifo.len() == FIFO_SIZE {
+                self.lsr.set_oe(T);
+            } else {
+                self.recv_fifo.push_back(byte);
+            }
+            self.restart_timeout();
+        } else {
+            //& The next character was transferred into the RBR before the previous character was read.
+            if self.lsr.dr() == T {
+                self.lsr.set_oe(T);
+            }
+            self.rbr = byte;
+        }
+        self.lsr.set_dr(T);
+        self.update_iir();
+    }
+
+    //& No characters have been removed from or input to the RCVR FIFO during the last 4 Char. times
+    //& and there is at least 1 Char. in it during this time.
+    fn restart_timeout(&mut self) {
+        self.timeout_ipending = false;
+        self.rx_timeout = match self.recv_fifo.is_empty() {
+            true => None,
+            false => Some(Instant::now() + 4 * self.char_time()),
+        };
+    }
+
+    /// Shift the characters out, loading the shift register from the holding register or the XMIT FIFO.
+    fn transmit(&mut self) {
+        if let Some((byte, done)) = self.tsr {
+            if Instant::now() < done {
+                return;
+            }
+            self.tsr = None;
+            // The transmitter output is looped back to the receiver input
+            match self.mcr.lpb() == T {
+                true => self.push_rx(byte),
+                false => self.backend.write(byte),
+            }
+        }
+
+        let next = match self.fcr.fe() == T {
+            true => self.xmit_fifo.pop_front(),
+            false => (self.lsr.thre() == F).then_some(self.thr),
+        };
+        match next {
+            Some(byte) => {
+                let byte = byte & self.word_mask();
+                self.tsr = Some((byte, Instant::now() + self.char_time()));
+                if self.xmit_fifo.is_empty() {
+                    self.lsr.set_thre(T);
+                    self.thr_ipending = true;
+                    self.update_iir();
+                }
+            }
+            //& This bit is set to a logic 1 whenever the THR and the TSR are both empty.
+            None => self.lsr.set_temt(T),
+        }
+    }
+
+    /// Inputs of the modem control lines, driven by the outputs in loopback mode.
+    fn modem_inputs(&self) -> MSR {
+        match self.mcr.lpb() == T {
+            true => MSR::ZERO
+                .with_cts(self.mcr.rts())
+                .with_dsr(self.mcr.dtr())
+                .with_ri(self.mcr.out1())
+                .with_dcd(self.mcr.out2()),
+            false => MSR::ZERO.with_dcd(T).with_dsr(T).with_cts(T),
+        }
+    }
+
+    /// Latch the changes of the modem control inputs into the delta bits.
+    fn update_msr(&mut self) {
+        let old = self.msr;
+        let new = self.modem_inputs();
+        //& TERI indicates that the RI input to the chip has changed from a low to a high state.
+        // The pin is the complement of the bit
+        let teri = (old.ri() == T) && (new.ri() == F);
+        self.msr = new
+            .with_dcts(old.dcts() | (old.cts() ^ new.cts()))
+            .with_ddsr(old.ddsr() | (old.dsr() ^ new.dsr()))
+            .with_teri(if teri { T } else { old.teri() })
+            .with_ddcd(old.ddcd() | (old.dcd() ^ new.dcd()));
+        self.update_iir();
     }
 
     fn update_iir(&mut self) {
@@ -246,14 +477,16 @@ impl Uart {
             && ((self.fcr.fe() == F)
                 // Or itl has been reached for fifo
                 || (self.recv_fifo.len() >= self.recv_fifo_itl as usize));
-        // In qemu srcode, it is said that this might get masked by the above handling
-        //TODO:
-        let cti = self.ier.erbfi() == T;
+        let cti = (self.ier.erbfi() == T)
+            //& Character Timeout Indication
+            && (self.fcr.fe() == T)
+            && self.timeout_ipending;
         let threi = (self.ier.etbei() == T)
             //& Transmitter Holding Register Empty
             && (self.thr_ipending);
-        //TODO:
-        let msi = self.ier.edssi() == T;
+        let msi = (self.ier.edssi() == T)
+            //& Clear to Send or Data Set Ready or Ring Indicator or Data Carrier Detect
+            && (self.msr.deltas().value() != 0);
 
         let id = match () {
             _ if rlsi => IIR::ID_RLSI,
@@ -281,11 +514,16 @@ impl Uart {
         //&  Writing a 1 to FCR1 clears all bytes in the RCVR FIFO and resets its counter logic to 0.
         if val.rfr() == T {
             self.recv_fifo.clear();
+            self.lsr.set_dr(F);
+            self.lsr.set_bi(F);
+            self.restart_timeout();
         }
 
         //& Writing a 1 to FCR2 clears all bytes in the XMIT FIFO and resets its counter logic to 0.
         if val.xfr() == T {
             self.xmit_fifo.clear();
+            self.lsr.set_thre(T);
+            self.thr_ipending = true;
         }
 
         self.fcr = FCR::new_with_raw_value(
@@ -307,19 +545,20 @@ impl Uart {
         self.update_iir();
     }
 
-    pub fn read(&mut self, offset: u64) -> Result<u8, Exception> {
+    fn read_byte(&mut self, offset: u64) -> u8 {
         let dlab: u8 = self.lcr.dlab().value();
 
         let ret: u8;
         let data = match (dlab, offset as usize) {
             RBR => {
                 if self.fcr.fe() == T {
-                    ret = self.recv_fifo.pop_back().unwrap_or(0);
+                    ret = self.recv_fifo.pop_front().unwrap_or(0);
 
                     if self.recv_fifo.is_empty() {
                         self.lsr.set_dr(F);
                         self.lsr.set_bi(F);
                     }
+                    self.restart_timeout();
                 } else {
                     ret = self.rbr;
 
@@ -331,13 +570,16 @@ impl Uart {
                 ret
             }
             IER => self.ier.raw_value(),
+            DLL => self.divisor as u8,
+            DLH => (self.divisor >> 8) as u8,
             (_, IIR) => {
+                ret = self.iir.raw_value();
                 // Reading the IIR Register resets THREI
                 if self.iir.id() == IIR::ID_THREI {
                     self.thr_ipending = false;
                     self.update_iir();
                 }
-                self.iir.raw_value()
+                ret
             }
             (_, LCR) => self.lcr.raw_value(),
             (_, MCR) => self.mcr.raw_value(),
@@ -351,42 +593,40 @@ impl Uart {
                 self.update_iir();
                 ret
             }
-            (_, MSR) => 0,
+            (_, MSR) => {
+                ret = self.msr.raw_value();
+                // The delta bits are reset on read
+                self.msr.set_deltas(u4::new(0));
+
+                self.update_iir();
+                ret
+            }
             (_, SCR) => self.scr,
             _ => self.reg[offset as usize],
         };
 
-        Ok(data)
+        data
     }
 
-    pub fn write(&mut self, offset: u64, value: u8) -> Result<(), Exception> {
+    fn write_byte(&mut self, offset: u64, value: u8) {
         let dlab: u8 = self.lcr.dlab().value();
 
         match (dlab, offset as usize) {
             THR => {
-                self.thr = value;
-
-                if self.fcr.fe() == T {
-                    // In case an overrun occurs
-                    if self.recv_fifo.len() == self.recv_fifo.capacity() {
-                        self.recv_fifo.pop_back();
-                        self.lsr.set_oe(T);
-                    }
-
-                    self.recv_fifo.push_front(value);
-                };
-
-                // Writing into the THR resets THREI
-                self.thr_ipending = false;
+                // A write to a full XMIT FIFO is lost
+                if self.fcr.fe() == F {
+                    self.thr = value;
+                } else if self.xmit_fifo.len() < FIFO_SIZE {
+                    self.xmit_fifo.push_back(value);
+                }
                 self.lsr.set_thre(F);
                 self.lsr.set_temt(F);
+                // Writing the THR resets THREI
+                self.thr_ipending = false;
                 self.update_iir();
-
-                //TODO:
-                // how this should be consumed?
             }
             IER => {
-                let val = IER::new_with_raw_value(value);
+                let val = IER::new_with_raw_value(value & 0x0f);
                 // If the bit changed
                 if (self.ier.etbei() ^ val.etbei()) == T {
                     if (val.etbei() == T) && (self.lsr.thre() == T) {
@@ -399,23 +639,67 @@ impl Uart {
                 self.ier = val;
                 self.update_iir();
             }
+            DLL => self.divisor = (self.divisor & 0xff00) | value as u16,
+            DLH => self.divisor = (self.divisor & 0x00ff) | ((value as u16) << 8),
             (_, FCR) => self.write_fcr(value),
             (_, LCR) => {
-                //TBD
+                self.lcr = LCR::new_with_raw_value(value);
             }
             (_, MCR) => {
-                self.mcr = MCR::new_with_raw_value(value);
-            }
-            (_, MSR) => {
-                self.msr = value;
+                self.mcr = MCR::new_with_raw_value(value & 0x1f);
+                // Entering or leaving loopback, or changing its outputs, moves the modem inputs
+                self.update_msr();
             }
             (_, SCR) => {
                 self.scr = value;
             }
             //& The Line Status Register is intended for read operations only.
             //& Writing to this register is not recommended as this operation is only used for factory testing.
-            (_, LSR) | _ => {}
+            // The same goes for the MODEM Status Register, which follows the inputs
+            (_, LSR) | (_, MSR) | _ => {}
         }
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64, _: Size) -> Result<u64, Exception> {
+        Ok(self.read_byte(offset) as u64)
+    }
+
+    fn write(&mut self, offset: u64, _: Size, value: u64) -> Result<(), Exception> {
+        self.write_byte(offset, value as u8);
         Ok(())
     }
+
+    // The registers are byte wide
+    fn pma(&self) -> Pma {
+        Pma::io(Size::BYTE as u8)
+    }
+
+    // The backend stays connected
+    fn reset(&mut self) {
+        let backend = mem::replace(&mut self.backend, Box::new(Null));
+        *self = Self::new(backend);
+    }
+
+    fn tick(&mut self, _: &mut Dma) -> Option<DeviceEvent> {
+        self.receive();
+        self.transmit();
+        if self
+            .rx_timeout
+            .is_some_and(|timeout| Instant::now() >= timeout)
+        {
+            self.rx_timeout = None;
+            self.timeout_ipending = true;
+            self.update_iir();
+        }
+        // Quitting from the backend ends the run cleanly
+        self.backend
+            .quit_requested()
+            .then_some(DeviceEvent::Exit(0))
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.intr
+    }
 }
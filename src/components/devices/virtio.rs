@@ -0,0 +1,450 @@
+use crate::components::{
+    devices::{Device, DeviceEvent},
+    dma::{Dma, DmaError},
+    mmu::Size,
+    trap::Exception,
+};
+
+/// Size of the addressable region of a transport
+pub const VIRTIO_SIZE: u64 = 0x1000;
+/// Transports of the machine, one after the other from the first base, as on qemu `virt`
+pub const VIRTIO_COUNT: usize = 8;
+/// Interrupt source of the first transport, the next ones following
+pub const IRQ_VIRTIO: u32 = 0x01;
+/// Requester id of the first transport towards the IOMMU, the next ones following
+pub const VIRTIO_DEVICE_ID: u32 = 0x10;
+
+/* Registers of the transport, 32 bits wide */
+// https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html#x1-1650002
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00c;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+/// Device-specific configuration space
+const CONFIG: u64 = 0x100;
+
+/// "virt" in little endian
+const MAGIC: u32 = 0x7472_6976;
+/// The modern transport, without the legacy registers
+const TRANSPORT_VERSION: u32 = 2;
+/// "QEMU", the vendor the drivers are used to
+const VENDOR: u32 = 0x554d_4551;
+
+/* Device status bits */
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_DEVICE_NEEDS_RESET: u32 = 64;
+
+/* Interrupt status bits */
+const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+const INTERRUPT_CONFIG_CHANGE: u32 = 1 << 1;
+
+/// Compliance with the version 1 of the spec, offered by every device
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// Entries of each queue at most
+const QUEUE_SIZE_MAX: u16 = 256;
+
+/* Virtqueue descriptor flags */
+/// The buffer continues in the `next` descriptor
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+/// The buffer is write-only for the device
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// A buffer of a descriptor chain, in the memory of the driver.
+#[derive(Clone, Copy, Debug)]
+pub struct Buffer {
+    pub address: u64,
+    pub len: u32,
+    /// Written by the device, read otherwise
+    pub writable: bool,
+}
+
+/// A request of the driver, as the buffers of a descriptor chain,
+/// the device-readable ones coming before the device-writable ones.
+pub struct Chain {
+    pub buffers: Vec<Buffer>,
+}
+
+impl Chain {
+    /// The device-readable part of the chain, as one sequence of bytes,
+    /// or `None` when it is longer than `max`, for the device to fail the request without buffering it.
+    pub fn read(&self, dma: &mut Dma, max: usize) -> Result<Option<Vec<u8>>, DmaError> {
+        let len: usize = self
+            .buffers
+            .iter()
+            .filter(|b| !b.writable)
+            .map(|b| b.len as usize)
+            .sum();
+        if len > max {
+            return Ok(None);
+        }
+        let mut bytes = Vec::with_capacity(len);
+        for buffer in self.buffers.iter().filter(|b| !b.writable) {
+            let start = bytes.len();
+            bytes.resize(start + buffer.len as usize, 0);
+            dma.read(buffer.address, &mut bytes[start..])?;
+        }
+        Ok(Some(bytes))
+    }
+
+    /// Bytes the device can write.
+    pub fn writable_len(&self) -> usize {
+        self.buffers
+            .iter()
+            .filter(|b| b.writable)
+            .map(|b| b.len as usize)
+            .sum()
+    }
+
+    /// Spread `bytes` over the device-writable part of the chain from `offset` in it,
+    /// returning the bytes written.
+    pub fn write(&self, dma: &mut Dma, offset: usize, bytes: &[u8]) -> Result<u32, DmaError> {
+        let mut offset = offset;
+        let mut bytes = bytes;
+        let mut written = 0;
+        for buffer in self.buffers.iter().filter(|b| b.writable) {
+            if bytes.is_empty() {
+                break;
+            }
+            // The buffers before the offset are skipped
+            let len = buffer.len as usize;
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let (head, tail) = bytes.split_at(bytes.len().min(len - offset));
+            dma.write(buffer.address + offset as u64, head)?;
+            written += head.len() as u32;
+            bytes = tail;
+            offset = 0;
+        }
+        Ok(written)
+    }
+}
+
+/// A device behind a virtio-mmio transport, serving the requests of its queues.
+pub trait VirtioDevice {
+    fn device_id(&self) -> u32;
+
+    /// Feature bits offered to the driver, beside `VIRTIO_F_VERSION_1`.
+    fn features(&self) -> u64;
+
+    fn queues(&self) -> usize;
+
+    /// The device-specific configuration space, in little endian.
+    fn config(&self) -> Vec<u8>;
+
+    /// Serve the request of the chain taken from the queue, returning the bytes written to it.
+    fn process(&mut self, queue: usize, chain: &Chain, dma: &mut Dma) -> Result<u32, DmaError>;
+}
+
+/// A split virtqueue, as set up by the driver.
+#[derive(Clone, Copy, Default)]
+struct Queue {
+    num: u16,
+    ready: bool,
+    /// Descriptor Area
+    desc: u64,
+    /// Driver Area, the available ring
+    driver: u64,
+    /// Device Area, the used ring
+    device: u64,
+    /// Next entry of the available ring to process
+    last_avail: u16,
+    /// Next entry of the used ring to fill
+    used_idx: u16,
+    notified: bool,
+}
+
+impl Queue {
+    /// Walk the descriptor chain from `head`.
+    fn chain(&self, dma: &mut Dma, head: u16) -> Result<Chain, DmaError> {
+        let mut buffers = Vec::new();
+        let mut index = head;
+        // A chain longer than the queue loops
+        for _ in 0..self.num {
+            let mut desc = [0; 16];
+            let address = self.desc + 16 * (index % self.num) as u64;
+            dma.read(address, &mut desc)?;
+            let flags = u16::from_le_bytes([desc[12], desc[13]]);
+            buffers.push(Buffer {
+                address: u64::from_le_bytes(desc[0..8].try_into().unwrap()),
+                len: u32::from_le_bytes(desc[8..12].try_into().unwrap()),
+                writable: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(Chain { buffers });
+            }
+            index = u16::from_le_bytes([desc[14], desc[15]]);
+        }
+        Err(DmaError::AccessFault(
+            self.desc + 16 * (index % self.num) as u64,
+        ))
+    }
+}
+
+/// Virtio over memory-mapped registers, version 2, with split virtqueues.
+/// https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html#x1-1440002
+/// A transport without a device reads a device ID of 0, for the driver to skip it,
+/// so that every slot of the device tree can be described whether populated or not:
+/// ```text
+/// virtio_mmio@10001000 {
+///     compatible = "virtio,mmio";
+///     reg = <0x0 0x10001000 0x0 0x1000>;
+///     interrupt-parent = <&plic>;
+///     interrupts = <0x01>;
+/// };
+/// ```
+/// The queues are served on the cycle after their notification.
+pub struct VirtioMmio {
+    device: Option<Box<dyn VirtioDevice>>,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Queue>,
+    interrupt_status: u32,
+    status: u32,
+}
+
+impl VirtioMmio {
+    pub fn new(device: Option<Box<dyn VirtioDevice>>) -> Self {
+        let queues = device.as_ref().map_or(0, |d| d.queues());
+        Self {
+            device,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues: vec![Queue::default(); queues],
+            interrupt_status: 0,
+            status: 0,
+        }
+    }
+
+    fn device_features(&self) -> u64 {
+        self.device
+            .as_ref()
+            .map_or(0, |d| d.features() | VIRTIO_F_VERSION_1)
+    }
+
+    fn queue(&mut self) -> Option<&mut Queue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    /// Set the low or high half of a 64-bit value.
+    fn set_half(value: &mut u64, high: bool, half: u32) {
+        *value = match high {
+            false => (*value & !0xffff_ffff) | half as u64,
+            true => (*value & 0xffff_ffff) | (half as u64) << 32,
+        };
+    }
+
+    fn read_register(&mut self, offset: u64) -> u32 {
+        // An empty slot only identifies itself
+        if self.device.is_none() && offset > DEVICE_ID {
+            return 0;
+        }
+        match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION => TRANSPORT_VERSION,
+            DEVICE_ID => self.device.as_ref().map_or(0, |d| d.device_id()),
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.device_features() as u32,
+                1 => (self.device_features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX => match self.queue() {
+                Some(_) => QUEUE_SIZE_MAX as u32,
+                //& reading from QueueNumMax returns zero when the queue is not available
+                None => 0,
+            },
+            QUEUE_READY => self.queue().map_or(0, |q| q.ready as u32),
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            // The configuration doesn't change under the driver
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: u64, value: u32) {
+        if self.device.is_none() {
+            return;
+        }
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = value,
+            DRIVER_FEATURES => match self.driver_features_sel {
+                0 => Self::set_half(&mut self.driver_features, false, value),
+                1 => Self::set_half(&mut self.driver_features, true, value),
+                _ => {}
+            },
+            DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+            QUEUE_SEL => self.queue_sel = value,
+            QUEUE_NUM => {
+                if let Some(queue) = self.queue() {
+                    queue.num = (value as u16).min(QUEUE_SIZE_MAX);
+                }
+            }
+            QUEUE_READY => {
+                if let Some(queue) = self.queue() {
+                    queue.ready = value & 1 != 0;
+                }
+            }
+            QUEUE_NOTIFY => {
+                if let Some(queue) = self.queues.get_mut(value as usize) {
+                    queue.notified = true;
+                }
+            }
+            INTERRUPT_ACK => self.interrupt_status &= !value,
+            STATUS => self.write_status(value),
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.desc, offset == QUEUE_DESC_HIGH, value);
+                }
+            }
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.driver, offset == QUEUE_DRIVER_HIGH, value);
+                }
+            }
+            QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.device, offset == QUEUE_DEVICE_HIGH, value);
+                }
+            }
+            // Read-only
+            _ => {}
+        }
+    }
+
+    fn write_status(&mut self, value: u32) {
+        //& Writing zero to Status triggers a device reset.
+        if value == 0 {
+            self.reset();
+            return;
+        }
+        let mut value = value;
+        //& The device MUST NOT offer a feature which requires another feature which was not offered,
+        //& and the driver MUST NOT accept a feature which the device did not offer.
+        let accepted = self.driver_features & !self.device_features() == 0
+            && self.driver_features & VIRTIO_F_VERSION_1 != 0;
+        if value & STATUS_FEATURES_OK != 0 && !accepted {
+            value &= !STATUS_FEATURES_OK;
+        }
+        self.status = value;
+    }
+
+    /// Serve the new entries of the available ring of the queue.
+    fn process_queue(&mut self, index: usize, dma: &mut Dma) -> Result<(), DmaError> {
+        let Some(device) = self.device.as_mut() else {
+            return Ok(());
+        };
+        let queue = &mut self.queues[index];
+        if !queue.ready || queue.num == 0 {
+            return Ok(());
+        }
+        let mut idx = [0; 2];
+        // flags, then idx
+        dma.read(queue.driver + 2, &mut idx)?;
+        let avail_idx = u16::from_le_bytes(idx);
+        while queue.last_avail != avail_idx {
+            let mut head = [0; 2];
+            let slot = (queue.last_avail % queue.num) as u64;
+            dma.read(queue.driver + 4 + 2 * slot, &mut head)?;
+            let head = u16::from_le_bytes(head);
+            let chain = queue.chain(dma, head)?;
+            let written = device.process(index, &chain, dma)?;
+
+            // The used element is written before the index that publishes it
+            let slot = (queue.used_idx % queue.num) as u64;
+            let mut elem = [0; 8];
+            elem[0..4].copy_from_slice(&(head as u32).to_le_bytes());
+            elem[4..8].copy_from_slice(&written.to_le_bytes());
+            dma.write(queue.device + 4 + 8 * slot, &elem)?;
+            queue.used_idx = queue.used_idx.wrapping_add(1);
+            dma.write(queue.device + 2, &queue.used_idx.to_le_bytes())?;
+
+            queue.last_avail = queue.last_avail.wrapping_add(1);
+            self.interrupt_status |= INTERRUPT_USED_BUFFER;
+        }
+        Ok(())
+    }
+}
+
+impl Device for VirtioMmio {
+    fn read(&mut self, offset: u64, size: Size) -> Result<u64, Exception> {
+        if offset >= CONFIG {
+            let config = self.device.as_ref().map_or(Vec::new(), |d| d.config());
+            let start = (offset - CONFIG) as usize;
+            // Beyond the configuration space reads as zero
+            let value = (0..size as usize).fold(0, |value, i| {
+                let byte = config.get(start + i).copied().unwrap_or(0);
+                value | (byte as u64) << (8 * i)
+            });
+            return Ok(value);
+        }
+        match size {
+            Size::WORD if offset % 4 == 0 => Ok(self.read_register(offset) as u64),
+            _ => Err(Exception::LoadAccessFault(offset)),
+        }
+    }
+
+    fn write(&mut self, offset: u64, size: Size, value: u64) -> Result<(), Exception> {
+        // The configuration space is read-only, no writable field being offered
+        if offset >= CONFIG {
+            return Ok(());
+        }
+        match size {
+            Size::WORD if offset % 4 == 0 => {
+                self.write_register(offset, value as u32);
+                Ok(())
+            }
+            _ => Err(Exception::StoreAccessFault(offset)),
+        }
+    }
+
+    // The queues and the features negotiated are forgotten, the backing of the device is kept
+    fn reset(&mut self) {
+        *self = Self::new(self.device.take());
+    }
+
+    fn tick(&mut self, dma: &mut Dma) -> Option<DeviceEvent> {
+        for index in 0..self.queues.len() {
+            if !self.queues[index].notified {
+                continue;
+            }
+            self.queues[index].notified = false;
+            if self.process_queue(index, dma).is_err() {
+                //& The device SHOULD set DEVICE_NEEDS_RESET when it enters an error state that a reset is needed.
+                self.status |= STATUS_DEVICE_NEEDS_RESET;
+                self.interrupt_status |= INTERRUPT_CONFIG_CHANGE;
+            }
+        }
+        None
+    }
+
+    fn is_interrupting(&self) -> bool {
+        self.interrupt_status != 0
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::{fd::AsRawFd, unix::fs::FileExt},
+};
+
+use crate::{
+    components::{
+        devices::virtio::{Chain, VirtioDevice},
+        dma::{Dma, DmaError},
+    },
+    config::DiskConfig,
+};
+
+// https://docs.oasis-open.org/virtio/virtio/v1.2/csd01/virtio-v1.2-csd01.html#x1-2740002
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+/* Feature bits */
+const VIRTIO_BLK_F_SIZE_MAX: u64 = 1 << 1;
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_BLK_F_DISCARD: u64 = 1 << 13;
+
+/* Request types */
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
+/* Request status */
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// Unit of the sectors of the requests and of the capacity, whatever the block size
+const SECTOR_SIZE: u64 = 512;
+/// type, reserved, sector
+const HEADER_SIZE: usize = 16;
+/// sector, num_sectors, flags
+const SEGMENT_SIZE: usize = 16;
+/// Bytes of a data buffer at most
+const SIZE_MAX: u32 = 0x1_0000;
+/// Data buffers of a request at most, the header and the status taking the other descriptors of the queue
+const SEG_MAX: u32 = 126;
+/// Data of a request at most, longer ones failing without being buffered
+const MAX_DATA_SIZE: usize = SIZE_MAX as usize * SEG_MAX as usize;
+/// Length of the identifier returned by GET_ID
+const ID_SIZE: usize = 20;
+/// Sectors of a discard at most, also bounding the zeroes written when holes can't be punched
+const MAX_DISCARD_SECTORS: u32 = 0x2000;
+const MAX_DISCARD_SEG: u32 = 1;
+/// Size of the configuration space, up to the discard fields
+const CONFIG_SIZE: usize = 48;
+
+/// Block device backed by a raw image of the host, one sector of the guest being 512 bytes of the file.
+/// The queue 0 is the request queue.
+pub struct VirtioBlk {
+    file: File,
+    read_only: bool,
+    /// Logical block size advertised to the driver
+    block_size: u32,
+    /// In sectors
+    capacity: u64,
+    id: [u8; ID_SIZE],
+}
+
+impl VirtioBlk {
+    pub fn new(config: &DiskConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!config.read_only)
+            .open(&config.path)?;
+        let capacity = file.metadata()?.len() / SECTOR_SIZE;
+        // The file name as serial number, truncated
+        let mut id = [0; ID_SIZE];
+        let name = config
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .as_encoded_bytes();
+        let len = name.len().min(ID_SIZE);
+        id[..len].copy_from_slice(&name[..len]);
+        Ok(Self {
+            file,
+            read_only: config.read_only,
+            block_size: config.sector_size,
+            capacity,
+            id,
+        })
+    }
+
+    /// Offset in the file of `len` bytes from the sector, when they are all within the disk.
+    fn offset(&self, sector: u64, len: usize) -> Option<u64> {
+        let end = sector.checked_add((len as u64).div_ceil(SECTOR_SIZE))?;
+        (end <= self.capacity).then_some(sector * SECTOR_SIZE)
+    }
+
+    fn read(&self, sector: u64, bytes: &mut [u8]) -> u8 {
+        match self.offset(sector, bytes.len()) {
+            Some(offset) if self.file.read_exact_at(bytes, offset).is_ok() => VIRTIO_BLK_S_OK,
+            _ => VIRTIO_BLK_S_IOERR,
+        }
+    }
+
+    //& A driver MUST NOT submit a request which would cause a write to a read-only device,
+    //& the device MUST fail it with VIRTIO_BLK_S_IOERR.
+    fn write(&self, sector: u64, bytes: &[u8]) -> u8 {
+        match self.offset(sector, bytes.len()) {
+            Some(offset) if !self.read_only && self.file.write_all_at(bytes, offset).is_ok() => {
+                VIRTIO_BLK_S_OK
+            }
+            _ => VIRTIO_BLK_S_IOERR,
+        }
+    }
+
+    fn flush(&self) -> u8 {
+        match self.file.sync_data() {
+            Ok(()) => VIRTIO_BLK_S_OK,
+            Err(_) => VIRTIO_BLK_S_IOERR,
+        }
+    }
+
+    /// Punch holes in the image over the segments, writing zeroes where the host can't.
+    fn discard(&self, segments: &[u8]) -> u8 {
+        if self.read_only || segments.len() % SEGMENT_SIZE != 0 {
+            return VIRTIO_BLK_S_IOERR;
+        }
+        for segment in segments.chunks(SEGMENT_SIZE) {
+            let sector = u64::from_le_bytes(segment[0..8].try_into().unwrap());
+            let sectors = u32::from_le_bytes(segment[8..12].try_into().unwrap());
+            let len = sectors as usize * SECTOR_SIZE as usize;
+            let Some(offset) = self.offset(sector, len) else {
+                return VIRTIO_BLK_S_IOERR;
+            };
+            if sectors > MAX_DISCARD_SECTORS {
+                return VIRTIO_BLK_S_IOERR;
+            }
+            // Safety: a valid descriptor of the open image, the range being within it
+            let punched = unsafe {
+                libc::fallocate(
+                    self.file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if punched != 0 && self.file.write_all_at(&vec![0; len], offset).is_err() {
+                return VIRTIO_BLK_S_IOERR;
+            }
+        }
+        VIRTIO_BLK_S_OK
+    }
+}
+
+impl VirtioDevice for VirtioBlk {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        let access = match self.read_only {
+            true => VIRTIO_BLK_F_RO,
+            false => VIRTIO_BLK_F_DISCARD,
+        };
+        VIRTIO_BLK_F_SIZE_MAX
+            | VIRTIO_BLK_F_SEG_MAX
+            | VIRTIO_BLK_F_BLK_SIZE
+            | VIRTIO_BLK_F_FLUSH
+            | access
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    fn config(&self) -> Vec<u8> {
+        let mut config = vec![0; CONFIG_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            config[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &self.capacity.to_le_bytes());
+        put(8, &SIZE_MAX.to_le_bytes());
+        put(12, &SEG_MAX.to_le_bytes());
+        put(20, &self.block_size.to_le_bytes());
+        put(36, &MAX_DISCARD_SECTORS.to_le_bytes());
+        put(40, &MAX_DISCARD_SEG.to_le_bytes());
+        // In sectors
+        put(44, &(self.block_size / SECTOR_SIZE as u32).to_le_bytes());
+        config
+    }
+
+    // The status is the last byte the device can write, the data coming before it
+    fn process(&mut self, _queue: usize, chain: &Chain, dma: &mut Dma) -> Result<u32, DmaError> {
+        let writable = chain.writable_len();
+        if writable == 0 {
+            return Ok(0);
+        }
+        // A request beyond the limits advertised fails with only its status written
+        let request = match chain.read(dma, HEADER_SIZE + MAX_DATA_SIZE)? {
+            Some(request) if writable - 1 <= MAX_DATA_SIZE => request,
+            _ => return chain.write(dma, writable - 1, &[VIRTIO_BLK_S_IOERR]),
+        };
+        let mut response = vec![0; writable - 1];
+        let status = match request.get(..HEADER_SIZE) {
+            None => VIRTIO_BLK_S_IOERR,
+            Some(header) => {
+                let kind = u32::from_le_bytes(header[0..4].try_into().unwrap());
+                let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+                let data = &request[HEADER_SIZE..];
+                match kind {
+                    VIRTIO_BLK_T_IN => self.read(sector, &mut response),
+                    VIRTIO_BLK_T_OUT => self.write(sector, data),
+                    VIRTIO_BLK_T_FLUSH => self.flush(),
+                    VIRTIO_BLK_T_GET_ID => {
+                        let len = response.len().min(ID_SIZE);
+                        response[..len].copy_from_slice(&self.id[..len]);
+                        VIRTIO_BLK_S_OK
+                    }
+                    VIRTIO_BLK_T_DISCARD => self.discard(data),
+                    _ => VIRTIO_BLK_S_UNSUPP,
+                }
+            }
+        };
+        response.push(status);
+        chain.write(dma, 0, &response)
+    }
+}
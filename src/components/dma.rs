@@ -0,0 +1,190 @@
+use std::{fmt, iter, mem};
+
+use crate::components::{
+    aia::Aia,
+    devices::{Device, dram::Dram},
+    mmu::Size,
+};
+
+/// Granule of the IOMMU translations, a transfer is translated a page at a time.
+const PAGE_SIZE: u64 = 4096;
+
+/// Failure of a DMA transfer, reported to the device that requested it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DmaError {
+    /// The physical address isn't in main memory.
+    AccessFault(u64),
+    /// The IOMMU rejected the device address.
+    TranslationFault(u64),
+}
+
+impl fmt::Display for DmaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmaError::AccessFault(address) => write!(f, "DMA access fault at {address:#x}"),
+            DmaError::TranslationFault(address) => {
+                write!(f, "DMA translation fault at {address:#x}")
+            }
+        }
+    }
+}
+
+/// The physical memory reachable by the bus-mastering devices.
+pub struct Memory<'a> {
+    dram: &'a mut Dram,
+    base: u64,
+    /// Receives the message-signaled interrupts of the devices
+    aia: Option<&'a mut Aia>,
+}
+
+impl<'a> Memory<'a> {
+    pub fn new(dram: &'a mut Dram, base: u64, aia: Option<&'a mut Aia>) -> Self {
+        Self { dram, base, aia }
+    }
+
+    /// Offset within the RAM of the range, which must be entirely in main memory.
+    fn offset(&self, address: u64, len: usize) -> Result<u64, DmaError> {
+        let offset = address.wrapping_sub(self.base);
+        match offset
+            .checked_add(len as u64)
+            .is_some_and(|end| address >= self.base && end <= self.dram.size())
+        {
+            true => Ok(offset),
+            false => Err(DmaError::AccessFault(address)),
+        }
+    }
+
+    pub fn read(&self, address: u64, bytes: &mut [u8]) -> Result<(), DmaError> {
+        let offset = self.offset(address, bytes.len())?;
+        self.dram.read_bytes(offset, bytes);
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<(), DmaError> {
+        // An MSI is a 32-bit write to the interrupt file of an IMSIC
+        if let (Ok(word), Some(aia)) = (<[u8; 4]>::try_from(bytes), self.aia.as_deref_mut()) {
+            let value = u32::from_le_bytes(word) as u64;
+            if let Some(result) = aia.write(address, Size::WORD, value) {
+                return result.map_err(|_| DmaError::AccessFault(address));
+            }
+        }
+        let offset = self.offset(address, bytes.len())?;
+        self.dram.write_bytes(offset, bytes);
+        Ok(())
+    }
+
+    pub fn read_u64(&self, address: u64) -> Result<u64, DmaError> {
+        let mut bytes = [0; 8];
+        self.read(address, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn write_u64(&mut self, address: u64, value: u64) -> Result<(), DmaError> {
+        self.write(address, &value.to_le_bytes())
+    }
+}
+
+/// Translation and protection of the addresses issued by the devices, placed between them and the memory.
+/// It is registered on the bus and programmed through its registers as any other device.
+pub trait Iommu: Device {
+    /// Translate the device address of the `device_id` to a physical one,
+    /// with `memory` holding the translation structures.
+    fn translate(
+        &mut self,
+        device_id: u32,
+        iova: u64,
+        write: bool,
+        memory: &mut Memory,
+    ) -> Result<u64, DmaError>;
+}
+
+/// Access to the memory on behalf of a device, through the IOMMU when there is one.
+pub struct Dma<'a> {
+    memory: Memory<'a>,
+    iommu: Option<&'a mut dyn Iommu>,
+    /// Requester id of the device, none for a device that isn't a bus master
+    device_id: Option<u32>,
+    /// Physical ranges outside of the RAM the device tried to reach, as (address, len, write)
+    faults: Vec<(u64, usize, bool)>,
+}
+
+impl<'a> Dma<'a> {
+    pub fn new(
+        memory: Memory<'a>,
+        iommu: Option<&'a mut dyn Iommu>,
+        device_id: Option<u32>,
+    ) -> Self {
+        Self {
+            memory,
+            iommu,
+            device_id,
+            faults: Vec::new(),
+        }
+    }
+
+    /// The accesses that faulted outside of the RAM since the last call.
+    pub fn take_faults(&mut self) -> Vec<(u64, usize, bool)> {
+        mem::take(&mut self.faults)
+    }
+
+    /// The memory as seen by the device, without translation.
+    pub fn memory(&mut self) -> &mut Memory<'a> {
+        &mut self.memory
+    }
+
+    /// The physical address of the device address.
+    /// Behind an IOMMU, a device without a requester id can't reach the memory.
+    fn translate(&mut self, address: u64, write: bool) -> Result<u64, DmaError> {
+        match (self.iommu.as_deref_mut(), self.device_id) {
+            (Some(iommu), Some(id)) => iommu.translate(id, address, write, &mut self.memory),
+            (Some(_), None) => Err(DmaError::TranslationFault(address)),
+            (None, _) => Ok(address),
+        }
+    }
+
+    /// Read the range from `address`, split at the pages as they may be translated apart.
+    pub fn read(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), DmaError> {
+        let mut address = address;
+        let mut bytes = bytes;
+        for part in split_pages(address, bytes.len()).map(|len| len as usize) {
+            let (head, tail) = mem::take(&mut bytes).split_at_mut(part);
+            let paddr = self.translate(address, false)?;
+            self.memory
+                .read(paddr, head)
+                .inspect_err(|_| self.faults.push((paddr, part, false)))?;
+            address = address.wrapping_add(part as u64);
+            bytes = tail;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u64, bytes: &[u8]) -> Result<(), DmaError> {
+        let mut address = address;
+        let mut bytes = bytes;
+        for part in split_pages(address, bytes.len()).map(|len| len as usize) {
+            let (head, tail) = bytes.split_at(part);
+            let paddr = self.translate(address, true)?;
+            self.memory
+                .write(paddr, head)
+                .inspect_err(|_| self.faults.push((paddr, part, true)))?;
+            address = address.wrapping_add(part as u64);
+            bytes = tail;
+        }
+        Ok(())
+    }
+}
+
+/// Lengths of the parts of the range within each page.
+fn split_pages(address: u64, len: usize) -> impl Iterator<Item = u64> {
+    let mut address = address;
+    let mut left = len as u64;
+    iter::from_fn(move || {
+        if left == 0 {
+            return None;
+        }
+        let part = (PAGE_SIZE - address % PAGE_SIZE).min(left);
+        address = address.wrapping_add(part);
+        left -= part;
+        Some(part)
+    })
+}
@@ -1,12 +1,15 @@
-use arbitrary_int::{u1, u2, u9, u12, u26, u39, u44, u56};
+use arbitrary_int::{u1, u2, u7, u9, u26, u44};
 use bitbybit::bitfield;
 
 use crate::{
     components::{
-        csr::{MStatus, Sapt},
+        csr::{MStatus, SATP_MODE_BARE, Sapt},
+        pma::AmoClass,
         system_bus::SystemBus,
+        tlb::{Tlb, TlbEntry},
         trap::Exception,
     },
+    config::{Config, MisalignedPolicy},
     cpu::PrivilegeMode,
 };
 
@@ -32,7 +35,7 @@ impl Size {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub enum MemoryAccessType {
     ///X
     Instruction,
@@ -50,34 +53,22 @@ impl MemoryAccessType {
             MemoryAccessType::Store => return Exception::StorePageFault(addr),
         }
     }
-}
-
-///Sv39 virtual address
-#[bitfield(u39)]
-pub struct Sv39va {
-    ///Virtual Page Number
-    #[bits(12..=20, rw)]
-    vpn: [u9; 3],
-    //Offset within the page
-    #[bits(0..=11, r)]
-    offset: u12,
-}
-
-///Sv39 physical address
-#[bitfield(u56)]
-pub struct Sv39pa {
-    ///Physical Page Number[2]
-    #[bits(30..=55, rw)]
-    ppn2: u26,
-    ///Physical Page Number[1]
-    #[bits(21..=29, rw)]
-    ppn1: u9,
-    ///Physical Page Number[0]
-    #[bits(12..=20, rw)]
-    ppn0: u9,
-    //Offset within the page
-    #[bits(0..=11, rw)]
-    offset: u12,
+    ///Return coresponding address-misaligned exception
+    pub fn misaligned(&self, addr: u64) -> Exception {
+        match self {
+            MemoryAccessType::Instruction => Exception::InstructionAddressMisaligned(addr),
+            MemoryAccessType::Load => Exception::LoadAddressMisaligned(addr),
+            MemoryAccessType::Store => Exception::StoreAddressMisaligned(addr),
+        }
+    }
+    ///Return coresponding access-fault exception
+    pub fn access_fault(&self, addr: u64) -> Exception {
+        match self {
+            MemoryAccessType::Instruction => Exception::InstructionAccessFault(addr),
+            MemoryAccessType::Load => Exception::LoadAccessFault(addr),
+            MemoryAccessType::Store => Exception::StoreAccessFault(addr),
+        }
+    }
 }
 
 ///Sv39 page table entry
@@ -87,6 +78,8 @@ pub struct Sv39pte {
     n: u1,
     #[bits(61..=62, r)]
     pbmt: u2,
+    #[bits(54..=60, r)]
+    reserved: u7,
     ///Physical Page Number[2]
     #[bits(28..=53, r)]
     ppn2: u26,
@@ -127,36 +120,172 @@ pub struct Sv39pte {
     v: u1,
 }
 
+/// Failure of a page-table walk, turned into the exception of the original access by the caller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WalkFault {
+    Page,
+    Access,
+    /// The guest-physical address of a PTE has no second-stage translation.
+    GuestPage(u64),
+}
+
+/// Memory holding the page tables, as seen by a walker.
+pub trait PageTables {
+    fn read_pte(&mut self, address: u64) -> Result<u64, WalkFault>;
+    fn write_pte(&mut self, address: u64, value: u64) -> Result<(), WalkFault>;
+}
+
+/// The page tables of the hart, reached through the system bus.
+struct HartPageTables<'a> {
+    bus: &'a mut SystemBus,
+    big_endian: bool,
+}
+
+impl PageTables for HartPageTables<'_> {
+    fn read_pte(&mut self, address: u64) -> Result<u64, WalkFault> {
+        let value = self
+            .bus
+            .read(address, Size::DWORD)
+            .map_err(|_| WalkFault::Access)?;
+        Ok(match self.big_endian {
+            true => value.swap_bytes(),
+            false => value,
+        })
+    }
+
+    fn write_pte(&mut self, address: u64, value: u64) -> Result<(), WalkFault> {
+        let value = match self.big_endian {
+            true => value.swap_bytes(),
+            false => value,
+        };
+        self.bus
+            .write(address, Size::DWORD, value)
+            .map_err(|_| WalkFault::Access)
+    }
+}
+
+/// Leaf PTE reached by a walk.
+#[derive(Clone, Copy)]
+pub struct Leaf {
+    pub pte: Sv39pte,
+    /// A superpage when greater than 0
+    pub level: u8,
+    /// Address of the PTE, for the update of A and D
+    pub address: u64,
+}
+
+impl Leaf {
+    /// The physical address of `vaddr`, within the page of the leaf.
+    //& If i>0, then this is a superpage translation and pa.ppn[i-1:0] = va.vpn[i-1:0].
+    pub fn physical(&self, vaddr: u64) -> u64 {
+        let offset = vaddr & ((1 << (12 + 9 * self.level as u64)) - 1);
+        (self.pte.ppn().value() << 12) | offset
+    }
+}
+
+/// Steps 2 to 5 of the translation process, from the root table at `root` down to the leaf PTE of `vaddr`.
+/// With `widened`, the root table is the 16KiB one of the Sv39x4 guest-physical addresses, indexed by 11 bits.
+pub fn walk_sv39(
+    tables: &mut impl PageTables,
+    root: u64,
+    vaddr: u64,
+    widened: bool,
+) -> Result<Leaf, WalkFault> {
+    let mut a = root;
+    let mut i: i8 = (LEVELS - 1) as i8;
+    loop {
+        // 2. Let pte be the value of the PTE at address a+va.vpn[i]×PTESIZE.
+        let vpn_mask = if widened && i == 2 { 0x7ff } else { 0x1ff };
+        let vpn = (vaddr >> (12 + 9 * i as u64)) & vpn_mask;
+        let address = a + vpn * PTESIZE;
+        // If accessing pte violates a PMA or PMP check, raise an access-fault exception corresponding to the original access type.
+        let pte = Sv39pte::new_with_raw_value(tables.read_pte(address)?);
+        // 3. If pte.v=0, or if pte.r=0 and pte.w=1,
+        // or if any bits or encodings that are reserved for future standard use are set within pte,
+        // which are also those of the Svnapot and Svpbmt extensions, as they aren't implemented.
+        if pte.v() == u1::new(0)
+            || (pte.r() == u1::new(0) && pte.w() == u1::new(1))
+            || pte.reserved().value() != 0
+            || pte.pbmt().value() != 0
+            || pte.n() == u1::new(1)
+        {
+            // stop and raise a page-fault exception corresponding to the original access type.
+            return Err(WalkFault::Page);
+        }
+        // 4. Otherwise, the PTE is valid.
+        // If pte.r=1 or pte.x=1, go to step 5.
+        if pte.r() == u1::new(1) || pte.x() == u1::new(1) {
+            // 5. A leaf PTE has been reached.
+            let ppn = [pte.ppn0().value() as u32, pte.ppn1().value() as u32];
+            // If i>0 and pte.ppn[i-1:0] ≠ 0, this is a misaligned superpage;
+            if ppn[..i as usize].iter().any(|&p| p != 0) {
+                // stop and raise a page-fault exception corresponding to the original access type.
+                return Err(WalkFault::Page);
+            }
+            return Ok(Leaf {
+                pte,
+                level: i as u8,
+                address,
+            });
+        }
+        // Otherwise, this PTE is a pointer to the next level of the page table.
+        // Let i=i-1.
+        i -= 1;
+        // If i<0, stop and raise a page-fault exception corresponding to the original access type.
+        if i < 0 {
+            return Err(WalkFault::Page);
+        }
+        // Otherwise, let a=pte.ppn×PAGESIZE and go to step 2.
+        a = pte.ppn().value() * PAGESIZE;
+    }
+}
+
+/// Reverse the byte order of a value of the given size.
+fn swap_bytes(value: u64, size: Size) -> u64 {
+    match size {
+        Size::BYTE => value,
+        Size::HWORD => (value as u16).swap_bytes() as u64,
+        Size::WORD => (value as u32).swap_bytes() as u64,
+        Size::DWORD => value.swap_bytes(),
+    }
+}
+
 pub struct Mmu {
     pub bus: SystemBus,
     //Use raw pointers for now, as self-referencing is a pita
     mstatus: *const u64,
     sapt: *const u64,
     p_mode: *const PrivilegeMode,
+    misaligned: MisalignedPolicy,
+    /// Data TLB, also caching the instruction fetches when unified
+    dtlb: Tlb,
+    itlb: Option<Tlb>,
 }
 
 impl Mmu {
-    pub fn new(mstatus: *const u64, sapt: *const u64, p_mode: *const PrivilegeMode) -> Self {
-        Self {
+    pub fn new(
+        mstatus: *const u64,
+        sapt: *const u64,
+        p_mode: *const PrivilegeMode,
+        config: &Config,
+    ) -> Result<Self, String> {
+        Ok(Self {
             //Fill this with NOPs, which is 0x13 on riscv
-            bus: SystemBus::new(),
+            bus: SystemBus::new(config)?,
             mstatus,
             sapt,
             p_mode,
-        }
+            misaligned: config.misaligned,
+            dtlb: Tlb::new(config.tlb.sets, config.tlb.ways),
+            itlb: config
+                .tlb
+                .split
+                .then(|| Tlb::new(config.tlb.sets, config.tlb.ways)),
+        })
     }
 
-    //TODO: caching of addresses?
     //12.3.2. Virtual Address Translation Process
     pub fn translate(&mut self, vaddr: u64, access: MemoryAccessType) -> Result<u64, Exception> {
-        // 12.1.11. Supervisor Address Translation and Protection
-        // The satp CSR is considered active when the effective privilege mode is S-mode or U-mode.
-        // Executions of the address-translation algorithm may only begin using a given value of satp when satp is active.
-        let p_mode = unsafe { *self.p_mode };
-        if p_mode == PrivilegeMode::Machine {
-            return Ok(vaddr);
-        }
-
         let satp = unsafe { Sapt::new_with_raw_value(*self.sapt) };
         //3.1.6.4. Memory Privilege in mstatus Register
         let mstatus = unsafe { MStatus::new_with_raw_value(*self.mstatus) };
@@ -164,73 +293,133 @@ impl Mmu {
         let p_mode = if mstatus.mprv() == u1::new(1) && access != MemoryAccessType::Instruction {
             mstatus.mpp()
         } else {
-            p_mode
+            unsafe { *self.p_mode }
         };
 
-        // 1. Let a be satp.ppn×PAGESIZE, and let i=LEVELS-1.
-        // The satp register must be active, i.e., the effective privilege mode must be S-mode or U-mode.
-        let mut a: u64 = satp.ppn().value() * PAGESIZE;
-        let mut i: i8 = (LEVELS - 1) as i8;
-        let mut pte;
-        let mut pte_address;
-        let va = Sv39va::new_with_raw_value(u39::new(vaddr));
-        loop {
-            // 2. Let pte be the value of the PTE at address a+va.vpn[i]×PTESIZE.
-            pte_address = a + (va.vpn(i as usize).value() as u64) * PTESIZE;
-            // If accessing pte violates a PMA or PMP check, raise an access-fault exception corresponding to the original access type.
-            let pte_value = self.bus.read(pte_address, Size::DWORD)?;
-            pte = Sv39pte::new_with_raw_value(pte_value);
-            // 3. If pte.v=0, or if pte.r=0 and pte.w=1,
-            //? or if any bits or encodings that are reserved for future standard use are set within pte,
-            if pte.v() == u1::new(0) || (pte.r() == u1::new(0) && pte.w() == u1::new(1)) {
-                // stop and raise a page-fault exception corresponding to the original access type.
-                return Err(access.page_fault(pte_address));
+        // 12.1.11. Supervisor Address Translation and Protection
+        // The satp CSR is considered active when the effective privilege mode is S-mode or U-mode.
+        // Executions of the address-translation algorithm may only begin using a given value of satp when satp is active.
+        //& When MODE=Bare, supervisor virtual addresses are equal to supervisor physical addresses.
+        if p_mode == PrivilegeMode::Machine || satp.mode().value() == SATP_MODE_BARE {
+            return Ok(vaddr);
+        }
+
+        //& Instruction fetch addresses and load and store effective addresses, which are 64 bits,
+        //& must have bits 63–39 all equal to bit 38, or else a page-fault exception will occur.
+        if ((vaddr as i64) << 25 >> 25) as u64 != vaddr {
+            return Err(access.page_fault(vaddr));
+        }
+
+        let asid = satp.asid();
+        match self.tlb(access).lookup(vaddr, asid) {
+            // A store to a page not yet dirty walks the table again, to set pte.d
+            Some(entry) if access != MemoryAccessType::Store || entry.pte.d() == u1::new(1) => {
+                Self::check_permissions(entry.pte, p_mode, mstatus, access, vaddr)?;
+                Ok(entry.physical(vaddr))
             }
-            // 4. Otherwise, the PTE is valid.
-            // If pte.r=1 or pte.x=1, go to step 5.
-            if pte.r() == u1::new(1) || pte.x() == u1::new(1) {
-                break;
-            };
-            // Otherwise, this PTE is a pointer to the next level of the page table.
-            // Let i=i-1.
-            i = i - 1;
-            // If i<0, stop and raise a page-fault exception corresponding to the original access type.
-            if i < 0 {
-                return Err(access.page_fault(pte_address));
+            _ => {
+                let entry = self.walk(vaddr, access, satp, p_mode, mstatus)?;
+                self.tlb(access).insert(entry);
+                Ok(entry.physical(vaddr))
             }
-            // Otherwise, let a=pte.ppn×PAGESIZE and go to step 2.
-            a = pte.ppn().value() * PAGESIZE;
         }
-        // 5. A leaf PTE has been reached.
-        let mut ppn = [
-            pte.ppn0().value() as u32,
-            pte.ppn1().value() as u32,
-            pte.ppn2().value() as u32,
-        ];
-        // If i>0 and pte.ppn[i-1:0] ≠ 0, this is a misaligned superpage;
-        if i > 0 {
-            for j in (i - 1)..=0 {
-                if ppn[j as usize] == 0 {
-                    continue;
-                }
-                // stop and raise a page-fault exception corresponding to the original access type.
-                return Err(access.page_fault(pte_address));
+    }
+
+    /// The TLB caching the translations of the access type.
+    #[inline(always)]
+    fn tlb(&mut self, access: MemoryAccessType) -> &mut Tlb {
+        match (access, self.itlb.as_mut()) {
+            (MemoryAccessType::Instruction, Some(itlb)) => itlb,
+            _ => &mut self.dtlb,
+        }
+    }
+
+    /// Invalidate the cached translations, as by an SFENCE.VMA with the given operands.
+    pub fn flush_tlb(&mut self, vaddr: Option<u64>, asid: Option<u16>) {
+        self.dtlb.flush(vaddr, asid);
+        if let Some(itlb) = self.itlb.as_mut() {
+            itlb.flush(vaddr, asid);
+        }
+    }
+
+    /// Walk the page table, returning the leaf PTE reached with its A and D bits updated.
+    fn walk(
+        &mut self,
+        vaddr: u64,
+        access: MemoryAccessType,
+        satp: Sapt,
+        p_mode: PrivilegeMode,
+        mstatus: MStatus,
+    ) -> Result<TlbEntry, Exception> {
+        // 1. Let a be satp.ppn×PAGESIZE, and let i=LEVELS-1.
+        // The satp register must be active, i.e., the effective privilege mode must be S-mode or U-mode.
+        let big_endian = self.is_pt_big_endian();
+        let mut tables = HartPageTables {
+            bus: &mut self.bus,
+            big_endian,
+        };
+        let fault = |fault| match fault {
+            WalkFault::Access => access.access_fault(vaddr),
+            _ => access.page_fault(vaddr),
+        };
+        let leaf =
+            walk_sv39(&mut tables, satp.ppn().value() * PAGESIZE, vaddr, false).map_err(fault)?;
+        let mut pte = leaf.pte;
+        // 6.-8. are performed again on each use of the cached translation
+        Self::check_permissions(pte, p_mode, mstatus, access, vaddr)?;
+
+        // 9. If pte.a=0, or if the original memory access is a store and pte.d=0:
+        if pte.a() == u1::new(0) || (access == MemoryAccessType::Store && pte.d() == u1::new(0)) {
+            // If the Svade extension is implemented, stop and raise a page-fault exception corresponding to the original access type.
+            //? ---Not yet.
+
+            /*  ---This part is skipped as this implementation uses only 1 hart. */
+            // Perform the following steps atomically:
+            // *Compare pte to the value of the PTE at address a+va.vpn[i]×PTESIZE.
+            // *If the comparison fails, return to step 2.
+            //
+            // *If the values match, set pte.a to 1 and, if the original memory access is a store, also set pte.d to 1.
+            pte.set_a(u1::new(1));
+            if access == MemoryAccessType::Store && pte.d() == u1::new(0) {
+                pte.set_d(u1::new(1));
             }
+            // If a store to pte would violate a PMA or PMP check, raise an access-fault exception corresponding to the original access type.
+            tables
+                .write_pte(leaf.address, pte.raw_value())
+                .map_err(fault)?;
         }
+
+        // 10. The translation is successful.
+        Ok(TlbEntry {
+            vpn: vaddr >> (12 + 9 * leaf.level as u64),
+            level: leaf.level,
+            asid: satp.asid(),
+            pte,
+        })
+    }
+
+    /// Determine if the access is allowed by the leaf PTE.
+    fn check_permissions(
+        pte: Sv39pte,
+        p_mode: PrivilegeMode,
+        mstatus: MStatus,
+        access: MemoryAccessType,
+        vaddr: u64,
+    ) -> Result<(), Exception> {
         // 6. Determine if the requested memory access is allowed by the pte.u bit,
         // given the current privilege mode and the value of the SUM and MXR fields of the mstatus register.
         match p_mode {
             PrivilegeMode::User => {
                 if pte.u() == u1::new(0) {
                     // If not, stop and raise a page-fault exception corresponding to the original access type.
-                    return Err(access.page_fault(pte_address));
+                    return Err(access.page_fault(vaddr));
                 }
             }
             PrivilegeMode::Supervisor => {
                 if pte.u() == u1::new(1) {
                     //When SUM=1, load and store access are permitted for S-mode on U pages.
                     if mstatus.sum() == u1::new(0) || access == MemoryAccessType::Instruction {
-                        return Err(access.page_fault(pte_address));
+                        return Err(access.page_fault(vaddr));
                     }
                 }
             }
@@ -249,79 +438,192 @@ impl Mmu {
                     // When MXR=1, allow load on X pages.
                     && (mstatus.mxr() == u1::new(0) || pte.x() == u1::new(0))
                 {
-                    return Err(Exception::LoadPageFault(pte_address));
+                    return Err(Exception::LoadPageFault(vaddr));
                 }
             }
             MemoryAccessType::Store => {
                 if pte.w() == u1::new(0) {
-                    return Err(Exception::StorePageFault(pte_address));
+                    return Err(Exception::StorePageFault(vaddr));
                 }
             }
             MemoryAccessType::Instruction => {
                 if pte.x() == u1::new(0) {
-                    return Err(Exception::InstructionPageFault(pte_address));
+                    return Err(Exception::InstructionPageFault(vaddr));
                 }
             }
         };
 
-        // 9. If pte.a=0, or if the original memory access is a store and pte.d=0:
-        if pte.a() == u1::new(0) || (access == MemoryAccessType::Store && pte.d() == u1::new(0)) {
-            // If the Svade extension is implemented, stop and raise a page-fault exception corresponding to the original access type.
-            //? ---Not yet.
+        Ok(())
+    }
 
-            /*  ---This part is skipped as this implementation uses only 1 hart. */
-            // Perform the following steps atomically:
-            // *Compare pte to the value of the PTE at address a+va.vpn[i]×PTESIZE.
-            // *If the comparison fails, return to step 2.
-            //
-            // *If the values match, set pte.a to 1 and, if the original memory access is a store, also set pte.d to 1.
-            pte.set_a(u1::new(1));
-            if access == MemoryAccessType::Store && pte.d() == u1::new(0) {
-                pte.set_d(u1::new(1));
-            }
-            // If a store to pte would violate a PMA or PMP check, raise an access-fault exception corresponding to the original access type.
-            self.bus.write(pte_address, Size::DWORD, pte.raw_value())?;
+    // Access faults report the virtual address, not the physical one of the bus
+    //& Instruction fetches are always little-endian.
+    pub fn fetch(&mut self, vaddr: u64) -> Result<u32, Exception> {
+        let access = MemoryAccessType::Instruction;
+        let paddr = self.translate(vaddr, access)?;
+        let value = self
+            .bus
+            .read(paddr, Size::WORD)
+            .map_err(|_| access.access_fault(vaddr))?;
+        Ok(value as u32)
+    }
+
+    /// Translate the pages spanned by a misaligned access, before any part of it is performed.
+    /// Returns the physical addresses of the parts in the first and second page,
+    /// and the number of bytes in the first one.
+    fn translate_misaligned(
+        &mut self,
+        vaddr: u64,
+        size: Size,
+        access: MemoryAccessType,
+    ) -> Result<(u64, u64, u64), Exception> {
+        //& Loads and stores where the effective address is not naturally aligned to the referenced datatype
+        //& may either be performed or raise an address-misaligned exception.
+        if self.misaligned == MisalignedPolicy::Trap {
+            return Err(access.misaligned(vaddr));
+        }
+        let size = size as u64;
+        let first = (PAGESIZE - vaddr % PAGESIZE).min(size);
+        let low = self.translate(vaddr, access)?;
+        // The fault of the second part reports its own address
+        let high = if first < size {
+            self.translate(vaddr.wrapping_add(first), access)?
+        } else {
+            0
+        };
+        // Only the regions supporting them perform misaligned accesses, such as main memory
+        let performed = |paddr| self.bus.pma(paddr).is_some_and(|pma| pma.misaligned);
+        if !performed(low) || (first < size && !performed(high)) {
+            return Err(access.access_fault(vaddr));
         }
+        Ok((low, high, first))
+    }
 
-        // 10. The translation is successful. The translated physical address is given as follows:
-        let mut pa = Sv39pa::ZERO;
-        // pa.pgoff = va.pgoff.
-        pa.set_offset(va.offset());
-        // If i>0, then this is a superpage translation and pa.ppn[i-1:0] = va.vpn[i-1:0].
-        // pa.ppn[LEVELS-1:i] = pte.ppn[LEVELS-1:i].
-        if i > 0 {
-            //modify pte.ppn
-            for j in (i - 1)..=0 {
-                ppn[j as usize] = va.vpn(j as usize).value() as u32;
-            }
+    /// Misaligned accesses are performed a byte at a time.
+    fn load_misaligned(&mut self, vaddr: u64, size: Size) -> Result<u64, Exception> {
+        let access = MemoryAccessType::Load;
+        let (low, high, first) = self.translate_misaligned(vaddr, size, access)?;
+        let mut value = 0;
+        for i in 0..size as u64 {
+            let paddr = if i < first {
+                low + i
+            } else {
+                high + (i - first)
+            };
+            let byte = self
+                .bus
+                .read(paddr, Size::BYTE)
+                .map_err(|_| access.access_fault(vaddr.wrapping_add(i)))?;
+            value |= byte << (8 * i);
         }
-        pa.set_ppn0(u9::new(ppn[0] as u16));
-        pa.set_ppn1(u9::new(ppn[1] as u16));
-        pa.set_ppn2(u26::new(ppn[2] as u32));
+        Ok(value)
+    }
 
-        Ok(pa.raw_value)
+    fn store_misaligned(&mut self, vaddr: u64, value: u64, size: Size) -> Result<(), Exception> {
+        let access = MemoryAccessType::Store;
+        let (low, high, first) = self.translate_misaligned(vaddr, size, access)?;
+        for i in 0..size as u64 {
+            let paddr = if i < first {
+                low + i
+            } else {
+                high + (i - first)
+            };
+            self.bus
+                .write(paddr, Size::BYTE, (value >> (8 * i)) & 0xff)
+                .map_err(|_| access.access_fault(vaddr.wrapping_add(i)))?;
+        }
+        Ok(())
     }
 
-    pub fn fetch(&mut self, vaddr: u64) -> Result<u32, Exception> {
-        let paddr = self.translate(vaddr, MemoryAccessType::Instruction)?;
-        let value = self.bus.read(paddr, Size::WORD)?;
-        Ok(value as u32)
+    /// Whether the explicit data accesses are big-endian,
+    /// given by the xBE bit of the effective privilege mode.
+    fn is_big_endian(&self) -> bool {
+        let mstatus = unsafe { MStatus::new_with_raw_value(*self.mstatus) };
+        //& When MPRV=1, load and store memory addresses are translated and protected, and endianness is applied,
+        //& as though the current privilege mode were set to MPP.
+        let p_mode = match mstatus.mprv() == u1::new(1) {
+            true => mstatus.mpp(),
+            false => unsafe { *self.p_mode },
+        };
+        let be = match p_mode {
+            PrivilegeMode::Machine => mstatus.mbe(),
+            PrivilegeMode::Supervisor => mstatus.sbe(),
+            _ => mstatus.ube(),
+        };
+        be == u1::new(1)
+    }
+
+    /// Whether the implicit accesses to the page tables are big-endian.
+    fn is_pt_big_endian(&self) -> bool {
+        let mstatus = unsafe { MStatus::new_with_raw_value(*self.mstatus) };
+        //& SBE also controls the endianness of the implicit memory accesses made to supervisor-level memory-management data structures, such as page tables.
+        mstatus.sbe() == u1::new(1)
     }
 
     pub fn load(&mut self, vaddr: u64, size: Size) -> Result<u64, Exception> {
-        let paddr = self.translate(vaddr, MemoryAccessType::Load)?;
-        let value = self.bus.read(paddr, size)?;
-        Ok(value)
+        let value = if vaddr % (size as u64) != 0 {
+            self.load_misaligned(vaddr, size)?
+        } else {
+            let access = MemoryAccessType::Load;
+            let paddr = self.translate(vaddr, access)?;
+            self.bus
+                .read(paddr, size)
+                .map_err(|_| access.access_fault(vaddr))?
+        };
+        // The memory is little-endian
+        match self.is_big_endian() {
+            true => Ok(swap_bytes(value, size)),
+            false => Ok(value),
+        }
     }
 
     pub fn store(&mut self, vaddr: u64, value: u64, size: Size) -> Result<(), Exception> {
-        let paddr = self.translate(vaddr, MemoryAccessType::Store)?;
-        self.bus.write(paddr, size, value)?;
+        let value = match self.is_big_endian() {
+            true => swap_bytes(value, size),
+            false => value,
+        };
+        if vaddr % (size as u64) != 0 {
+            return self.store_misaligned(vaddr, value, size);
+        }
+        let access = MemoryAccessType::Store;
+        let paddr = self.translate(vaddr, access)?;
+        self.bus
+            .write(paddr, size, value)
+            .map_err(|_| access.access_fault(vaddr))?;
         Ok(())
     }
 
+    /// Check that LR/SC can hold a reservation on the region of `vaddr`.
+    pub fn check_reservable(
+        &mut self,
+        vaddr: u64,
+        access: MemoryAccessType,
+    ) -> Result<(), Exception> {
+        let paddr = self.translate(vaddr, access)?;
+        match self.bus.pma(paddr).is_some_and(|pma| pma.reservable) {
+            true => Ok(()),
+            false => Err(access.access_fault(vaddr)),
+        }
+    }
+
+    /// Check that the region of `vaddr` supports the AMOs of the class.
+    pub fn check_amo(&mut self, vaddr: u64, class: AmoClass) -> Result<(), Exception> {
+        let access = MemoryAccessType::Store;
+        let paddr = self.translate(vaddr, access)?;
+        match self.bus.pma(paddr).is_some_and(|pma| pma.amo >= class) {
+            true => Ok(()),
+            false => Err(access.access_fault(vaddr)),
+        }
+    }
+
     /// Inject a binary file in physical memory
     pub fn inject(&mut self, addr: u64, bin: &[u8]) {
         self.bus.inject(addr, bin);
     }
+
+    /// Drop the cached translations and reset the devices.
+    pub fn reset(&mut self) {
+        self.flush_tlb(None, None);
+        self.bus.reset();
+    }
 }
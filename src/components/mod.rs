@@ -1,6 +1,10 @@
+pub mod aia;
 pub mod csr;
 pub mod devices;
+pub mod dma;
 pub mod mmu;
+pub mod pma;
 pub mod registers;
 pub mod system_bus;
+pub mod tlb;
 pub mod trap;
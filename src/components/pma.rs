@@ -0,0 +1,73 @@
+use crate::components::mmu::Size;
+
+/* 3.6. Physical Memory Attributes */
+//& The physical memory map for a complete system includes various address ranges,
+//& some corresponding to memory regions and some to memory-mapped control registers.
+//& Some memory regions might not support reads, writes, or execution;
+//& some might not support subword or subblock accesses; some might not support atomic operations.
+
+/// Support of the atomic memory operations, each class including the previous ones.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum AmoClass {
+    AmoNone,
+    /// Only AMOSWAP.
+    AmoSwap,
+    /// Also AMOAND, AMOOR and AMOXOR.
+    AmoLogical,
+    /// Also AMOADD, AMOMIN, AMOMAX, AMOMINU and AMOMAXU.
+    AmoArithmetic,
+}
+
+/// Attributes of a region of the physical address space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pma {
+    /// Supported access widths, one bit per size in bytes.
+    pub widths: u8,
+    pub amo: AmoClass,
+    /// LR/SC can hold a reservation on the region.
+    pub reservable: bool,
+    /// Reads and writes have no side effects, so they can be repeated.
+    pub idempotent: bool,
+    pub cacheable: bool,
+    /// Misaligned accesses are performed, instead of raising an access fault.
+    pub misaligned: bool,
+}
+
+impl Pma {
+    pub const ALL_WIDTHS: u8 =
+        Size::BYTE as u8 | Size::HWORD as u8 | Size::WORD as u8 | Size::DWORD as u8;
+
+    /// Main memory, supporting every kind of access.
+    pub const MEMORY: Pma = Pma {
+        widths: Self::ALL_WIDTHS,
+        amo: AmoClass::AmoArithmetic,
+        reservable: true,
+        idempotent: true,
+        cacheable: true,
+        misaligned: true,
+    };
+
+    /// Read-only memory, such as the boot ROM.
+    pub const ROM: Pma = Pma {
+        amo: AmoClass::AmoNone,
+        reservable: false,
+        ..Self::MEMORY
+    };
+
+    /// Device registers, accessed only with the given widths.
+    pub const fn io(widths: u8) -> Pma {
+        Pma {
+            widths,
+            amo: AmoClass::AmoNone,
+            reservable: false,
+            idempotent: false,
+            cacheable: false,
+            misaligned: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn supports(&self, size: Size) -> bool {
+        self.widths & size as u8 != 0
+    }
+}
@@ -1,13 +1,34 @@
-use crate::components::{
-    devices::{
-        dram::{DRAM_SIZE, Dram},
-        plic::Plic,
-        rom::Mrom,
-        test::Test,
-        uart::{UART_SIZE, Uart},
+use std::{any::Any, fmt, mem};
+
+use crate::{
+    components::{
+        aia::Aia,
+        devices::{
+            Device, DeviceEvent,
+            aplic::{APLIC_NUM_SOURCES, APLIC_SIZE},
+            chardev::{self, CharBackend},
+            clint::{CLINT_SIZE, Clint},
+            dmac::{DMAC_DEVICE_ID, DMAC_SIZE, DmaController, IRQ_DMAC},
+            dram::Dram,
+            imsic::IMSIC_FILE_SIZE,
+            iommu::{IOMMU_SIZE, IRQ_IOMMU, RiscvIommu},
+            plic::{PLIC_M_CONTEXT, PLIC_S_CONTEXT, Plic},
+            rom::Mrom,
+            rtc::{IRQ_RTC, RTC_SIZE, Rtc},
+            syscon::{SYSCON_SIZE, Syscon},
+            test::Test,
+            uart::{UART_SIZE, Uart},
+            virtio::{
+                IRQ_VIRTIO, VIRTIO_COUNT, VIRTIO_DEVICE_ID, VIRTIO_SIZE, VirtioDevice, VirtioMmio,
+            },
+            virtio_blk::VirtioBlk,
+        },
+        dma::{Dma, Iommu, Memory},
+        mmu::Size,
+        pma::Pma,
+        trap::Exception,
     },
-    mmu::Size,
-    trap::Exception,
+    config::{AiaMode, Config, UnmappedPolicy},
 };
 
 /* Device memory mapping */
@@ -18,63 +39,586 @@ pub const MROM_BASE: u64 = 0x1000;
 pub const MROM_END: u64 = MROM_BASE + 0xf000;
 pub const TEST_BASE: u64 = 0x10_0000;
 pub const TEST_END: u64 = TEST_BASE + 0x1000;
+pub const RTC_BASE: u64 = 0x10_1000;
+pub const RTC_END: u64 = RTC_BASE + RTC_SIZE;
+pub const SYSCON_BASE: u64 = 0x10_2000;
+pub const SYSCON_END: u64 = SYSCON_BASE + SYSCON_SIZE;
+pub const CLINT_BASE: u64 = 0x200_0000;
+pub const CLINT_END: u64 = CLINT_BASE + CLINT_SIZE;
+pub const IOMMU_BASE: u64 = 0x301_0000;
+pub const IOMMU_END: u64 = IOMMU_BASE + IOMMU_SIZE;
+pub const DMAC_BASE: u64 = 0x400_0000;
+pub const DMAC_END: u64 = DMAC_BASE + DMAC_SIZE;
 pub const PLIC_BASE: u64 = 0xc00_0000;
 pub const PLIC_END: u64 = 0xc00_0000 + 0x20_8000;
+// With AIA, the APLIC domains take the place of the PLIC
+pub const APLIC_M_BASE: u64 = 0xc00_0000;
+pub const APLIC_M_END: u64 = APLIC_M_BASE + APLIC_SIZE;
+pub const APLIC_S_BASE: u64 = 0xd00_0000;
+pub const APLIC_S_END: u64 = APLIC_S_BASE + APLIC_SIZE;
+/// Base of the console UART, the other ones are placed by the configuration
 pub const UART0_BASE: u64 = 0x1000_0000;
-pub const UART0_END: u64 = UART0_BASE + UART_SIZE;
+/// Base of the first virtio-mmio transport, the next ones following
+pub const VIRTIO_BASE: u64 = 0x1000_1000;
+pub const IMSIC_M_BASE: u64 = 0x2400_0000;
+pub const IMSIC_M_END: u64 = IMSIC_M_BASE + IMSIC_FILE_SIZE;
+pub const IMSIC_S_BASE: u64 = 0x2800_0000;
+pub const IMSIC_S_END: u64 = IMSIC_S_BASE + IMSIC_FILE_SIZE;
+/// Default base of the RAM, its placement is part of the configuration
 pub const DRAM_BASE: u64 = 0x8000_0000;
-pub const DRAM_END: u64 = DRAM_BASE + DRAM_SIZE;
-/* Known memory regions */
-pub const SBI_REGION: u64 = DRAM_BASE;
-pub const KERNEL_REGION: u64 = DRAM_BASE + 0x4_000;
+/* Known memory regions, as offsets within the RAM */
+pub const SBI_OFFSET: u64 = 0;
+pub const KERNEL_OFFSET: u64 = 0x4_000;
+
+/// A device attached over [base, end) of the physical address space.
+struct Region {
+    name: String,
+    /// Requester id of a bus-mastering device, identifying its DMA towards the IOMMU
+    device_id: Option<u32>,
+    base: u64,
+    end: u64,
+    /// Interrupt source of the controller the device output is wired to
+    irq: Option<u32>,
+    /// Attributes of the region, as given by the device on registration
+    pma: Pma,
+    device: Box<dyn Device>,
+}
+
+/// Error of a region registration.
+#[derive(Debug)]
+pub enum RegionError {
+    /// The region overlaps an already mapped one.
+    Overlap { name: String, other: String },
+    /// The region extends past the end of the physical address space.
+    OutOfRange { name: String },
+    /// The interrupt source doesn't exist on the interrupt controller.
+    InvalidIrq { name: String, irq: u32 },
+    /// The requester id is already the one of another device.
+    DeviceIdInUse { name: String, other: String },
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Overlap { name, other } => {
+                write!(f, "region `{name}` overlaps `{other}`")
+            }
+            RegionError::OutOfRange { name } => {
+                write!(
+                    f,
+                    "region `{name}` extends past the end of the address space"
+                )
+            }
+            RegionError::InvalidIrq { name, irq } => {
+                write!(
+                    f,
+                    "region `{name}` is wired to the irq {irq}, which the interrupt controller lacks"
+                )
+            }
+            RegionError::DeviceIdInUse { name, other } => {
+                write!(f, "region `{name}` has the device id of `{other}`")
+            }
+        }
+    }
+}
+
+/// Master of an access on the bus.
+#[derive(Clone, Debug)]
+pub enum AccessSource {
+    /// The hart, which knows the instruction that made the access.
+    Hart,
+    /// The DMA of the device registered under the name.
+    Device(String),
+}
+
+/// An access to a hole of the physical memory map.
+#[derive(Clone, Debug)]
+pub struct UnmappedAccess {
+    pub address: u64,
+    /// In bytes
+    pub len: usize,
+    pub write: bool,
+    pub source: AccessSource,
+}
 
 pub struct SystemBus {
-    rom: Mrom,
-    pub test: Test,
+    // Most of the accesses go to DRAM, so it is matched before the regions
     dram: Dram,
-    pub uart0: Uart,
+    dram_base: u64,
+    dram_end: u64,
+    // The interrupt controllers are wired to the hart, so they stay apart from the other devices
+    pub clint: Clint,
     pub plic: Plic,
+    pub aia: Option<Aia>,
+    aia_mode: AiaMode,
+    /// Sorted by base address, not overlapping
+    regions: Vec<Region>,
+    unmapped: UnmappedPolicy,
+    log_unmapped: bool,
+    /// Recorded to be reported by the hart, which knows the context of the access
+    unmapped_accesses: Vec<UnmappedAccess>,
+    /// Loaded in RAM at startup, and again on a reset
+    images: Vec<(u64, Vec<u8>)>,
 }
 
 impl SystemBus {
-    pub fn new() -> Self {
-        Self {
-            rom: Mrom::new(),
-            test: Test::new(),
-            dram: Dram::new(),
-            uart0: Uart::new(),
-            plic: Plic::new(),
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let dram_end = config.ram.end()?;
+        let mut bus = Self {
+            dram: Dram::new(&config.ram).map_err(|e| format!("cannot map the RAM backing: {e}"))?,
+            dram_base: config.ram.base,
+            dram_end,
+            clint: Clint::new(config.timer.timebase_frequency),
+            plic: Plic::new(config.plic.sources),
+            aia: Aia::new(config.aia),
+            aia_mode: config.aia,
+            regions: Vec::new(),
+            unmapped: config.unmapped,
+            log_unmapped: config.log_unmapped,
+            unmapped_accesses: Vec::new(),
+            images: Vec::new(),
+        };
+        let backends = config
+            .uarts
+            .iter()
+            .enumerate()
+            .map(|(index, uart)| {
+                // Only the console reads the host terminal
+                chardev::open(&uart.backend, index == 0 && config.console_input)
+                    .map_err(|e| format!("cannot open the backend of uart{index}: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let disks = config
+            .disks
+            .iter()
+            .map(|disk| {
+                VirtioBlk::new(disk)
+                    .map_err(|e| format!("cannot open the disk image {}: {e}", disk.path.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        bus.register_default_devices(config, backends, disks)
+            .map_err(|e| format!("the configured devices don't fit the memory map: {e}"))?;
+        Ok(bus)
+    }
+
+    fn register_default_devices(
+        &mut self,
+        config: &Config,
+        backends: Vec<Box<dyn CharBackend>>,
+        disks: Vec<VirtioBlk>,
+    ) -> Result<(), RegionError> {
+        self.register(
+            "mrom",
+            MROM_BASE,
+            MROM_END - MROM_BASE,
+            None,
+            None,
+            Box::new(Mrom::new(self.dram_base)),
+        )?;
+        self.register(
+            "test",
+            TEST_BASE,
+            TEST_END - TEST_BASE,
+            None,
+            None,
+            Box::new(Test::new()),
+        )?;
+        self.register(
+            "dmac",
+            DMAC_BASE,
+            DMAC_END - DMAC_BASE,
+            Some(IRQ_DMAC),
+            Some(DMAC_DEVICE_ID),
+            Box::new(DmaController::new()),
+        )?;
+        self.register(
+            "syscon",
+            SYSCON_BASE,
+            SYSCON_END - SYSCON_BASE,
+            None,
+            None,
+            Box::new(Syscon::new(&config.syscon)),
+        )?;
+        self.register(
+            "rtc",
+            RTC_BASE,
+            RTC_END - RTC_BASE,
+            Some(IRQ_RTC),
+            None,
+            Box::new(Rtc::new(config.rtc_epoch)),
+        )?;
+        for (index, (uart, backend)) in config.uarts.iter().zip(backends).enumerate() {
+            self.register(
+                &format!("uart{index}"),
+                uart.base,
+                UART_SIZE,
+                Some(uart.irq),
+                None,
+                Box::new(Uart::new(backend)),
+            )?;
+        }
+        if config.iommu {
+            self.register(
+                "iommu",
+                IOMMU_BASE,
+                IOMMU_END - IOMMU_BASE,
+                Some(IRQ_IOMMU),
+                None,
+                Box::new(RiscvIommu::new()),
+            )?;
+        }
+        // Every transport is there, the ones past the disks being empty
+        let mut disks = disks.into_iter();
+        for index in 0..VIRTIO_COUNT {
+            let disk = disks
+                .next()
+                .map(|blk| Box::new(blk) as Box<dyn VirtioDevice>);
+            self.register(
+                &format!("virtio{index}"),
+                VIRTIO_BASE + VIRTIO_SIZE * index as u64,
+                VIRTIO_SIZE,
+                Some(IRQ_VIRTIO + index as u32),
+                Some(VIRTIO_DEVICE_ID + index as u32),
+                Box::new(VirtioMmio::new(disk)),
+            )?;
         }
+        Ok(())
     }
 
-    pub fn read(&mut self, address: u64, size: Size) -> Result<u64, Exception> {
-        match address {
-            MROM_BASE..MROM_END => Ok(self.rom.read(address - MROM_BASE, size)?),
-            DRAM_BASE..DRAM_END => Ok(self.dram.read(address - DRAM_BASE, size)?),
-            UART0_BASE..UART0_END if size == Size::BYTE => {
-                Ok(self.uart0.read(address - UART0_BASE)? as u64)
+    /// The regions with a fixed place in the memory map.
+    fn fixed_regions(&self) -> Vec<(&'static str, u64, u64)> {
+        let mut fixed = vec![
+            ("dram", self.dram_base, self.dram_end),
+            ("clint", CLINT_BASE, CLINT_END),
+        ];
+        match &self.aia {
+            Some(aia) => {
+                fixed.push(("aplic-m", APLIC_M_BASE, APLIC_M_END));
+                fixed.push(("aplic-s", APLIC_S_BASE, APLIC_S_END));
+                if aia.imsic.is_some() {
+                    fixed.push(("imsic-m", IMSIC_M_BASE, IMSIC_M_END));
+                    fixed.push(("imsic-s", IMSIC_S_BASE, IMSIC_S_END));
+                }
             }
-            PLIC_BASE..PLIC_END if size == Size::WORD => {
-                Ok(self.plic.read(address - PLIC_BASE)? as u64)
+            None => fixed.push(("plic", PLIC_BASE, PLIC_END)),
+        }
+        fixed
+    }
+
+    /// Number of the interrupt sources of the external interrupt controller, numbered from 1.
+    pub fn interrupt_sources(&self) -> u32 {
+        match &self.aia {
+            Some(_) => APLIC_NUM_SOURCES,
+            None => self.plic.sources(),
+        }
+    }
+
+    /// Attach a device over `size` bytes from `base`,
+    /// with its interrupt output wired to the `irq` source of the interrupt controller.
+    /// A bus-mastering device has a `device_id`, its requester id towards the IOMMU.
+    pub fn register(
+        &mut self,
+        name: &str,
+        base: u64,
+        size: u64,
+        irq: Option<u32>,
+        device_id: Option<u32>,
+        device: Box<dyn Device>,
+    ) -> Result<(), RegionError> {
+        let Some(end) = base.checked_add(size) else {
+            return Err(RegionError::OutOfRange {
+                name: name.to_string(),
+            });
+        };
+        //& Interrupt source 0 does not exist
+        if let Some(irq) = irq.filter(|&irq| irq == 0 || irq > self.interrupt_sources()) {
+            return Err(RegionError::InvalidIrq {
+                name: name.to_string(),
+                irq,
+            });
+        }
+        let overlapping = self
+            .fixed_regions()
+            .into_iter()
+            .chain(
+                self.regions
+                    .iter()
+                    .map(|r| (r.name.as_str(), r.base, r.end)),
+            )
+            .find(|&(_, other_base, other_end)| base < other_end && other_base < end);
+        if let Some((other, _, _)) = overlapping {
+            return Err(RegionError::Overlap {
+                name: name.to_string(),
+                other: other.to_string(),
+            });
+        }
+        let sharing = self
+            .regions
+            .iter()
+            .find(|r| device_id.is_some() && r.device_id == device_id);
+        if let Some(other) = sharing {
+            return Err(RegionError::DeviceIdInUse {
+                name: name.to_string(),
+                other: other.name.clone(),
+            });
+        }
+
+        let index = self.regions.partition_point(|r| r.base < base);
+        self.regions.insert(
+            index,
+            Region {
+                name: name.to_string(),
+                device_id,
+                base,
+                end,
+                irq,
+                pma: device.pma(),
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    /// Detach the device registered under `name`.
+    pub fn unregister(&mut self, name: &str) -> Option<Box<dyn Device>> {
+        let index = self.regions.iter().position(|r| r.name == name)?;
+        Some(self.regions.remove(index).device)
+    }
+
+    /// The device registered under `name`, if it is of type `T`.
+    pub fn device<T: Any>(&self, name: &str) -> Option<&T> {
+        self.regions
+            .iter()
+            .find(|r| r.name == name)
+            .and_then(|r| r.device.as_ref().as_any().downcast_ref())
+    }
+
+    /// The device registered under `name`, if it is of type `T`.
+    pub fn device_mut<T: Any>(&mut self, name: &str) -> Option<&mut T> {
+        self.regions
+            .iter_mut()
+            .find(|r| r.name == name)
+            .and_then(|r| r.device.as_mut().as_any_mut().downcast_mut())
+    }
+
+    /// The requester id of the device registered under `name`, when it is a bus master.
+    pub fn device_id(&self, name: &str) -> Option<u32> {
+        self.regions
+            .iter()
+            .find(|r| r.name == name)
+            .and_then(|r| r.device_id)
+    }
+
+    /// Access to the memory on behalf of the device `device_id`, as by its DMA.
+    pub fn dma(&mut self, device_id: u32) -> Dma<'_> {
+        let memory = Memory::new(&mut self.dram, self.dram_base, self.aia.as_mut());
+        let iommu = self.regions.iter_mut().find_map(|r| r.device.as_iommu());
+        Dma::new(memory, iommu, Some(device_id))
+    }
+
+    /// The region at `index`, with the IOMMU translating its DMA when another region is one.
+    /// The first IOMMU of the memory map translates the DMA of every other device,
+    /// and reaches its own queues and tables untranslated.
+    fn master(regions: &mut [Region], index: usize) -> (&mut Region, Option<&mut dyn Iommu>) {
+        let (before, rest) = regions.split_at_mut(index);
+        let (region, after) = rest.split_first_mut().expect("region index out of bounds");
+        let iommu = before
+            .iter_mut()
+            .chain(after.iter_mut())
+            .find_map(|r| r.device.as_iommu());
+        (region, iommu)
+    }
+
+    /// Binary search of the region holding the address.
+    fn region(&self, address: u64) -> Option<&Region> {
+        let index = self.regions.partition_point(|r| r.base <= address);
+        self.regions
+            .get(index.checked_sub(1)?)
+            .filter(|r| address < r.end)
+    }
+
+    fn region_mut(&mut self, address: u64) -> Option<&mut Region> {
+        let index = self.regions.partition_point(|r| r.base <= address);
+        self.regions
+            .get_mut(index.checked_sub(1)?)
+            .filter(|r| address < r.end)
+    }
+
+    /// Attributes of the physical address, none when nothing is mapped there.
+    pub fn pma(&self, address: u64) -> Option<Pma> {
+        if (self.dram_base..self.dram_end).contains(&address) {
+            return Some(Pma::MEMORY);
+        }
+        if let Some(region) = self.region(address) {
+            return Some(region.pma);
+        }
+        // mtime and mtimecmp are 64 bits wide
+        if (CLINT_BASE..CLINT_END).contains(&address) {
+            return Some(Pma::io(Size::WORD as u8 | Size::DWORD as u8));
+        }
+        // The registers of the other interrupt controllers are 32 bits wide
+        self.fixed_regions()
+            .into_iter()
+            .any(|(_, base, end)| (base..end).contains(&address))
+            .then_some(Pma::io(Size::WORD as u8))
+    }
+
+    /// Keep the access to a hole of the memory map, for it to be reported.
+    fn record_unmapped(&mut self, access: UnmappedAccess) {
+        if self.log_unmapped || self.unmapped == UnmappedPolicy::Stop {
+            self.unmapped_accesses.push(access);
+        }
+    }
+
+    /// Handle an access of the hart to a hole of the memory map, as by the policy.
+    fn unmapped_access(&mut self, address: u64, size: Size, write: bool) -> Result<(), Exception> {
+        self.record_unmapped(UnmappedAccess {
+            address,
+            len: size as usize,
+            write,
+            source: AccessSource::Hart,
+        });
+        match (self.unmapped, write) {
+            (UnmappedPolicy::Ignore, _) => Ok(()),
+            (_, false) => Err(Exception::LoadAccessFault(address)),
+            (_, true) => Err(Exception::StoreAccessFault(address)),
+        }
+    }
+
+    /// The accesses to holes of the memory map since the last call.
+    pub fn take_unmapped(&mut self) -> Vec<UnmappedAccess> {
+        mem::take(&mut self.unmapped_accesses)
+    }
+
+    /// Advance the devices, returning the first event requested.
+    pub fn tick(&mut self) -> Option<DeviceEvent> {
+        let mut event = None;
+        let mut faults = Vec::new();
+        // Every device is ticked, even after one requested an event
+        for index in 0..self.regions.len() {
+            let (region, iommu) = Self::master(&mut self.regions, index);
+            let memory = Memory::new(&mut self.dram, self.dram_base, self.aia.as_mut());
+            let mut dma = Dma::new(memory, iommu, region.device_id);
+            event = event.or(region.device.tick(&mut dma));
+            let name = &region.name;
+            faults.extend(dma.take_faults().into_iter().map(|f| (name.clone(), f)));
+        }
+        // Only the DMA to the holes, the other regions not being reachable by it
+        for (name, (address, len, write)) in faults {
+            if self.pma(address).is_none() {
+                self.record_unmapped(UnmappedAccess {
+                    address,
+                    len,
+                    write,
+                    source: AccessSource::Device(name),
+                });
             }
-            _ => Err(Exception::LoadAccessFault),
         }
+        event
     }
-    pub fn write(&mut self, address: u64, size: Size, value: u64) -> Result<(), Exception> {
-        match address {
-            TEST_BASE..TEST_END => Ok(self.test.write(address - TEST_BASE, size, value)),
-            DRAM_BASE..DRAM_END => Ok(self.dram.write(address - DRAM_BASE, size, value)?),
-            UART0_BASE..UART0_END if size == Size::BYTE => {
-                Ok(self.uart0.write(address - UART0_BASE, value as u8)?)
+
+    /// Bring every device back to its power-on state.
+    /// The RAM keeps its contents, except for the images that are loaded again.
+    pub fn reset(&mut self) {
+        self.regions.iter_mut().for_each(|r| r.device.reset());
+        self.clint.reset();
+        self.plic.reset();
+        self.aia = Aia::new(self.aia_mode);
+        self.unmapped_accesses.clear();
+        for (address, bin) in &self.images {
+            self.dram.write_bytes(address - self.dram_base, bin);
+        }
+    }
+
+    /// Drive the sources of the interrupt controller from the interrupt outputs of the devices.
+    /// Returns the external interrupt signals of the hart, as (MEIP, SEIP).
+    pub fn update_interrupts(&mut self) -> (bool, bool) {
+        let lines = self
+            .regions
+            .iter()
+            .filter_map(|r| r.irq.map(|irq| (irq, r.device.is_interrupting())));
+        match self.aia.as_mut() {
+            Some(aia) => {
+                lines.for_each(|(irq, level)| aia.set_source(irq, level));
+                aia.update()
             }
-            PLIC_BASE..PLIC_END if size == Size::WORD => {
-                Ok(self.plic.write(address - PLIC_BASE, value as u32)?)
+            None => {
+                let plic = &mut self.plic;
+                lines.for_each(|(irq, level)| plic.set_source(irq, level));
+                (
+                    plic.is_interrupting(PLIC_M_CONTEXT),
+                    plic.is_interrupting(PLIC_S_CONTEXT),
+                )
             }
-            _ => Err(Exception::StoreAccessFault),
+        }
+    }
+
+    /// Access faults report the physical address, as the devices only know the offset within their region.
+    pub fn read(&mut self, address: u64, size: Size) -> Result<u64, Exception> {
+        self.read_device(address, size)
+            .map_err(|_| Exception::LoadAccessFault(address))
+    }
+
+    pub fn write(&mut self, address: u64, size: Size, value: u64) -> Result<(), Exception> {
+        self.write_device(address, size, value)
+            .map_err(|_| Exception::StoreAccessFault(address))
+    }
+
+    fn read_device(&mut self, address: u64, size: Size) -> Result<u64, Exception> {
+        if (self.dram_base..self.dram_end).contains(&address) {
+            return self.dram.read(address - self.dram_base, size);
+        }
+        match self.pma(address) {
+            None => return self.unmapped_access(address, size, false).map(|_| 0),
+            Some(pma) if !pma.supports(size) => return Err(Exception::LoadAccessFault(address)),
+            Some(_) => {}
+        }
+        if (CLINT_BASE..CLINT_END).contains(&address) {
+            return self.clint.read(address - CLINT_BASE, size);
+        }
+        // The AIA replaces the PLIC in the memory map
+        if let Some(result) = self.aia.as_mut().and_then(|aia| aia.read(address, size)) {
+            return result;
+        }
+        if (PLIC_BASE..PLIC_END).contains(&address) && self.aia.is_none() {
+            return Ok(self.plic.read(address - PLIC_BASE)? as u64);
+        }
+        match self.region_mut(address) {
+            Some(region) => region.device.read(address - region.base, size),
+            None => Err(Exception::LoadAccessFault(address)),
+        }
+    }
+
+    fn write_device(&mut self, address: u64, size: Size, value: u64) -> Result<(), Exception> {
+        if (self.dram_base..self.dram_end).contains(&address) {
+            return self.dram.write(address - self.dram_base, size, value);
+        }
+        match self.pma(address) {
+            None => return self.unmapped_access(address, size, true),
+            Some(pma) if !pma.supports(size) => return Err(Exception::StoreAccessFault(address)),
+            Some(_) => {}
+        }
+        if (CLINT_BASE..CLINT_END).contains(&address) {
+            return self.clint.write(address - CLINT_BASE, size, value);
+        }
+        if let Some(result) = self
+            .aia
+            .as_mut()
+            .and_then(|aia| aia.write(address, size, value))
+        {
+            return result;
+        }
+        if (PLIC_BASE..PLIC_END).contains(&address) && self.aia.is_none() {
+            return self.plic.write(address - PLIC_BASE, value as u32);
+        }
+        match self.region_mut(address) {
+            Some(region) => region.device.write(address - region.base, size, value),
+            None => Err(Exception::StoreAccessFault(address)),
         }
     }
 
     pub fn inject(&mut self, address: u64, bin: &[u8]) {
-        self.dram.write_bytes(address - DRAM_BASE, bin);
+        self.dram.write_bytes(address - self.dram_base, bin);
+        self.images.push((address, bin.to_vec()));
     }
 }
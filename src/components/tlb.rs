@@ -0,0 +1,121 @@
+use crate::components::mmu::Sv39pte;
+
+/// A cached leaf translation.
+#[derive(Clone, Copy)]
+pub struct TlbEntry {
+    /// Virtual page number, shifted by the size of the page
+    pub vpn: u64,
+    /// Level of the leaf PTE, a superpage when greater than 0
+    pub level: u8,
+    pub asid: u16,
+    /// The leaf PTE, its permissions are checked again on every hit, as they depend on the privilege mode, SUM and MXR
+    pub pte: Sv39pte,
+}
+
+impl TlbEntry {
+    #[inline(always)]
+    fn page_shift(level: u8) -> u64 {
+        12 + 9 * level as u64
+    }
+
+    /// Whether the entry maps the virtual address for the address space.
+    #[inline(always)]
+    fn matches(&self, vaddr: u64, asid: u16) -> bool {
+        self.vpn == vaddr >> Self::page_shift(self.level)
+            && (self.asid == asid || self.pte.g().value() == 1)
+    }
+
+    /// The physical address of `vaddr`, within the page of the entry.
+    //& If i>0, then this is a superpage translation and pa.ppn[i-1:0] = va.vpn[i-1:0].
+    // Those bits of pte.ppn are zero, as misaligned superpages aren't cached
+    #[inline(always)]
+    pub fn physical(&self, vaddr: u64) -> u64 {
+        let offset = vaddr & ((1 << Self::page_shift(self.level)) - 1);
+        (self.pte.ppn().value() << 12) | offset
+    }
+}
+
+/// Set-associative translation cache, with round-robin replacement within a set.
+pub struct Tlb {
+    sets: usize,
+    ways: usize,
+    entries: Vec<Option<TlbEntry>>,
+    /// Next way to replace, per set
+    victim: Vec<usize>,
+}
+
+impl Tlb {
+    /// A TLB with no sets or ways caches nothing.
+    pub fn new(sets: usize, ways: usize) -> Self {
+        let (sets, ways) = if sets == 0 || ways == 0 {
+            (0, 0)
+        } else {
+            (sets, ways)
+        };
+        Self {
+            sets,
+            ways,
+            entries: vec![None; sets * ways],
+            victim: vec![0; sets],
+        }
+    }
+
+    #[inline(always)]
+    fn set(&self, vpn: u64) -> usize {
+        (vpn % self.sets as u64) as usize * self.ways
+    }
+
+    pub fn lookup(&self, vaddr: u64, asid: u16) -> Option<TlbEntry> {
+        if self.sets == 0 {
+            return None;
+        }
+        // The page size isn't known before the lookup, so probe the set of each level
+        (0..3).find_map(|level| {
+            let set = self.set(vaddr >> TlbEntry::page_shift(level));
+            self.entries[set..set + self.ways]
+                .iter()
+                .flatten()
+                .find(|e| e.level == level && e.matches(vaddr, asid))
+                .copied()
+        })
+    }
+
+    pub fn insert(&mut self, entry: TlbEntry) {
+        if self.sets == 0 {
+            return;
+        }
+        let index = self.set(entry.vpn) / self.ways;
+        let set = index * self.ways;
+        let ways = &mut self.entries[set..set + self.ways];
+        // Replace the stale entry of the same page, then a free way, then the victim
+        let way = ways
+            .iter()
+            .position(|e| {
+                e.is_some_and(|e| {
+                    e.vpn == entry.vpn && e.level == entry.level && e.asid == entry.asid
+                })
+            })
+            .or_else(|| ways.iter().position(Option::is_none))
+            .unwrap_or_else(|| {
+                let way = self.victim[index];
+                self.victim[index] = (way + 1) % self.ways;
+                way
+            });
+        self.entries[set + way] = Some(entry);
+    }
+
+    /// Invalidate the entries of `vaddr` (or all of them) for `asid` (or all of the address spaces).
+    //& If rs2≠x0, SFENCE.VMA orders only reads and writes to the page tables for the address space identified by rs2,
+    //& and it doesn't order accesses to global mappings.
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u16>) {
+        self.entries.iter_mut().for_each(|slot| {
+            let hit = slot.is_some_and(|e| {
+                vaddr.is_none_or(|vaddr| e.vpn == vaddr >> TlbEntry::page_shift(e.level))
+                    && asid.is_none_or(|asid| e.asid == asid && e.pte.g().value() == 0)
+            });
+            if hit {
+                *slot = None;
+            }
+        });
+    }
+}
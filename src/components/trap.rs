@@ -2,6 +2,7 @@ use arbitrary_int::u1;
 
 use crate::{
     components::csr::{MCAUSE, MEDELEG, MEPC, MIDELEG, MTVAL, MTVEC, SCAUSE, SEPC, STVAL, STVEC},
+    config::TvalPolicy,
     cpu::{Cpu, PrivilegeMode},
 };
 
@@ -21,14 +22,17 @@ pub enum Trap {
 #[repr(u8)]
 #[derive(Copy, Clone, Debug)]
 pub enum Exception {
-    InstructionAddressMisaligned = 0,
-    InstructionAccessFault,
-    IllegalInstruction,
+    /// Target address of the jump
+    InstructionAddressMisaligned(u64) = 0,
+    /// Faulting address, the physical one until the mmu reports it
+    InstructionAccessFault(u64),
+    /// Bits of the faulting instruction
+    IllegalInstruction(u32),
     Breakpoint,
-    LoadAddressMisaligned,
-    LoadAccessFault,
-    StoreAddressMisaligned,
-    StoreAccessFault,
+    LoadAddressMisaligned(u64),
+    LoadAccessFault(u64),
+    StoreAddressMisaligned(u64),
+    StoreAccessFault(u64),
     EnvironmentCallFromUMode,
     EnvironmentCallFromSMode,
     EnvironmentCallFromMMode = 11,
@@ -41,48 +45,41 @@ pub enum Exception {
 }
 
 impl Exception {
-    fn epc(&self, pc: u64) -> u64 {
-        //& ECALL and EBREAK cause the receiving privilege mode’s epc register to be set to the address of the ECALL or EBREAK instruction itself,
-        //& not the address of the following instruction.
-        //? spec says that xepc should be written with the address of the instruction that triggered the trap,
-        // tho it doesn't make much sense.
-        match self {
-            Exception::Breakpoint
-            | Exception::EnvironmentCallFromUMode
-            | Exception::EnvironmentCallFromSMode
-            | Exception::EnvironmentCallFromMMode => pc.wrapping_sub(4),
-            _ => pc,
+    /// Value written to xtval, `epc` being the address of the instruction that caused the exception.
+    fn tval(&self, epc: u64, policy: TvalPolicy) -> u64 {
+        if policy == TvalPolicy::Zero {
+            return 0;
         }
-    }
-
-    fn tval(&self, pc: u64) -> u64 {
-        //& If mtval is written with a nonzero value when a breakpoint, address-misaligned, access-fault, page-fault,
-        //& or hardware-error exception occurs on an instruction fetch, load, or store, then mtval will contain the faulting virtual address.
         match self {
-            Exception::Breakpoint
-            | Exception::LoadAccessFault
-            | Exception::StoreAccessFault
-            | Exception::InstructionAccessFault
-            | Exception::LoadAddressMisaligned
-            | Exception::StoreAddressMisaligned
-            | Exception::InstructionAddressMisaligned => pc,
-            Exception::InstructionPageFault(addr)
+            //& If mtval is written with a nonzero value when a breakpoint, address-misaligned, access-fault, page-fault,
+            //& or hardware-error exception occurs on an instruction fetch, load, or store, then mtval will contain the faulting virtual address.
+            //& When a breakpoint exception is raised by an EBREAK instruction, mtval is written with the address of the instruction.
+            Exception::Breakpoint => epc,
+            Exception::InstructionAddressMisaligned(addr)
+            | Exception::InstructionAccessFault(addr)
+            | Exception::LoadAddressMisaligned(addr)
+            | Exception::LoadAccessFault(addr)
+            | Exception::StoreAddressMisaligned(addr)
+            | Exception::StoreAccessFault(addr)
+            | Exception::InstructionPageFault(addr)
             | Exception::LoadPageFault(addr)
             | Exception::StorePageFault(addr) => *addr,
+            //& On an illegal-instruction trap, mtval may be written with the first XLEN or ILEN bits of the faulting instruction.
+            Exception::IllegalInstruction(bits) if policy == TvalPolicy::Full => *bits as u64,
             _ => 0,
         }
     }
     // An enum with payloads can't be casted to an int
     pub fn code(&self) -> u64 {
         match self {
-            Exception::InstructionAddressMisaligned => 0,
-            Exception::InstructionAccessFault => 1,
-            Exception::IllegalInstruction => 2,
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
             Exception::Breakpoint => 3,
-            Exception::LoadAddressMisaligned => 4,
-            Exception::LoadAccessFault => 5,
-            Exception::StoreAddressMisaligned => 6,
-            Exception::StoreAccessFault => 7,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
             Exception::EnvironmentCallFromUMode => 8,
             Exception::EnvironmentCallFromSMode => 9,
             Exception::EnvironmentCallFromMMode => 11,
@@ -95,51 +92,12 @@ impl Exception {
         }
     }
 
-    pub fn take_trap(&self, cpu: &mut Cpu) {
-        let cause = self.code();
-        let epc = self.epc(cpu.pc);
-        let pp_mode = *cpu.p_mode;
-        let medeleg = cpu.csr.read(MEDELEG);
-        let mut mstatus = cpu.csr.read_mstatus();
-        //& Traps never transition from a more-privileged mode to a less-privileged mode.
-        if pp_mode <= PrivilegeMode::Supervisor && ((medeleg >> cause) & 1) == 1 {
-            // Switch to S-mode
-            *cpu.p_mode = PrivilegeMode::Supervisor;
-            //& When a trap is taken from privilege mode y into privilege mode x,
-            //& xPIE is set to the value of xIE;
-            mstatus.set_spie(mstatus.sie());
-            //& xIE is set to 0;
-            mstatus.set_sie(u1::new(0));
-            //& and xPP is set to y.
-            mstatus.set_spp(u1::new(pp_mode as u8));
-            // Jump to Supervisor trap handler
-            cpu.pc = cpu.csr.read(STVEC) & !3;
-            //& When a trap is taken into S-mode, sepc is written with the virtual address of the instruction that was interrupted or that encountered the exception.
-            //& The low bit of sepc (sepc[0]) is always zero.
-            cpu.csr.write(SEPC, epc & !1);
-            //& When a trap is taken into S-mode, scause is written with a code indicating the event that caused the trap.
-            cpu.csr.write(SCAUSE, cause);
-            cpu.csr.write(STVAL, self.tval(cpu.pc));
-        } else {
-            // Switch to M-mode
-            *cpu.p_mode = PrivilegeMode::Supervisor;
-            //& When a trap is taken from privilege mode y into privilege mode x,
-            //& xPIE is set to the value of xIE;
-            mstatus.set_mpie(mstatus.mie());
-            //& xIE is set to 0;
-            mstatus.set_mie(u1::new(0));
-            //& and xPP is set to y.
-            mstatus.set_mpp(pp_mode);
-            // Jump to Machine trap handler
-            cpu.pc = cpu.csr.read(MTVEC) & !3;
-            //& When a trap is taken into M-mode, mepc is written with the virtual address of the instruction that was interrupted or that encountered the exception.
-            //& The low bit of mepc (mepc[0]) is always zero.
-            cpu.csr.write(MEPC, epc & !1);
-            //& When a trap is taken into M-mode, mcause is written with a code indicating the event that caused the trap.
-            cpu.csr.write(MCAUSE, cause);
-            cpu.csr.write(MTVAL, self.tval(cpu.pc));
-        }
-        cpu.csr.write_mstatus(&mstatus);
+    /// Take the trap, `pc` being the address of the instruction that caused the exception.
+    pub fn take_trap(&self, cpu: &mut Cpu, pc: u64) {
+        //& ECALL and EBREAK cause the receiving privilege mode’s epc register to be set to the address of the ECALL or EBREAK instruction itself,
+        //& not the address of the following instruction.
+        let tval = self.tval(pc, cpu.config.tval);
+        take_trap(cpu, self.code(), false, pc, tval);
     }
 }
 
@@ -156,14 +114,44 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
+    /// Default priority order of the major interrupts, from the highest.
+    pub const PRIORITY: [Interrupt; 7] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+        Interrupt::CounterOverflow,
+    ];
+
+    /// The highest priority interrupt in a set of mip-like bits.
+    pub fn highest(bits: u64) -> Option<Interrupt> {
+        Self::PRIORITY
+            .into_iter()
+            .find(|i| (bits >> (*i as u64)) & 1 == 1)
+    }
+
+    /// Take the trap, interrupting the instruction at the current pc.
     pub fn take_trap(&self, cpu: &mut Cpu) {
-        let cause = *self as u64;
         let epc = cpu.pc;
-        let pp_mode = *cpu.p_mode;
-        let mideleg = cpu.csr.read(MIDELEG);
-        let mut mstatus = cpu.csr.read_mstatus();
-        //& Traps never transition from a more-privileged mode to a less-privileged mode.
-        if pp_mode <= PrivilegeMode::Supervisor && ((mideleg >> cause) & 1) == 1 {
+        take_trap(cpu, *self as u64, true, epc, 0);
+    }
+}
+
+/// The trap entry shared by exceptions and interrupts.
+fn take_trap(cpu: &mut Cpu, cause: u64, interrupt: bool, epc: u64, tval: u64) {
+    let pp_mode = *cpu.p_mode;
+    let deleg = match interrupt {
+        true => cpu.csr.read(MIDELEG),
+        false => cpu.csr.read(MEDELEG),
+    };
+    //& The Interrupt bit in the mcause register is set if the trap was caused by an interrupt.
+    let xcause = cause | (interrupt as u64) << 63;
+    let mut mstatus = cpu.csr.read_mstatus();
+    //& Traps never transition from a more-privileged mode to a less-privileged mode.
+    let (xtvec, xepc, xcause_csr, xtval) =
+        if pp_mode <= PrivilegeMode::Supervisor && ((deleg >> cause) & 1) == 1 {
             // Switch to S-mode
             *cpu.p_mode = PrivilegeMode::Supervisor;
             //& When a trap is taken from privilege mode y into privilege mode x,
@@ -173,27 +161,10 @@ impl Interrupt {
             mstatus.set_sie(u1::new(0));
             //& and xPP is set to y.
             mstatus.set_spp(u1::new(pp_mode as u8));
-            // Jump to Supervisor trap handler
-            let stvec = cpu.csr.read(STVEC);
-            let offset = match stvec & 3 {
-                // Vectored
-                //& Asynchronous interrupts set pc to BASE+4×cause.
-                1 => 4 * cause,
-                // Direct
-                //& All traps set pc to BASE.
-                _ => 0,
-            };
-            cpu.pc = (stvec & !3) + offset;
-            //& When a trap is taken into S-mode, sepc is written with the virtual address of the instruction that was interrupted or that encountered the exception.
-            //& The low bit of sepc (sepc[0]) is always zero.
-            cpu.csr.write(SEPC, epc & !1);
-            //& When a trap is taken into S-mode, scause is written with a code indicating the event that caused the trap.
-            // Set MSB to indicate an interrupt
-            cpu.csr.write(SCAUSE, cause | 1 << 63);
-            cpu.csr.write(STVAL, 0);
+            (STVEC, SEPC, SCAUSE, STVAL)
         } else {
             // Switch to M-mode
-            *cpu.p_mode = PrivilegeMode::Supervisor;
+            *cpu.p_mode = PrivilegeMode::Machine;
             //& When a trap is taken from privilege mode y into privilege mode x,
             //& xPIE is set to the value of xIE;
             mstatus.set_mpie(mstatus.mie());
@@ -201,25 +172,27 @@ impl Interrupt {
             mstatus.set_mie(u1::new(0));
             //& and xPP is set to y.
             mstatus.set_mpp(pp_mode);
-            // Jump to Machine trap handler
-            let mtvec = cpu.csr.read(MTVEC);
-            let offset = match mtvec & 3 {
-                // Vectored
-                //& Asynchronous interrupts set pc to BASE+4×cause.
-                1 => 4 * cause,
-                // Direct
-                //& All traps set pc to BASE.
-                _ => 0,
-            };
-            cpu.pc = (mtvec & !3) + offset;
-            //& When a trap is taken into M-mode, mepc is written with the virtual address of the instruction that was interrupted or that encountered the exception.
-            //& The low bit of mepc (mepc[0]) is always zero.
-            cpu.csr.write(MEPC, epc & !1);
-            //& When a trap is taken into M-mode, mcause is written with a code indicating the event that caused the trap.
-            // Set MSB to indicate an interrupt
-            cpu.csr.write(MCAUSE, cause | 1 << 63);
-            cpu.csr.write(MTVAL, 0);
-        }
-        cpu.csr.write_mstatus(&mstatus);
-    }
+            (MTVEC, MEPC, MCAUSE, MTVAL)
+        };
+    cpu.csr.write_mstatus(&mstatus);
+
+    // Jump to the trap handler
+    let tvec = cpu.csr.read(xtvec);
+    let offset = match tvec & 3 {
+        // Vectored
+        //& Asynchronous interrupts set pc to BASE+4×cause.
+        1 if interrupt => 4 * cause,
+        // Direct
+        //& All traps set pc to BASE.
+        //& When MODE=Vectored, all synchronous exceptions into machine mode cause the pc to be set to the address in the BASE field.
+        _ => 0,
+    };
+    cpu.pc = (tvec & !3).wrapping_add(offset);
+    //& When a trap is taken into x-mode, xepc is written with the virtual address of the instruction that was interrupted or that encountered the exception.
+    //& The low bit of xepc (xepc[0]) is always zero.
+    cpu.csr.write(xepc, epc & !1);
+    //& When a trap is taken into x-mode, xcause is written with a code indicating the event that caused the trap.
+    cpu.csr.write(xcause_csr, xcause);
+    //& Otherwise, xtval is written with zero.
+    cpu.csr.write(xtval, tval);
 }
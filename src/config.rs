@@ -0,0 +1,612 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::components::{
+    devices::{
+        aplic::APLIC_NUM_SOURCES,
+        clint::TIMEBASE_FREQUENCY,
+        dmac::IRQ_DMAC,
+        dram::DRAM_SIZE,
+        iommu::IRQ_IOMMU,
+        plic::{PLIC_MAX_SOURCES, PLIC_SOURCES},
+        rtc::IRQ_RTC,
+        syscon::SYSCON_SIZE,
+        uart::{IRQ_UART, UART_SIZE},
+        virtio::{IRQ_VIRTIO, VIRTIO_COUNT, VIRTIO_SIZE},
+    },
+    system_bus::{
+        APLIC_M_BASE, APLIC_M_END, APLIC_S_BASE, APLIC_S_END, CLINT_BASE, CLINT_END, DMAC_BASE,
+        DMAC_END, DRAM_BASE, IMSIC_M_BASE, IMSIC_M_END, IMSIC_S_BASE, IMSIC_S_END, IOMMU_BASE,
+        IOMMU_END, MROM_BASE, MROM_END, PLIC_BASE, PLIC_END, RTC_BASE, RTC_END, SYSCON_BASE,
+        SYSCON_END, TEST_BASE, TEST_END, UART0_BASE, VIRTIO_BASE,
+    },
+};
+
+/// Advanced Interrupt Architecture setup, mirrors qemu's `virt,aia=` machine option.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AiaMode {
+    /// No AIA, the PLIC is the external interrupt controller.
+    #[default]
+    None,
+    /// APLIC with direct delivery to the harts.
+    Aplic,
+    /// APLIC forwarding interrupts as MSIs to the per-hart IMSICs.
+    AplicImsic,
+}
+
+impl FromStr for AiaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(AiaMode::None),
+            "aplic" => Ok(AiaMode::Aplic),
+            "aplic-imsic" => Ok(AiaMode::AplicImsic),
+            _ => Err(format!(
+                "unknown aia mode `{s}`, expected none|aplic|aplic-imsic"
+            )),
+        }
+    }
+}
+
+/// What is written to xtval on an exception, the spec allows any of the values to be replaced by zero.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TvalPolicy {
+    /// Faulting addresses and the bits of illegal instructions.
+    #[default]
+    Full,
+    /// Faulting addresses only, zero for illegal instructions.
+    Address,
+    /// Always zero.
+    Zero,
+}
+
+impl FromStr for TvalPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(TvalPolicy::Full),
+            "address" => Ok(TvalPolicy::Address),
+            "zero" => Ok(TvalPolicy::Zero),
+            _ => Err(format!(
+                "unknown tval policy `{s}`, expected full|address|zero"
+            )),
+        }
+    }
+}
+
+/// How loads and stores to addresses that aren't naturally aligned are handled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MisalignedPolicy {
+    /// Performed by the hardware, split in accesses to each of the pages they span.
+    #[default]
+    Hardware,
+    /// Raise an address-misaligned exception, for the software to emulate them.
+    Trap,
+}
+
+impl FromStr for MisalignedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hardware" => Ok(MisalignedPolicy::Hardware),
+            "trap" => Ok(MisalignedPolicy::Trap),
+            _ => Err(format!(
+                "unknown misaligned policy `{s}`, expected hardware|trap"
+            )),
+        }
+    }
+}
+
+/// What accesses to holes of the physical memory map do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum UnmappedPolicy {
+    /// Raise an access fault.
+    #[default]
+    Fault,
+    /// Read as zero, with the writes ignored.
+    Ignore,
+    /// Raise an access fault and stop the emulation, reporting the access.
+    Stop,
+}
+
+impl FromStr for UnmappedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fault" => Ok(UnmappedPolicy::Fault),
+            "ignore" => Ok(UnmappedPolicy::Ignore),
+            "stop" => Ok(UnmappedPolicy::Stop),
+            _ => Err(format!(
+                "unknown unmapped policy `{s}`, expected fault|ignore|stop"
+            )),
+        }
+    }
+}
+
+/// Geometry of the TLBs caching the address translations of the MMU.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TlbConfig {
+    /// Number of sets, 0 disables the caching.
+    pub sets: usize,
+    /// Associativity of each set.
+    pub ways: usize,
+    /// Separate TLBs for the instruction fetches and the data accesses.
+    pub split: bool,
+}
+
+impl Default for TlbConfig {
+    fn default() -> Self {
+        Self {
+            sets: 64,
+            ways: 4,
+            split: true,
+        }
+    }
+}
+
+/// Host memory holding the guest RAM.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum RamBacking {
+    /// Private memory, allocated a page at a time on the first write.
+    #[default]
+    Sparse,
+    /// A host file mapped shared, kept across runs and readable by other processes.
+    File(PathBuf),
+    /// A named POSIX shared-memory object, for other processes to map while running.
+    Shm(String),
+}
+
+impl FromStr for RamBacking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "sparse" => Ok(RamBacking::Sparse),
+            Some(("file", path)) if !path.is_empty() => Ok(RamBacking::File(path.into())),
+            Some(("shm", name)) if !name.is_empty() && !name.contains('/') => {
+                Ok(RamBacking::Shm(name.to_string()))
+            }
+            _ => Err(format!(
+                "unknown ram backing `{s}`, expected sparse|file:PATH|shm:NAME"
+            )),
+        }
+    }
+}
+
+/// Placement of the guest RAM in the physical address space, and its backing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RamConfig {
+    pub base: u64,
+    pub size: u64,
+    pub backing: RamBacking,
+}
+
+impl RamConfig {
+    /// End of the RAM, which must not go past the end of the physical address space.
+    pub fn end(&self) -> Result<u64, String> {
+        self.base.checked_add(self.size).ok_or_else(|| {
+            format!(
+                "ram of {:#x} bytes at {:#x} extends past the end of the address space",
+                self.size, self.base
+            )
+        })
+    }
+}
+
+impl Default for RamConfig {
+    fn default() -> Self {
+        Self {
+            base: DRAM_BASE,
+            size: DRAM_SIZE,
+            backing: RamBacking::Sparse,
+        }
+    }
+}
+
+/// Host side of a UART.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum SerialBackend {
+    /// The host terminal.
+    #[default]
+    Stdio,
+    /// Output discarded, no input.
+    Null,
+    /// Output appended to a file, no input.
+    File(PathBuf),
+    /// A listening Unix domain socket, for a client to connect to.
+    Unix(PathBuf),
+    /// A new host pseudo-terminal, its path printed on startup.
+    Pty,
+}
+
+impl FromStr for SerialBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "stdio" => Ok(SerialBackend::Stdio),
+            None if s == "null" => Ok(SerialBackend::Null),
+            None if s == "pty" => Ok(SerialBackend::Pty),
+            Some(("file", path)) if !path.is_empty() => Ok(SerialBackend::File(path.into())),
+            Some(("unix", path)) if !path.is_empty() => Ok(SerialBackend::Unix(path.into())),
+            _ => Err(format!(
+                "unknown serial backend `{s}`, expected stdio|null|pty|file:PATH|unix:PATH"
+            )),
+        }
+    }
+}
+
+/// A 16550 UART, wired to an interrupt source of the interrupt controller.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UartConfig {
+    pub base: u64,
+    pub irq: u32,
+    pub backend: SerialBackend,
+}
+
+// The console UART of qemu `virt`
+impl Default for UartConfig {
+    fn default() -> Self {
+        Self {
+            base: UART0_BASE,
+            irq: IRQ_UART,
+            backend: SerialBackend::Stdio,
+        }
+    }
+}
+
+impl FromStr for UartConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid uart `{s}`, expected BASE:IRQ[:BACKEND]");
+        let mut fields = s.splitn(3, ':');
+        let (Some(base), Some(irq)) = (fields.next(), fields.next()) else {
+            return Err(error());
+        };
+        let base = parse_size(base).map_err(|_| error())?;
+        let irq = parse_size(irq).map_err(|_| error())?;
+        let backend = fields.next().map_or(Ok(SerialBackend::Null), str::parse)?;
+        // Source 0 doesn't exist, 1023 is the last one of the interrupt controllers
+        if !(1..1024).contains(&irq) {
+            return Err(error());
+        }
+        Ok(Self {
+            base,
+            irq: irq as u32,
+            backend,
+        })
+    }
+}
+
+/// A virtio-blk disk, backed by a raw image file of the host.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DiskConfig {
+    pub path: PathBuf,
+    /// Fail the writes, leaving the image untouched.
+    pub read_only: bool,
+    /// Logical block size advertised to the driver, a power of two from 512 to 4096.
+    pub sector_size: u32,
+}
+
+impl FromStr for DiskConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid disk `{s}`, expected PATH[,ro][,sector-size=SIZE]");
+        let mut options = s.split(',');
+        let path = options.next().filter(|p| !p.is_empty()).ok_or_else(error)?;
+        let mut disk = Self {
+            path: path.into(),
+            read_only: false,
+            sector_size: 512,
+        };
+        for option in options {
+            match option.split_once('=') {
+                None if option == "ro" => disk.read_only = true,
+                Some(("sector-size", size)) => {
+                    disk.sector_size = parse_size(size)
+                        .ok()
+                        .and_then(|size| u32::try_from(size).ok())
+                        .filter(|size| size.is_power_of_two() && (512..=4096).contains(size))
+                        .ok_or_else(error)?;
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(disk)
+    }
+}
+
+/// Clock of the machine timer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimerConfig {
+    /// Ticks of mtime per second, as advertised by `timebase-frequency` in the device tree.
+    /// The device tree printed by `--dump-dts` follows it.
+    pub timebase_frequency: u64,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            timebase_frequency: TIMEBASE_FREQUENCY,
+        }
+    }
+}
+
+/// Interrupt sources of the PLIC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PlicConfig {
+    /// Number of the sources, as advertised by `riscv,ndev` in the device tree, at most 1023.
+    pub sources: u32,
+}
+
+impl Default for PlicConfig {
+    fn default() -> Self {
+        Self {
+            sources: PLIC_SOURCES,
+        }
+    }
+}
+
+/// Write to a syscon register that triggers an action of the machine.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SysconAction {
+    pub offset: u64,
+    pub value: u32,
+    /// Bits of the register compared to the value
+    pub mask: u32,
+}
+
+impl FromStr for SysconAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid syscon action `{s}`, expected OFFSET:VALUE[:MASK]");
+        let fields = s
+            .split(':')
+            .map(parse_size)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error())?;
+        let (offset, value, mask) = match fields[..] {
+            [offset, value] => (offset, value, u32::MAX as u64),
+            [offset, value, mask] => (offset, value, mask),
+            _ => return Err(error()),
+        };
+        // The registers are words within the block
+        if offset % 4 != 0
+            || offset >= SYSCON_SIZE
+            || value > u32::MAX as u64
+            || mask > u32::MAX as u64
+        {
+            return Err(error());
+        }
+        Ok(Self {
+            offset,
+            value: value as u32,
+            mask: mask as u32,
+        })
+    }
+}
+
+/// Actions of the syscon block, the properties of the `syscon-poweroff` and `syscon-reboot` nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SysconConfig {
+    pub poweroff: SysconAction,
+    pub reboot: SysconAction,
+}
+
+// The values of the finisher of qemu `virt`, also driven through syscon
+impl Default for SysconConfig {
+    fn default() -> Self {
+        Self {
+            poweroff: SysconAction {
+                offset: 0,
+                value: 0x5555,
+                mask: u32::MAX,
+            },
+            reboot: SysconAction {
+                offset: 0,
+                value: 0x7777,
+                mask: u32::MAX,
+            },
+        }
+    }
+}
+
+/// Parse an address or a size, in decimal or with a `0x` prefix in hex,
+/// optionally followed by a K, M, G or T binary multiplier.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.chars().last() {
+        Some('K' | 'k') => (&s[..s.len() - 1], 10),
+        Some('M' | 'm') => (&s[..s.len() - 1], 20),
+        Some('G' | 'g') => (&s[..s.len() - 1], 30),
+        Some('T' | 't') => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|e| format!("invalid size `{s}`: {e}"))?;
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size `{s}` overflows"))
+}
+
+/// Machine configuration, fixed for the lifetime of a `Cpu`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub aia: AiaMode,
+    /// The external interrupt controller when there is no AIA.
+    pub plic: PlicConfig,
+    pub tval: TvalPolicy,
+    /// Execute reserved encodings the way the decoder happens to read them,
+    /// instead of raising an illegal-instruction exception.
+    pub lenient: bool,
+    pub misaligned: MisalignedPolicy,
+    /// Support big-endian data accesses, making mstatus.MBE/SBE/UBE writable.
+    pub big_endian: bool,
+    pub tlb: TlbConfig,
+    pub ram: RamConfig,
+    pub unmapped: UnmappedPolicy,
+    /// Report every access to a hole of the physical memory map, with the pc and privilege mode.
+    pub log_unmapped: bool,
+    /// Place a RISC-V IOMMU between the bus-mastering devices and the memory.
+    pub iommu: bool,
+    pub timer: TimerConfig,
+    pub syscon: SysconConfig,
+    /// Initial time of the RTC in seconds since the Unix epoch, for runs not depending on the host clock,
+    /// the time then advancing with the cycles of the hart. The host time when none.
+    pub rtc_epoch: Option<u64>,
+    /// The UARTs of the machine, the first one being the console.
+    pub uarts: Vec<UartConfig>,
+    /// Read the host terminal into the console UART when stdio, putting it in raw mode.
+    pub console_input: bool,
+    /// The disks, each behind a virtio-mmio transport, the first one being `/dev/vda`.
+    pub disks: Vec<DiskConfig>,
+}
+
+impl Config {
+    /// Check the settings depending on each other, which the parsing of each one can't.
+    pub fn validate(&self) -> Result<(), String> {
+        let ram_end = self.ram.end()?;
+        self.validate_plic()?;
+        if self.disks.len() > VIRTIO_COUNT {
+            return Err(format!(
+                "{} disks given, at most {VIRTIO_COUNT} can be attached",
+                self.disks.len()
+            ));
+        }
+        self.validate_uarts(ram_end)
+    }
+
+    /// Number of the interrupt sources of the external interrupt controller.
+    pub fn interrupt_sources(&self) -> u32 {
+        match self.aia {
+            AiaMode::None => self.plic.sources,
+            AiaMode::Aplic | AiaMode::AplicImsic => APLIC_NUM_SOURCES,
+        }
+    }
+
+    /// The PLIC has room for the sources of the devices wired to it, the ones of the UARTs being checked with them.
+    fn validate_plic(&self) -> Result<(), String> {
+        let sources = self.plic.sources;
+        if !(1..=PLIC_MAX_SOURCES).contains(&sources) {
+            return Err(format!(
+                "invalid number of PLIC sources `{sources}`, expected 1 to {PLIC_MAX_SOURCES}"
+            ));
+        }
+        if self.aia != AiaMode::None {
+            return Ok(());
+        }
+        match self
+            .fixed_irqs()
+            .into_iter()
+            .find(|(_, irq)| *irq > sources)
+        {
+            Some((name, irq)) => Err(format!(
+                "{sources} PLIC sources leave out the irq {irq} of {name}"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The interrupt sources of the devices always there, or there with the IOMMU.
+    fn fixed_irqs(&self) -> Vec<(String, u32)> {
+        let mut irqs = vec![("rtc".to_string(), IRQ_RTC), ("dmac".to_string(), IRQ_DMAC)];
+        irqs.extend(
+            (0..VIRTIO_COUNT).map(|index| (format!("virtio{index}"), IRQ_VIRTIO + index as u32)),
+        );
+        if self.iommu {
+            irqs.push(("iommu".to_string(), IRQ_IOMMU));
+        }
+        irqs
+    }
+
+    /// Each UART has an interrupt source of the controller to itself, and a region overlapping no other one.
+    fn validate_uarts(&self, ram_end: u64) -> Result<(), String> {
+        let mut regions = vec![
+            ("mrom".to_string(), MROM_BASE, MROM_END),
+            ("test".to_string(), TEST_BASE, TEST_END),
+            ("rtc".to_string(), RTC_BASE, RTC_END),
+            ("syscon".to_string(), SYSCON_BASE, SYSCON_END),
+            ("clint".to_string(), CLINT_BASE, CLINT_END),
+            ("dmac".to_string(), DMAC_BASE, DMAC_END),
+            ("ram".to_string(), self.ram.base, ram_end),
+        ];
+        match self.aia {
+            AiaMode::None => regions.push(("plic".to_string(), PLIC_BASE, PLIC_END)),
+            AiaMode::Aplic | AiaMode::AplicImsic => {
+                regions.push(("aplic-m".to_string(), APLIC_M_BASE, APLIC_M_END));
+                regions.push(("aplic-s".to_string(), APLIC_S_BASE, APLIC_S_END));
+            }
+        }
+        if self.aia == AiaMode::AplicImsic {
+            regions.push(("imsic-m".to_string(), IMSIC_M_BASE, IMSIC_M_END));
+            regions.push(("imsic-s".to_string(), IMSIC_S_BASE, IMSIC_S_END));
+        }
+        regions.extend((0..VIRTIO_COUNT).map(|index| {
+            let base = VIRTIO_BASE + VIRTIO_SIZE * index as u64;
+            (format!("virtio{index}"), base, base + VIRTIO_SIZE)
+        }));
+        if self.iommu {
+            regions.push(("iommu".to_string(), IOMMU_BASE, IOMMU_END));
+        }
+        let mut irqs = self.fixed_irqs();
+
+        let sources = self.interrupt_sources();
+        for (index, uart) in self.uarts.iter().enumerate() {
+            let name = format!("uart{index}");
+            if uart.irq > sources {
+                return Err(format!(
+                    "invalid irq `{}` of {name}, expected at most {sources}",
+                    uart.irq
+                ));
+            }
+            if let Some((other, _)) = irqs.iter().find(|(_, irq)| *irq == uart.irq) {
+                return Err(format!("{name} has the irq {} of {other}", uart.irq));
+            }
+            let end = uart
+                .base
+                .checked_add(UART_SIZE)
+                .ok_or_else(|| format!("{name} extends past the end of the address space"))?;
+            if let Some((other, _, _)) = regions
+                .iter()
+                .find(|(_, base, other_end)| uart.base < *other_end && *base < end)
+            {
+                return Err(format!("{name} at {:#x} overlaps {other}", uart.base));
+            }
+            irqs.push((name.clone(), uart.irq));
+            regions.push((name, uart.base, end));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            aia: AiaMode::default(),
+            plic: PlicConfig::default(),
+            tval: TvalPolicy::default(),
+            lenient: false,
+            misaligned: MisalignedPolicy::default(),
+            big_endian: false,
+            tlb: TlbConfig::default(),
+            ram: RamConfig::default(),
+            unmapped: UnmappedPolicy::default(),
+            log_unmapped: false,
+            iommu: false,
+            timer: TimerConfig::default(),
+            syscon: SysconConfig::default(),
+            rtc_epoch: None,
+            uarts: vec![UartConfig::default()],
+            console_input: false,
+            disks: Vec::new(),
+        }
+    }
+}
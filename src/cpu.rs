@@ -1,18 +1,19 @@
 use std::pin::Pin;
 
-use arbitrary_int::u2;
+use arbitrary_int::{u1, u2};
 use bitbybit::bitenum;
 
-use crate::components::csr::{Csr, MIE, MIP, MSTATUS, SAPT};
-use crate::components::devices::uart::IRQ_UART;
+use crate::components::csr::{Csr, MIDELEG, MIE, MIP, MSTATUS, SAPT};
+use crate::components::devices::DeviceEvent;
 use crate::components::mmu::Mmu;
 use crate::components::registers::XRegisters;
-use crate::components::system_bus::MROM_BASE;
+use crate::components::system_bus::{AccessSource, MROM_BASE};
 use crate::components::trap::{Exception, Interrupt};
+use crate::config::{Config, UnmappedPolicy};
 use crate::instructions::decode_and_execute;
-use crate::util::{F, T};
+use crate::util::T;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[bitenum(u2, exhaustive = true)]
 pub enum PrivilegeMode {
     User = 0b00,
@@ -40,10 +41,18 @@ pub struct Cpu {
     //& The invalidation of a hart’s reservation when it executes an LR or SC imply that a hart can only hold one reservation at a time
     pub reservation: Option<u64>,
     pub is_idle: bool,
+    /// Supervisor-level external interrupt signal, from the interrupt controller
+    pub seip: bool,
+    pub config: Config,
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_config(&Config::default()).expect("the default machine is valid")
+    }
+
+    /// Build the machine, failing when its devices can't be set up as configured.
+    pub fn with_config(config: &Config) -> Result<Self, String> {
         let csr = Box::pin(Csr::new());
         let mstatus = &csr.csrs[MSTATUS];
         let sapt = &csr.csrs[SAPT];
@@ -53,93 +62,125 @@ impl Cpu {
             x_regs: XRegisters::new(),
             // start in firmware
             pc: MROM_BASE,
-            mmu: Mmu::new(mstatus, sapt, p_mode.as_ref().get_ref()),
+            mmu: Mmu::new(mstatus, sapt, p_mode.as_ref().get_ref(), config)?,
             csr: csr,
             p_mode: p_mode,
             reservation: None,
             is_idle: false,
+            seip: false,
+            config: config.clone(),
         };
-        cpu
+        Ok(cpu)
     }
-    pub fn run(&mut self) {
-        while self.mmu.bus.test.exit == 0 {
-            self.tick();
+    /// Run until a device stops the machine, returning the exit code requested by the software,
+    /// none when stopped on an error.
+    pub fn run(&mut self) -> Option<u64> {
+        loop {
+            match self.tick() {
+                Some(DeviceEvent::Exit(code)) => return Some(code),
+                Some(DeviceEvent::Stop) => return None,
+                Some(DeviceEvent::Reset) => self.reset(),
+                None => {}
+            }
         }
-        println!("Exited with {}", self.mmu.bus.test.exit);
     }
 
-    fn handle_exception(&mut self, e: Exception) {
-        println!("Exception {:?}", e.code());
-        e.take_trap(self);
+    /// Bring the whole machine back to its power-on state, restarting from the MROM.
+    pub fn reset(&mut self) {
+        self.x_regs = XRegisters::new();
+        self.pc = MROM_BASE;
+        // Overwritten in place, as the MMU points into them
+        *self.csr = Csr::new();
+        *self.p_mode = PrivilegeMode::Machine;
+        self.reservation = None;
+        self.is_idle = false;
+        self.seip = false;
+        self.mmu.reset();
     }
 
-    fn handle_interrupt(&mut self) {
-        match *self.p_mode {
-            PrivilegeMode::Machine => {
-                if self.csr.read_mstatus().mie() == F {
-                    // Machine mode interrupt disabled
-                    return;
-                }
-            }
-            PrivilegeMode::Supervisor => {
-                if self.csr.read_mstatus().sie() == F {
-                    // Supervisor mode interrupt disabled
-                    return;
-                }
-            }
-            _ => {}
-        }
+    /// `pc` is the address of the instruction that raised the exception.
+    fn handle_exception(&mut self, e: Exception, pc: u64) {
+        e.take_trap(self, pc);
+    }
 
-        let irq: u32 = if self.mmu.bus.uart0.is_interrupting() {
-            IRQ_UART
-        } else {
-            return;
-        };
-        self.mmu.bus.plic.set_pending(irq, true);
-        let pending = MIP::new_with_raw_value(self.csr.read(MIP) & self.csr.read(MIE));
+    /// Drive the interrupt-pending bits of mip from the interrupt lines of the devices.
+    fn update_interrupt_lines(&mut self) {
+        let (meip, seip) = self.mmu.bus.update_interrupts();
+        let clint = &self.mmu.bus.clint;
+        let (mtip, msip) = (clint.mtip(), clint.msip());
+        //& MEIP is read-only in mip, and is set and cleared by a platform-specific interrupt controller.
+        //& MTIP is read-only in mip, and is cleared by writing to the memory-mapped machine-mode timer compare register.
+        //& MSIP is read-only in mip, and is written by accesses to memory-mapped control registers.
+        let mut mip = MIP::new_with_raw_value(self.csr.read(MIP));
+        mip.set_meip(u1::new(meip as u8));
+        mip.set_mtip(u1::new(mtip as u8));
+        mip.set_msip(u1::new(msip as u8));
+        self.csr.write(MIP, mip.raw_value());
+        //& SEIP may be written by M-mode software to indicate to S-mode that an external interrupt is pending.
+        //& Additionally, the platform-level interrupt controller may generate supervisor-level external interrupts.
+        // The signal is kept apart from the writable bit, so software can't clear it.
+        self.seip = seip;
+    }
 
-        let interrupt: Option<Interrupt> = match pending {
-            mut p if p.meip() == T => {
-                p.set_meip(F);
-                Some(Interrupt::MachineExternal)
-            }
-            mut p if p.msip() == T => {
-                p.set_msip(F);
-                Some(Interrupt::MachineSoftware)
-            }
-            mut p if p.mtip() == T => {
-                p.set_mtip(F);
-                Some(Interrupt::MachineTimer)
-            }
-            mut p if p.seip() == T => {
-                p.set_seip(F);
-                Some(Interrupt::SupervisorExternal)
-            }
-            mut p if p.ssip() == T => {
-                p.set_ssip(F);
-                Some(Interrupt::SupervisorSoftware)
-            }
-            mut p if p.stip() == T => {
-                p.set_stip(F);
-                Some(Interrupt::SupervisorTimer)
-            }
-            _ => None,
-        };
-        // Writeback MIP
-        self.csr.write(MIP, pending.raw_value());
+    /// The interrupt-pending bits, with the supervisor external interrupt signal.
+    pub fn pending_interrupts(&self) -> u64 {
+        self.csr.read(MIP) | (self.seip as u64) << (Interrupt::SupervisorExternal as u64)
+    }
+
+    fn handle_interrupt(&mut self) {
+        self.update_interrupt_lines();
+        let pending = self.pending_interrupts() & self.csr.read(MIE);
+
+        //& The WFI instruction can also be executed when interrupts are disabled.
+        //& If an enabled interrupt is present or later becomes present, the hart resumes execution.
+        if pending != 0 {
+            self.is_idle = false;
+        }
 
+        //& Interrupts for higher-privilege modes, y>x, are always globally enabled regardless of the setting of the global yIE bit
+        //& for the higher-privilege mode. Interrupts for lower-privilege modes, w<x, are always globally disabled regardless of
+        //& the setting of any global wIE bit for the lower-privilege mode.
+        let p_mode = *self.p_mode;
+        let mstatus = self.csr.read_mstatus();
+        let m_enabled = p_mode < PrivilegeMode::Machine || mstatus.mie() == T;
+        let s_enabled = p_mode < PrivilegeMode::Supervisor
+            || (p_mode == PrivilegeMode::Supervisor && mstatus.sie() == T);
+
+        let mideleg = self.csr.read(MIDELEG);
+        let m_pending = if m_enabled { pending & !mideleg } else { 0 };
+        let s_pending = if s_enabled { pending & mideleg } else { 0 };
+        // Interrupts into M-mode are taken before the ones delegated to S-mode
+        let interrupt = Interrupt::highest(m_pending).or_else(|| Interrupt::highest(s_pending));
+
+        // The pending bits are levels, cleared by the source or by software for the writable ones,
+        // so they're left untouched on delivery.
         if let Some(interrupt) = interrupt {
             interrupt.take_trap(self);
         }
     }
 
-    pub fn tick(&mut self) {
+    /// Execute a cycle of the hart and the devices, returning the event requested by a device.
+    pub fn tick(&mut self) -> Option<DeviceEvent> {
+        let event = self.mmu.bus.tick();
+        // The accesses of the devices, made before the hart's
+        let event = event.or_else(|| self.report_unmapped(self.pc, *self.p_mode));
+        // Nothing more is executed once a device stopped the machine
+        if event.is_some() {
+            return event;
+        }
         self.handle_interrupt();
 
-        if self.is_idle {
-            return;
+        if !self.is_idle {
+            return self.step();
         }
+        None
+    }
 
+    /// Execute an instruction, returning the stop requested by its accesses to unmapped addresses.
+    fn step(&mut self) -> Option<DeviceEvent> {
+        // The pc is advanced before execution, keep the address of the instruction for the trap
+        let pc = self.pc;
+        let p_mode = *self.p_mode;
         //exception block
         let _ = (|| -> Result<(), Exception> {
             // IF - instruction fetch stage
@@ -151,11 +192,34 @@ impl Cpu {
             decode_and_execute(self, enc_inst)?;
             Ok(())
         })()
-        .map_err(|e| self.handle_exception(e));
+        .map_err(|e| self.handle_exception(e, pc));
+        self.report_unmapped(pc, p_mode)
+    }
+
+    /// Log the accesses to holes of the physical memory map,
+    /// the ones of the hart being made by the instruction at `pc`.
+    fn report_unmapped(&mut self, pc: u64, p_mode: PrivilegeMode) -> Option<DeviceEvent> {
+        let accesses = self.mmu.bus.take_unmapped();
+        if accesses.is_empty() {
+            return None;
+        }
+        for access in accesses {
+            let kind = if access.write { "write" } else { "read" };
+            let source = match &access.source {
+                AccessSource::Hart => format!("pc {pc:#x} in {p_mode:?} mode"),
+                AccessSource::Device(name) => format!("DMA of {name}"),
+            };
+            eprintln!(
+                "Unmapped {kind} of {} bytes at {:#x}, {source}",
+                access.len, access.address,
+            );
+        }
+        (self.config.unmapped == UnmappedPolicy::Stop).then_some(DeviceEvent::Stop)
     }
 
+    /// Print the registers on stderr, stdout being the console of the guest.
     pub fn dump_state(&self) {
-        println!("Xreg: {:?}", self.x_regs);
-        println!("PC: {}", self.pc);
+        eprintln!("Xreg: {:?}", self.x_regs);
+        eprintln!("PC: {}", self.pc);
     }
 }
@@ -0,0 +1,328 @@
+use crate::{
+    components::{
+        devices::{
+            aplic::{APLIC_NUM_SOURCES, APLIC_SIZE},
+            clint::CLINT_SIZE,
+            dmac::{DMAC_DEVICE_ID, DMAC_SIZE, IRQ_DMAC},
+            imsic::{IMSIC_FILE_SIZE, IMSIC_NUM_IDS},
+            iommu::{IOMMU_SIZE, IRQ_IOMMU},
+            rtc::{IRQ_RTC, RTC_SIZE},
+            syscon::SYSCON_SIZE,
+            uart::{UART_CLOCK, UART_SIZE},
+            virtio::{IRQ_VIRTIO, VIRTIO_COUNT, VIRTIO_DEVICE_ID, VIRTIO_SIZE},
+        },
+        system_bus::{
+            APLIC_M_BASE, APLIC_S_BASE, CLINT_BASE, DMAC_BASE, IMSIC_M_BASE, IMSIC_S_BASE,
+            IOMMU_BASE, PLIC_BASE, PLIC_END, RTC_BASE, SYSCON_BASE, VIRTIO_BASE,
+        },
+    },
+    config::{AiaMode, Config, SysconAction},
+};
+
+/* Phandles of the interrupt controllers */
+const CPU_INTC: u32 = 0x2;
+const PLIC: u32 = 0x3;
+const IMSIC_M: u32 = 0x3;
+const IMSIC_S: u32 = 0x4;
+const APLIC_M: u32 = 0x5;
+const APLIC_S: u32 = 0x6;
+/* Local interrupts of the hart, the causes of mip */
+const IRQ_M_SOFT: u32 = 0x3;
+const IRQ_M_TIMER: u32 = 0x7;
+const IRQ_S_EXT: u32 = 0x9;
+const IRQ_M_EXT: u32 = 0xb;
+/// Trigger type of the second cell of an APLIC interrupt specifier
+const IRQ_TYPE_LEVEL_HIGH: u32 = 0x4;
+
+/// Source text of a device tree, indented by the nesting of the nodes.
+struct Tree {
+    text: String,
+    depth: usize,
+    aia: AiaMode,
+}
+
+impl Tree {
+    fn line(&mut self, line: &str) {
+        self.text.push_str(&"\t".repeat(self.depth));
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn blank(&mut self) {
+        self.text.push('\n');
+    }
+
+    fn open(&mut self, node: &str) {
+        self.line(&format!("{node} {{"));
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.line("};");
+    }
+
+    /// The region of a node, with two cells for the address and two for the size.
+    fn reg(&mut self, base: u64, size: u64) {
+        self.line(&format!(
+            "reg = <{:#x} {:#x} {:#x} {:#x}>;",
+            base >> 32,
+            base as u32,
+            size >> 32,
+            size as u32
+        ));
+    }
+
+    /// The wiring of a device to the `irq` source of the external interrupt controller.
+    fn interrupts(&mut self, irq: u32) {
+        match self.aia {
+            AiaMode::None => {
+                self.line(&format!("interrupt-parent = <{PLIC:#x}>;"));
+                self.line(&format!("interrupts = <{irq:#x}>;"));
+            }
+            AiaMode::Aplic | AiaMode::AplicImsic => {
+                self.line(&format!("interrupt-parent = <{APLIC_S:#x}>;"));
+                self.line(&format!(
+                    "interrupts = <{irq:#x} {IRQ_TYPE_LEVEL_HIGH:#x}>;"
+                ));
+            }
+        }
+    }
+
+    /// A `syscon-poweroff` or `syscon-reboot` node, performing the action on the syscon block.
+    fn syscon_action(&mut self, name: &str, action: &SysconAction) {
+        self.open(name);
+        self.line(&format!("compatible = \"syscon-{name}\";"));
+        self.line("regmap = <&syscon>;");
+        self.line(&format!("offset = <{:#x}>;", action.offset));
+        self.line(&format!("value = <{:#x}>;", action.value));
+        if action.mask != u32::MAX {
+            self.line(&format!("mask = <{:#x}>;", action.mask));
+        }
+        self.close();
+    }
+}
+
+/// The device tree of the machine as configured, as a source for `dtc`.
+/// https://github.com/devicetree-org/devicetree-specification/tree/main/source
+pub fn generate(config: &Config) -> String {
+    let mut tree = Tree {
+        text: String::new(),
+        depth: 0,
+        aia: config.aia,
+    };
+    tree.line("/dts-v1/;");
+    tree.blank();
+    tree.open("/");
+    tree.line("#address-cells = <0x2>;");
+    tree.line("#size-cells = <0x2>;");
+    tree.line("compatible = \"riscv-virtio\";");
+    tree.line("model = \"riscv-virtio,qemu\";");
+    tree.blank();
+    cpus(&mut tree, config);
+    tree.blank();
+
+    tree.open(&format!("memory@{:x}", config.ram.base));
+    tree.line("device_type = \"memory\";");
+    tree.reg(config.ram.base, config.ram.size);
+    tree.close();
+
+    for (index, uart) in config.uarts.iter().enumerate() {
+        tree.blank();
+        tree.open(&format!("uart{index}: serial@{:x}", uart.base));
+        tree.line("compatible = \"ns16550a\";");
+        tree.reg(uart.base, UART_SIZE);
+        tree.line(&format!("clock-frequency = <{UART_CLOCK:#x}>;"));
+        tree.interrupts(uart.irq);
+        tree.close();
+    }
+
+    // The DMA controller of the emulator, without a Linux driver
+    tree.blank();
+    tree.open(&format!("dma-controller@{DMAC_BASE:x}"));
+    tree.line("compatible = \"risc-v,dmac\";");
+    tree.reg(DMAC_BASE, DMAC_SIZE);
+    tree.interrupts(IRQ_DMAC);
+    if config.iommu {
+        tree.line(&format!("iommus = <&iommu {DMAC_DEVICE_ID:#x}>;"));
+    }
+    tree.close();
+
+    tree.blank();
+    tree.open(&format!("rtc@{RTC_BASE:x}"));
+    tree.line("compatible = \"google,goldfish-rtc\";");
+    tree.reg(RTC_BASE, RTC_SIZE);
+    tree.interrupts(IRQ_RTC);
+    tree.close();
+
+    tree.blank();
+    tree.open(&format!("syscon: syscon@{SYSCON_BASE:x}"));
+    tree.line("compatible = \"syscon\";");
+    tree.reg(SYSCON_BASE, SYSCON_SIZE);
+    tree.close();
+    tree.blank();
+    tree.syscon_action("poweroff", &config.syscon.poweroff);
+    tree.blank();
+    tree.syscon_action("reboot", &config.syscon.reboot);
+
+    // Every transport, the ones without a disk reading as no device
+    for index in 0..VIRTIO_COUNT {
+        let base = VIRTIO_BASE + VIRTIO_SIZE * index as u64;
+        tree.blank();
+        tree.open(&format!("virtio_mmio@{base:x}"));
+        tree.line("compatible = \"virtio,mmio\";");
+        tree.reg(base, VIRTIO_SIZE);
+        tree.interrupts(IRQ_VIRTIO + index as u32);
+        if config.iommu {
+            tree.line(&format!(
+                "iommus = <&iommu {:#x}>;",
+                VIRTIO_DEVICE_ID + index as u32
+            ));
+        }
+        tree.close();
+    }
+
+    if config.iommu {
+        // https://github.com/torvalds/linux/blob/master/Documentation/devicetree/bindings/iommu/riscv,iommu.yaml
+        tree.blank();
+        tree.open(&format!("iommu: iommu@{IOMMU_BASE:x}"));
+        tree.line("compatible = \"riscv,iommu\";");
+        tree.reg(IOMMU_BASE, IOMMU_SIZE);
+        tree.interrupts(IRQ_IOMMU);
+        tree.line("#iommu-cells = <0x1>;");
+        tree.close();
+    }
+
+    tree.blank();
+    soc(&mut tree, config);
+    tree.close();
+    tree.text
+}
+
+/// The single hart, with its local interrupt controller.
+fn cpus(tree: &mut Tree, config: &Config) {
+    tree.open("cpus");
+    tree.line("#address-cells = <0x1>;");
+    tree.line("#size-cells = <0x0>;");
+    tree.line(&format!(
+        "timebase-frequency = <{:#x}>;",
+        config.timer.timebase_frequency
+    ));
+    tree.blank();
+    tree.open("cpu-map");
+    tree.open("cluster0");
+    tree.open("core0");
+    tree.line("cpu = <0x1>;");
+    tree.close();
+    tree.close();
+    tree.close();
+    tree.blank();
+    tree.open("cpu@0");
+    tree.line("phandle = <0x1>;");
+    tree.line("device_type = \"cpu\";");
+    tree.line("reg = <0x0>;");
+    tree.line("compatible = \"riscv\";");
+    match config.aia {
+        AiaMode::None => tree.line("riscv,isa = \"rv64imasu\";"),
+        AiaMode::Aplic | AiaMode::AplicImsic => {
+            tree.line("riscv,isa = \"rv64ima_zicsr_zifencei_smaia_ssaia\";")
+        }
+    }
+    tree.line("mmu-type = \"riscv,sv39\";");
+    tree.blank();
+    tree.open("interrupt-controller");
+    tree.line(&format!("phandle = <{CPU_INTC:#x}>;"));
+    tree.line("#interrupt-cells = <0x1>;");
+    tree.line("interrupt-controller;");
+    tree.line("compatible = \"riscv,cpu-intc\";");
+    tree.close();
+    tree.close();
+    tree.close();
+}
+
+/// The interrupt controllers.
+/// https://github.com/torvalds/linux/tree/master/Documentation/devicetree/bindings/interrupt-controller
+fn soc(tree: &mut Tree, config: &Config) {
+    tree.open("soc");
+    tree.line("#address-cells = <0x2>;");
+    tree.line("#size-cells = <0x2>;");
+    tree.line("compatible = \"simple-bus\";");
+    tree.line("ranges;");
+
+    // Machine software and timer interrupts of the hart
+    tree.blank();
+    tree.open(&format!("clint@{CLINT_BASE:x}"));
+    tree.line(&format!(
+        "interrupts-extended = <{CPU_INTC:#x} {IRQ_M_SOFT:#x} {CPU_INTC:#x} {IRQ_M_TIMER:#x}>;"
+    ));
+    tree.reg(CLINT_BASE, CLINT_SIZE);
+    tree.line("compatible = \"sifive,clint0\", \"riscv,clint0\";");
+    tree.close();
+
+    if config.aia == AiaMode::None {
+        tree.blank();
+        tree.open(&format!("plic@{PLIC_BASE:x}"));
+        tree.line(&format!("phandle = <{PLIC:#x}>;"));
+        tree.line("#interrupt-cells = <0x1>;");
+        tree.line("#address-cells = <0x0>;");
+        tree.line(&format!("riscv,ndev = <{:#x}>;", config.plic.sources));
+        tree.reg(PLIC_BASE, PLIC_END - PLIC_BASE);
+        tree.line("interrupt-controller;");
+        tree.line("compatible = \"riscv,plic0\";");
+        tree.line(&format!(
+            "interrupts-extended = <{CPU_INTC:#x} {IRQ_M_EXT:#x} {CPU_INTC:#x} {IRQ_S_EXT:#x}>;"
+        ));
+        tree.close();
+        tree.close();
+        return;
+    }
+
+    let msi = config.aia == AiaMode::AplicImsic;
+    if msi {
+        for (base, phandle, irq) in [
+            (IMSIC_M_BASE, IMSIC_M, IRQ_M_EXT),
+            (IMSIC_S_BASE, IMSIC_S, IRQ_S_EXT),
+        ] {
+            tree.blank();
+            tree.open(&format!("imsics@{base:x}"));
+            tree.line(&format!("phandle = <{phandle:#x}>;"));
+            tree.line("riscv,ipi-id = <0x1>;");
+            tree.line(&format!("riscv,num-ids = <{IMSIC_NUM_IDS:#x}>;"));
+            tree.reg(base, IMSIC_FILE_SIZE);
+            tree.line(&format!("interrupts-extended = <{CPU_INTC:#x} {irq:#x}>;"));
+            tree.line("msi-controller;");
+            tree.line("#msi-cells = <0x0>;");
+            tree.line("interrupt-controller;");
+            tree.line("#interrupt-cells = <0x0>;");
+            tree.line("compatible = \"riscv,imsics\";");
+            tree.close();
+        }
+    }
+    // The M-level domain delegates every source to the S-level one
+    let domains = [
+        (APLIC_M_BASE, APLIC_M, IMSIC_M, IRQ_M_EXT),
+        (APLIC_S_BASE, APLIC_S, IMSIC_S, IRQ_S_EXT),
+    ];
+    for (base, phandle, imsic, irq) in domains {
+        tree.blank();
+        tree.open(&format!("aplic@{base:x}"));
+        tree.line(&format!("phandle = <{phandle:#x}>;"));
+        if phandle == APLIC_M {
+            tree.line(&format!(
+                "riscv,delegation = <{APLIC_S:#x} 0x1 {APLIC_NUM_SOURCES:#x}>;"
+            ));
+            tree.line(&format!("riscv,children = <{APLIC_S:#x}>;"));
+        }
+        tree.line(&format!("riscv,num-sources = <{APLIC_NUM_SOURCES:#x}>;"));
+        tree.reg(base, APLIC_SIZE);
+        match msi {
+            true => tree.line(&format!("msi-parent = <{imsic:#x}>;")),
+            false => tree.line(&format!("interrupts-extended = <{CPU_INTC:#x} {irq:#x}>;")),
+        }
+        tree.line("interrupt-controller;");
+        tree.line("#interrupt-cells = <0x2>;");
+        tree.line("compatible = \"riscv,aplic\";");
+        tree.close();
+    }
+    tree.close();
+}
@@ -1,9 +1,14 @@
 use crate::{
-    components::{mmu::Size, trap::Exception},
+    components::{
+        mmu::{MemoryAccessType, Size},
+        pma::AmoClass,
+        trap::Exception,
+    },
+    config::MisalignedPolicy,
     cpu::Cpu,
     instructions::types::ARType,
 };
-use arbitrary_int::{u3, u5};
+use arbitrary_int::u5;
 use std::ops::{BitAnd, BitOr, BitXor};
 
 const LR: u8 = 0x2;
@@ -23,27 +28,30 @@ const AMMO_D: u8 = 0x3;
 
 //& Zalrsc and Zaamo extensions requires that the address held in rs1 be naturally aligned to the size of the operand.
 //& If the address is not naturally aligned, an address-misaligned exception or an access-fault exception will be generated.
-fn check_alignment(address: u64, size: Size) -> Result<(), Exception> {
-    if address % (size as u64) != 0 {
-        return Err(Exception::LoadAddressMisaligned);
+// Misaligned AMOs are never performed, even when the hardware supports misaligned loads and stores,
+// so it raises an access fault there, as there is nothing for the software to emulate.
+// LR raises the load exceptions, SC and the AMOs the store/AMO ones.
+fn check_alignment(
+    cpu: &Cpu,
+    address: u64,
+    size: Size,
+    access: MemoryAccessType,
+) -> Result<(), Exception> {
+    if address % (size as u64) == 0 {
+        return Ok(());
+    }
+    match cpu.config.misaligned {
+        MisalignedPolicy::Trap => Err(access.misaligned(address)),
+        MisalignedPolicy::Hardware => Err(access.access_fault(address)),
     }
-    Ok(())
 }
 
-fn amo_load_value(cpu: &mut Cpu, funct3: u3, address: u64) -> Result<(u64, Size), Exception> {
-    match funct3.value() {
-        AMMO_W => {
-            const SIZE: Size = Size::WORD;
-            check_alignment(address, SIZE)?;
-            //sign extend
-            Ok((cpu.mmu.load(address, SIZE)? as i32 as u64, SIZE))
-        }
-        AMMO_D => {
-            const SIZE: Size = Size::DWORD;
-            check_alignment(address, SIZE)?;
-            Ok((cpu.mmu.load(address, SIZE)?, SIZE))
-        }
-        _ => return Err(Exception::IllegalInstruction),
+fn amo_load_value(cpu: &mut Cpu, size: Size, address: u64) -> Result<u64, Exception> {
+    let value = cpu.mmu.load(address, size)?;
+    match size {
+        //sign extend
+        Size::WORD => Ok(value as i32 as u64),
+        _ => Ok(value),
     }
 }
 pub fn handle_amo(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
@@ -56,33 +64,57 @@ pub fn handle_amo(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
         rtype.funct3(),
         rtype.funct5(),
     );
+    let size = match funct3.value() {
+        AMMO_W => Size::WORD,
+        AMMO_D => Size::DWORD,
+        _ => return Err(Exception::IllegalInstruction(instr)),
+    };
+
+    let class = match funct5.value() {
+        AMOSWAP => Some(AmoClass::AmoSwap),
+        AMOXOR | AMOAND | AMOOR => Some(AmoClass::AmoLogical),
+        AMOADD | AMOMIN | AMOMAX | AMOMINU | AMOMAXU => Some(AmoClass::AmoArithmetic),
+        _ => None,
+    };
+    //& AMOs to a region that doesn't support the operation raise a store/AMO access fault.
+    if let Some(class) = class {
+        let address = cpu.x_regs.read(rs1);
+        check_alignment(cpu, address, size, MemoryAccessType::Store)?;
+        cpu.mmu.check_amo(address, class)?;
+    }
 
     match funct5.value() {
-        LR => instr_lr(cpu, rd, rs1, funct3)?,
-        SC => instr_sc(cpu, rd, rs1, rs2, funct3)?,
-        AMOSWAP => amo_op(cpu, rd, rs1, rs2, funct3, |_, rhs| rhs)?,
-        AMOADD => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.wrapping_add(rhs))?,
-        AMOXOR => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.bitxor(rhs))?,
-        AMOAND => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.bitand(rhs))?,
-        AMOOR => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.bitor(rhs))?,
-        AMOMIN => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| {
+        // rs2 is reserved for LR, only ignored in lenient mode
+        LR if rs2.value() != 0 && !cpu.config.lenient => {
+            return Err(Exception::IllegalInstruction(instr));
+        }
+        LR => instr_lr(cpu, rd, rs1, size)?,
+        SC => instr_sc(cpu, rd, rs1, rs2, size)?,
+        AMOSWAP => amo_op(cpu, rd, rs1, rs2, size, |_, rhs| rhs)?,
+        AMOADD => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.wrapping_add(rhs))?,
+        AMOXOR => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.bitxor(rhs))?,
+        AMOAND => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.bitand(rhs))?,
+        AMOOR => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.bitor(rhs))?,
+        AMOMIN => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| {
             (lhs as i64).min(rhs as i64) as u64
         })?,
-        AMOMAX => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| {
+        AMOMAX => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| {
             (lhs as i64).max(rhs as i64) as u64
         })?,
-        AMOMINU => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.min(rhs))?,
-        AMOMAXU => amo_op(cpu, rd, rs1, rs2, funct3, |lhs, rhs| lhs.max(rhs))?,
-        _ => return Err(Exception::IllegalInstruction),
+        AMOMINU => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.min(rhs))?,
+        AMOMAXU => amo_op(cpu, rd, rs1, rs2, size, |lhs, rhs| lhs.max(rhs))?,
+        _ => return Err(Exception::IllegalInstruction(instr)),
     }
 
     Ok(())
 }
 
-fn instr_lr(cpu: &mut Cpu, rd: u5, rs1: u5, funct3: u3) -> Result<(), Exception> {
+fn instr_lr(cpu: &mut Cpu, rd: u5, rs1: u5, size: Size) -> Result<(), Exception> {
     //Load the data value from the address in rs1
     let address = cpu.x_regs.read(rs1);
-    let (value, _) = amo_load_value(cpu, funct3, address)?;
+    check_alignment(cpu, address, size, MemoryAccessType::Load)?;
+    cpu.mmu.check_reservable(address, MemoryAccessType::Load)?;
+    let value = amo_load_value(cpu, size, address)?;
 
     cpu.reservation = Some(address);
     cpu.x_regs.write(rd, value);
@@ -90,15 +122,11 @@ fn instr_lr(cpu: &mut Cpu, rd: u5, rs1: u5, funct3: u3) -> Result<(), Exception>
     Ok(())
 }
 
-fn instr_sc(cpu: &mut Cpu, rd: u5, rs1: u5, rs2: u5, funct3: u3) -> Result<(), Exception> {
+fn instr_sc(cpu: &mut Cpu, rd: u5, rs1: u5, rs2: u5, size: Size) -> Result<(), Exception> {
     let address = cpu.x_regs.read(rs1);
 
-    let size = match funct3.value() {
-        AMMO_W => Size::WORD,
-        AMMO_D => Size::DWORD,
-        _ => return Err(Exception::IllegalInstruction),
-    };
-    check_alignment(address, size)?;
+    check_alignment(cpu, address, size, MemoryAccessType::Store)?;
+    cpu.mmu.check_reservable(address, MemoryAccessType::Store)?;
 
     let success = match cpu.reservation.is_some_and(|raddr| raddr == address) {
         true => {
@@ -114,12 +142,13 @@ fn instr_sc(cpu: &mut Cpu, rd: u5, rs1: u5, rs2: u5, funct3: u3) -> Result<(), E
     Ok(())
 }
 
-fn amo_op<F>(cpu: &mut Cpu, rd: u5, rs1: u5, rs2: u5, funct3: u3, op: F) -> Result<(), Exception>
+fn amo_op<F>(cpu: &mut Cpu, rd: u5, rs1: u5, rs2: u5, size: Size, op: F) -> Result<(), Exception>
 where
     F: Fn(u64, u64) -> u64,
 {
+    // Alignment and support of the region are checked by the decoder
     let address = cpu.x_regs.read(rs1);
-    let (value, size) = amo_load_value(cpu, funct3, address)?;
+    let value = amo_load_value(cpu, size, address)?;
 
     let new_val = op(value, cpu.x_regs.read(rs2));
     cpu.mmu.store(address, new_val, size)?;
@@ -1,4 +1,4 @@
-use crate::{cpu::Cpu, instructions::types::BType};
+use crate::{components::trap::Exception, cpu::Cpu, instructions::types::BType};
 
 const BEQ: u8 = 0x0;
 const BNE: u8 = 0x1;
@@ -7,7 +7,7 @@ const BGE: u8 = 0x5;
 const BLTU: u8 = 0x6;
 const BGEU: u8 = 0x7;
 
-pub fn handle_branch(cpu: &mut Cpu, instr: u32) {
+pub fn handle_branch(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let btype = BType::new_with_raw_value(instr);
     let (rs1, rs2, imm, funct3) = (btype.rs1(), btype.rs2(), btype.imm(), btype.funct3());
 
@@ -22,9 +22,12 @@ pub fn handle_branch(cpu: &mut Cpu, instr: u32) {
         BGE => (lhs as i64).ge(&(rhs as i64)),
         BLTU => lhs.lt(&rhs),
         BGEU => lhs.ge(&rhs),
-        _ => false,
+        // funct3 2 and 3 are reserved
+        _ if cpu.config.lenient => false,
+        _ => return Err(Exception::IllegalInstruction(instr)),
     };
     if take_branch {
         cpu.pc = cpu.pc.wrapping_sub(4).wrapping_add(imm.value() as u64);
     }
+    Ok(())
 }
@@ -29,14 +29,19 @@ const JALR: u8 = 0x67 >> 2;
 const JAL: u8 = 0x6f >> 2;
 const SYSTEM: u8 = 0x73 >> 2;
 
+// MISC-MEM funct3
+const FENCE: u8 = 0x0;
+const FENCE_I: u8 = 0x1;
+
 pub fn decode_and_execute(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
+    //& The base RISC-V ISA has fixed-length 32-bit instructions, with the lowest two bits set to 11.
+    if instr & 0x3 != 0x3 && !cpu.config.lenient {
+        return Err(Exception::IllegalInstruction(instr));
+    }
     let opcode = ((instr >> 2) & 0x1f) as u8;
     match opcode {
         LOAD => handle_load(cpu, instr)?,
-        MISC_MEM => {
-            // Zifencei not supported,
-            // treated as a NO-OP as this implementation is cache-less
-        }
+        MISC_MEM => handle_misc_mem(cpu, instr)?,
         OP_IMM => handle_op_imm(cpu, instr)?,
         OP_IMMW => handle_op_immw(cpu, instr)?,
         STORE => handle_store(cpu, instr)?,
@@ -45,16 +50,27 @@ pub fn decode_and_execute(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
         OPW => handle_opw(cpu, instr)?,
         LUI => instr_lui(cpu, instr),
         AUIPC => instr_auipc(cpu, instr),
-        BRANCH => handle_branch(cpu, instr),
-        JALR => instr_jalr(cpu, instr),
+        BRANCH => handle_branch(cpu, instr)?,
+        JALR => instr_jalr(cpu, instr)?,
         JAL => instr_jal(cpu, instr),
         SYSTEM => handle_system(cpu, instr)?,
-        _ => return Err(Exception::IllegalInstruction),
+        _ => return Err(Exception::IllegalInstruction(instr)),
     }
     Ok(())
 }
 
 /* Single class instructions */
+fn handle_misc_mem(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
+    let itype = IType::new_with_raw_value(instr);
+    match itype.funct3().value() {
+        // FENCE, and FENCE.I from Zifencei,
+        // treated as a NO-OP as this implementation is sequential and cache-less
+        FENCE | FENCE_I => Ok(()),
+        _ if cpu.config.lenient => Ok(()),
+        _ => Err(Exception::IllegalInstruction(instr)),
+    }
+}
+
 fn instr_lui(cpu: &mut Cpu, instr: u32) {
     let utype = UType::new_with_raw_value(instr);
     let (rd, imm) = (utype.rd(), utype.imm());
@@ -88,9 +104,13 @@ fn instr_jal(cpu: &mut Cpu, instr: u32) {
         .wrapping_sub(4)
         .wrapping_add(imm.value() as u64);
 }
-fn instr_jalr(cpu: &mut Cpu, instr: u32) {
+fn instr_jalr(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let itype = IType::new_with_raw_value(instr);
     let (rd, rs1, imm) = (itype.rd(), itype.rs1(), itype.imm());
+    // funct3 is reserved other than 0
+    if itype.funct3().value() != 0 && !cpu.config.lenient {
+        return Err(Exception::IllegalInstruction(instr));
+    }
 
     cpu.x_regs.write(rd, cpu.pc);
     cpu.pc = cpu
@@ -99,4 +119,5 @@ fn instr_jalr(cpu: &mut Cpu, instr: u32) {
         .wrapping_add(imm.value() as u64)
         //clear the lsb
         .bitand(!1);
+    Ok(())
 }
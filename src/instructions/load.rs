@@ -17,6 +17,10 @@ pub fn handle_load(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let itype = IType::new_with_raw_value(instr);
     let (rd, funct3, rs1, imm) = (itype.rd(), itype.funct3(), itype.rs1(), itype.imm());
 
+    // funct3=7 would be a LDU, which doesn't exist
+    if funct3.value() == 0x7 && !cpu.config.lenient {
+        return Err(Exception::IllegalInstruction(instr));
+    }
     let size = 1 << (funct3.value() & 0x3);
     let addr = cpu.x_regs.read(rs1).wrapping_add(imm.value() as u64);
     let val = cpu.mmu.load(
@@ -30,7 +34,7 @@ pub fn handle_load(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
         LB => val as i8 as u64,
         LH => val as i16 as u64,
         LW => val as i32 as u64,
-        //@Note: the 0x7 case is ignored and treated like a normal unsign value,
+        //@Note: in lenient mode the 0x7 case is treated like a normal unsign value,
         //like a potential LDU that doesn't exist..
         _ => val,
     };
@@ -92,7 +92,7 @@ pub fn handle_op(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
                 lhs.wrapping_rem(rhs)
             }
         }
-        _ => return Err(Exception::IllegalInstruction),
+        _ => return Err(Exception::IllegalInstruction(instr)),
     };
 
     cpu.x_regs.write(rd, value);
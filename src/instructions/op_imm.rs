@@ -26,19 +26,29 @@ pub fn handle_op_imm(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
         XORI => lhs.bitxor(rhs),
         ORI => lhs.bitor(rhs),
         ANDI => lhs.bitand(rhs),
+        // imm[11:6] are reserved, only ignored in lenient mode
+        SLLI if (rhs >> 6) & 0x3f != 0 && !cpu.config.lenient => {
+            return Err(Exception::IllegalInstruction(instr));
+        }
         SLLI => lhs.shl(shamt),
-        SRLI_SRAI => handle_srli_srai(lhs, imm, shamt)?,
-        _ => return Err(Exception::IllegalInstruction),
+        SRLI_SRAI => handle_srli_srai(lhs, imm, shamt, cpu.config.lenient)
+            .ok_or(Exception::IllegalInstruction(instr))?,
+        _ => return Err(Exception::IllegalInstruction(instr)),
     };
     cpu.x_regs.write(rd, value);
     Ok(())
 }
 
-fn handle_srli_srai(lhs: u64, imm: i12, shamt: u8) -> Result<u64, Exception> {
-    //discard the shamt and unneeded bits
-    match imm.value() >> 10 {
-        0x0 => Ok(lhs.shr(shamt)),
-        0x1 => Ok((lhs as i64).shr(shamt) as u64),
-        _ => return Err(Exception::IllegalInstruction),
+fn handle_srli_srai(lhs: u64, imm: i12, shamt: u8, lenient: bool) -> Option<u64> {
+    //discard the shamt
+    let funct6 = (imm.value() >> 6) & 0x3f;
+    // only imm[10] tells the shift apart, the other bits are reserved
+    if funct6 & !0x10 != 0 && !lenient {
+        return None;
+    }
+    match funct6 >> 4 {
+        0x0 => Some(lhs.shr(shamt)),
+        0x1 => Some((lhs as i64).shr(shamt) as u64),
+        _ => None,
     }
 }
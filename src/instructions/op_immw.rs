@@ -16,19 +16,29 @@ pub fn handle_op_immw(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let shamt = (rhs & 0x1f) as u8;
     let value = match funct3.value() {
         ADDIW => lhs.wrapping_add(rhs),
+        // imm[11:5] are reserved, only ignored in lenient mode
+        SLLIW if (rhs >> 5) & 0x7f != 0 && !cpu.config.lenient => {
+            return Err(Exception::IllegalInstruction(instr));
+        }
         SLLIW => lhs.shl(shamt),
-        SRLIW_SRAIW => handle_srliw_sraiw(lhs, imm, shamt)?,
-        _ => return Err(Exception::IllegalInstruction),
+        SRLIW_SRAIW => handle_srliw_sraiw(lhs, imm, shamt, cpu.config.lenient)
+            .ok_or(Exception::IllegalInstruction(instr))?,
+        _ => return Err(Exception::IllegalInstruction(instr)),
     };
 
     cpu.x_regs.write(rd, value as u64);
     Ok(())
 }
-fn handle_srliw_sraiw(lhs: u32, imm: i12, shamt: u8) -> Result<u32, Exception> {
-    //discard the shamt and unneeded bits
-    match imm.value() >> 10 {
-        0x0 => Ok(lhs.shr(shamt)),
-        0x1 => Ok((lhs as i32).shr(shamt) as u32),
-        _ => return Err(Exception::IllegalInstruction),
+fn handle_srliw_sraiw(lhs: u32, imm: i12, shamt: u8, lenient: bool) -> Option<u32> {
+    //discard the shamt
+    let funct7 = (imm.value() >> 5) & 0x7f;
+    // only imm[10] tells the shift apart, the other bits are reserved
+    if funct7 & !0x20 != 0 && !lenient {
+        return None;
+    }
+    match funct7 >> 5 {
+        0x0 => Some(lhs.shr(shamt)),
+        0x1 => Some((lhs as i32).shr(shamt) as u32),
+        _ => None,
     }
 }
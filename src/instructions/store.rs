@@ -14,8 +14,10 @@ pub fn handle_store(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let (funct3, rs1, rs2, imm) = (stype.funct3(), stype.rs1(), stype.rs2(), stype.imm());
 
     // Can compute the size direcly..
-    // Ignore the possible values greater than SD, normally it should throw for an unknown instruction
-    // but do we really need to?
+    // The values greater than SD are reserved, only ignored in lenient mode
+    if funct3.value() > _SD && !cpu.config.lenient {
+        return Err(Exception::IllegalInstruction(instr));
+    }
     let size = 1 << (funct3.value() & 0x3);
     let size = Size::from_unchecked(size);
 
@@ -1,10 +1,18 @@
 use crate::{
     components::{
-        csr::{MEPC, SEPC},
-        trap::Exception,
+        csr::{
+            MENVCFG, MEPC, MIDELEG, MIE, MIP, MIREG, MISELECT, MSTATEEN0, MSTATEEN0H, MSTATEEN3,
+            MSTATEEN3H, MSTATUS, MTOPEI, MTOPI, MVIEN, MVIP, SAPT, SATP_MODE_BARE, SATP_MODE_SV39,
+            SCONTEXT, SENVCFG, SEPC, SIE, SIP, SIREG, SISELECT, SSTATEEN0, SSTATEEN3, SSTATUS,
+            STATEEN_SE, STATEEN0_AIA, STATEEN0_CONTEXT, STATEEN0_CSRIND, STATEEN0_ENVCFG,
+            STATEEN0_IMSIC, STATEEN0_SE0, STOPEI, STOPI, Sapt,
+        },
+        devices::imsic::{IPRIO_BASE, IPRIO_END, InterruptFile},
+        trap::{Exception, Interrupt},
     },
     cpu::{Cpu, PrivilegeMode},
-    instructions::types::IType,
+    instructions::types::{IType, RType},
+    util::T,
 };
 use arbitrary_int::{u1, u2, u3, u5};
 
@@ -21,12 +29,54 @@ pub const _CSRRSI: u8 = 0x6;
 pub const _CSRRCI: u8 = 0x7;
 /// Used to return from a trap taken into S-mode
 pub const SRET: u16 = 0x102;
+/// Used to order the page table updates against the implicit accesses, funct7 of the immediate
+pub const SFENCE_VMA: u16 = 0x09;
 /// Used to return from a trap taken into M-mode
 pub const MRET: u16 = 0x302;
 pub const WFI: u16 = 0x105;
 pub const ECALL: u16 = 0x0;
 pub const EBREAK: u16 = 0x1;
 
+/// Bits of mvien that are writable: SSIP and SEIP
+const MVIEN_MASK: u64 = 1 << 1 | 1 << 9;
+/// Bits of mvip that are implemented: SSIP, STIP and SEIP
+const MVIP_MASK: u64 = 1 << 1 | 1 << 5 | 1 << 9;
+/// Bits of mie that are implemented: the M and S-level software, timer and external interrupts
+const MIE_MASK: u64 = 1 << 1 | 1 << 3 | 1 << 5 | 1 << 7 | 1 << 9 | 1 << 11;
+/// Bits of mip that are writable: SSIP, STIP and SEIP, the others being driven by the devices
+const MIP_MASK: u64 = 1 << 1 | 1 << 5 | 1 << 9;
+/// Bits of sip that are writable: SSIP
+const SIP_MASK: u64 = 1 << 1;
+/// Bits of mstatus visible in sstatus: SIE, SPIE, UBE, SPP, VS, FS, XS, SUM, MXR, UXL and SD
+const SSTATUS_MASK: u64 = 1 << 1
+    | 1 << 5
+    | 1 << 6
+    | 1 << 8
+    | 3 << 9
+    | 3 << 13
+    | 3 << 15
+    | 1 << 18
+    | 1 << 19
+    | 3 << 32
+    | 1 << 63;
+/// Bits of sstatus that are writable: SIE, SPIE, SPP, SUM and MXR
+const SSTATUS_WRITE_MASK: u64 = 1 << 1 | 1 << 5 | 1 << 8 | 1 << 18 | 1 << 19;
+/// Endianness control bits of mstatus: MBE, SBE and UBE
+const MSTATUS_BE_MASK: u64 = 1 << 37 | 1 << 36 | 1 << 6;
+/// Bits of mstateen0 that are implemented
+const MSTATEEN0_MASK: u64 = STATEEN0_SE0
+    | STATEEN0_ENVCFG
+    | STATEEN0_CSRIND
+    | STATEEN0_AIA
+    | STATEEN0_IMSIC
+    | STATEEN0_CONTEXT;
+/// Bits of sstateen0-3 that are implemented.
+/// None, as they only control user-level state (Zcmt, Zfinx, custom) that isn't implemented
+const SSTATEEN_MASK: u64 = 0;
+/// Bits of menvcfg and senvcfg that are implemented: FIOM,
+/// which has no effect as there are no fences with I/O ordering to strengthen
+const ENVCFG_MASK: u64 = 1;
+
 //@Note: in theory those instructions should execute atomically
 pub fn handle_system(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
     let itype = IType::new_with_raw_value(instr);
@@ -37,28 +87,37 @@ pub fn handle_system(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
 
     //handle the funct3=0 subclass
     if funct3.value() == 0 {
+        // SFENCE.VMA carries rs2 in the low bits of the immediate
+        if csr_addr >> 5 == SFENCE_VMA && rd.value() == 0 {
+            return instr_sfence_vma(cpu, instr);
+        }
+        // rs1 and rd are reserved for the rest of the funct3=0 instructions
+        if (rd.value() != 0 || rsi.value() != 0) && !cpu.config.lenient {
+            return Err(Exception::IllegalInstruction(instr));
+        }
         match csr_addr {
-            ECALL => instr_ecall(cpu)?,
+            ECALL => instr_ecall(cpu, instr)?,
             EBREAK => instr_ebreak()?,
             SRET => instr_sret(cpu),
             MRET => instr_mret(cpu),
             WFI => instr_wfi(cpu),
-            _ => {}
+            _ if cpu.config.lenient => {}
+            _ => return Err(Exception::IllegalInstruction(instr)),
         }
         return Ok(());
     }
 
-    handle_zicsr(cpu, rd, rsi, csr_addr, funct3)
+    handle_zicsr(cpu, instr, rd, rsi, csr_addr, funct3)
 }
 
 fn handle_zicsr(
     cpu: &mut Cpu,
+    instr: u32,
     rd: u5,
     rsi: u5,
     csr_addr: u16,
     funct3: u3,
 ) -> Result<(), Exception> {
-    // TODO: handle the cases of rs1=x0 (?)
     // handle Zicsr extension
     // check for bit[2]
     let rs_val = if (funct3.value() >> 2) != 0 {
@@ -68,35 +127,279 @@ fn handle_zicsr(
         //rsi is a reg
         cpu.x_regs.read(rsi)
     };
-    let csr_val = cpu.csr.read(csr_addr as usize);
-    match funct3.value() & 0x3 {
+    let addr = csr_addr as usize;
+    //& CSRRW always writes the CSR, CSRRS and CSRRC only when rs1 isn't x0 (or uimm isn't 0)
+    let writes = (funct3.value() & 0x3) == CSRRW || rsi.value() != 0;
+    check_csr_access(cpu, instr, addr, writes)?;
+
+    let csr_val = match funct3.value() & 0x3 {
         CSRRW => {
+            //& If rd=x0, then the instruction shall not read the CSR
+            //& and shall not cause any of the side effects that might occur on a CSR read.
+            let csr_val = if rd.value() != 0 {
+                read_csr(cpu, instr, addr)?
+            } else {
+                0
+            };
             //swap the values
-            cpu.csr.write(csr_addr as usize, rs_val);
+            write_csr(cpu, instr, addr, rs_val)?;
+            csr_val
         }
-        CSRRS => {
-            //reg_val act as a set bit mask
-            cpu.csr.write(csr_addr as usize, csr_val | rs_val);
-        }
-        CSRRC => {
-            //reg_val act as a clear bit mask
-            cpu.csr.write(csr_addr as usize, csr_val & (!rs_val));
+        op @ (CSRRS | CSRRC) => {
+            let csr_val = read_csr(cpu, instr, addr)?;
+            //& For both CSRRS and CSRRC, if rs1=x0, then the instruction will not write to the CSR at all.
+            //& the same for the immediate forms when uimm=0
+            if rsi.value() != 0 {
+                //& Only the software-writable SEIP bit participates in the read-modify-write sequence
+                //& of a CSRRS or CSRRC instruction.
+                let old = match addr {
+                    MIP => cpu.csr.read(MIP),
+                    _ => csr_val,
+                };
+                let value = if op == CSRRS {
+                    //reg_val act as a set bit mask
+                    old | rs_val
+                } else {
+                    //reg_val act as a clear bit mask
+                    old & (!rs_val)
+                };
+                write_csr(cpu, instr, addr, value)?;
+            }
+            csr_val
         }
         _ => {
             // The hypervisor instructions would land here for now, as there isn't a planned support for it.
-            return Err(Exception::IllegalInstruction);
+            return Err(Exception::IllegalInstruction(instr));
         }
-    }
+    };
     cpu.x_regs.write(rd, csr_val);
     Ok(())
 }
 
-fn instr_ecall(cpu: &Cpu) -> Result<(), Exception> {
+/// Check the privilege and state-enable rules for an access to the CSR.
+fn check_csr_access(cpu: &Cpu, instr: u32, addr: usize, writes: bool) -> Result<(), Exception> {
+    //& The top two bits (csr[11:10]) indicate whether the register is read/write (00, 01, or 10) or read-only (11).
+    //& Attempts to write a read-only register raise an illegal-instruction exception.
+    if writes && (addr >> 10) == 0b11 {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    //& The next two bits (csr[9:8]) encode the lowest privilege level that can access the CSR.
+    //& Attempts to access a CSR without appropriate privilege level raise illegal-instruction exceptions.
+    let p_mode = *cpu.p_mode;
+    if (p_mode as u8) < ((addr >> 8) & 0b11) as u8 {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    // The high halves of mstateen only exist for RV32
+    if (MSTATEEN0H..=MSTATEEN3H).contains(&addr) {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+
+    /* Smstateen */
+    //& The mstateen registers control access to state from all privilege modes less privileged than M
+    if p_mode == PrivilegeMode::Machine {
+        return Ok(());
+    }
+    let (mstateen, bit) = match addr {
+        SSTATEEN0 => (MSTATEEN0, STATEEN0_SE0),
+        SSTATEEN0..=SSTATEEN3 => (MSTATEEN0 + (addr - SSTATEEN0), STATEEN_SE),
+        SENVCFG => (MSTATEEN0, STATEEN0_ENVCFG),
+        SISELECT | SIREG => (MSTATEEN0, STATEEN0_CSRIND),
+        STOPI => (MSTATEEN0, STATEEN0_AIA),
+        STOPEI => (MSTATEEN0, STATEEN0_IMSIC),
+        SCONTEXT => (MSTATEEN0, STATEEN0_CONTEXT),
+        _ => return Ok(()),
+    };
+    //& When a bit of mstateen is zero, attempts to access the state from a less privileged mode
+    //& raise an illegal-instruction exception.
+    if cpu.csr.read(mstateen) & bit == 0 {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    Ok(())
+}
+
+/// Read a CSR, applying the side effects of the registers that are not plain storage.
+fn read_csr(cpu: &mut Cpu, instr: u32, addr: usize) -> Result<u64, Exception> {
+    let value = match addr {
+        MIREG => {
+            let iselect = cpu.csr.read(MISELECT);
+            read_ireg(cpu, instr, PrivilegeMode::Machine, iselect)?
+        }
+        SIREG => {
+            let iselect = cpu.csr.read(SISELECT);
+            read_ireg(cpu, instr, PrivilegeMode::Supervisor, iselect)?
+        }
+        MTOPEI => topei_file(cpu, instr, PrivilegeMode::Machine)?.topei(),
+        STOPEI => topei_file(cpu, instr, PrivilegeMode::Supervisor)?.topei(),
+        //& mtopi reports the highest-priority pending-and-enabled interrupt that traps into M-mode
+        MTOPI => {
+            let pending = cpu.pending_interrupts() & cpu.csr.read(MIE) & !cpu.csr.read(MIDELEG);
+            top_interrupt(pending)
+        }
+        STOPI => {
+            let pending = cpu.pending_interrupts() & cpu.csr.read(MIE) & cpu.csr.read(MIDELEG);
+            top_interrupt(pending)
+        }
+        //& The value of SEIP read by CSRR is the logical-OR of the software-writable bit and the interrupt signal
+        MIP => cpu.pending_interrupts(),
+        //& The sip and sie registers are subsets of the mip and mie registers.
+        //& If an interrupt is delegated to S-mode by setting a bit in the mideleg register,
+        //& it becomes visible in the sip register and is maskable using the sie register.
+        SIP => cpu.pending_interrupts() & cpu.csr.read(MIDELEG),
+        SIE => cpu.csr.read(MIE) & cpu.csr.read(MIDELEG),
+        //& The sstatus register is a subset of the mstatus register.
+        SSTATUS => cpu.csr.read(MSTATUS) & SSTATUS_MASK,
+        MVIP => {
+            //& When a bit of mvien is zero, the same bit of mvip is an alias of mip
+            let mvien = cpu.csr.read(MVIEN);
+            (cpu.csr.read(MIP) & MVIP_MASK & !mvien) | (cpu.csr.read(MVIP) & mvien)
+        }
+        _ => cpu.csr.read(addr),
+    };
+    Ok(value)
+}
+
+/// Write a CSR, applying the side effects of the registers that are not plain storage.
+fn write_csr(cpu: &mut Cpu, instr: u32, addr: usize, value: u64) -> Result<(), Exception> {
+    match addr {
+        MIREG => {
+            let iselect = cpu.csr.read(MISELECT);
+            write_ireg(cpu, instr, PrivilegeMode::Machine, iselect, value)?
+        }
+        SIREG => {
+            let iselect = cpu.csr.read(SISELECT);
+            write_ireg(cpu, instr, PrivilegeMode::Supervisor, iselect, value)?
+        }
+        //& A write to *topei claims the reported interrupt, the value written is ignored
+        MTOPEI => topei_file(cpu, instr, PrivilegeMode::Machine)?.claim(),
+        STOPEI => topei_file(cpu, instr, PrivilegeMode::Supervisor)?.claim(),
+        // Read-only
+        MTOPI | STOPI => {}
+        MIE => cpu.csr.write(MIE, value & MIE_MASK),
+        // Only the software-writable bits are affected, the device lines being driven by the hart
+        MIP => {
+            let mip = (cpu.csr.read(MIP) & !MIP_MASK) | (value & MIP_MASK);
+            cpu.csr.write(MIP, mip);
+        }
+        //& Otherwise, the corresponding bits in sip and sie are read-only zero.
+        SIP => {
+            let mask = SIP_MASK & cpu.csr.read(MIDELEG);
+            let mip = (cpu.csr.read(MIP) & !mask) | (value & mask);
+            cpu.csr.write(MIP, mip);
+        }
+        SIE => {
+            let mask = MIE_MASK & cpu.csr.read(MIDELEG);
+            let mie = (cpu.csr.read(MIE) & !mask) | (value & mask);
+            cpu.csr.write(MIE, mie);
+        }
+        //& If big-endian memory accesses aren't supported, MBE, SBE and UBE are read-only zero.
+        MSTATUS => {
+            let mask = match cpu.config.big_endian {
+                true => !0,
+                false => !MSTATUS_BE_MASK,
+            };
+            cpu.csr.write(MSTATUS, value & mask);
+        }
+        SSTATUS => {
+            // UBE is writable with the big-endian support
+            let mask = match cpu.config.big_endian {
+                true => SSTATUS_WRITE_MASK | 1 << 6,
+                false => SSTATUS_WRITE_MASK,
+            };
+            let mstatus = (cpu.csr.read(MSTATUS) & !mask) | (value & mask);
+            cpu.csr.write(MSTATUS, mstatus);
+        }
+        MVIEN => cpu.csr.write(MVIEN, value & MVIEN_MASK),
+        MVIP => {
+            let mvien = cpu.csr.read(MVIEN);
+            let alias = MVIP_MASK & !mvien;
+            let mip = (cpu.csr.read(MIP) & !alias) | (value & alias);
+            cpu.csr.write(MIP, mip);
+            cpu.csr.write(MVIP, value & MVIP_MASK & mvien);
+        }
+        MSTATEEN0 => cpu.csr.write(MSTATEEN0, value & MSTATEEN0_MASK),
+        // mstateen1-3 only have their SE bit implemented
+        MSTATEEN0..=MSTATEEN3 => cpu.csr.write(addr, value & STATEEN_SE),
+        SSTATEEN0..=SSTATEEN3 => {
+            //& A bit of sstateen is read-only zero when the same bit of mstateen is zero
+            let mstateen = cpu.csr.read(MSTATEEN0 + (addr - SSTATEEN0));
+            cpu.csr.write(addr, value & SSTATEEN_MASK & mstateen);
+        }
+        MENVCFG | SENVCFG => cpu.csr.write(addr, value & ENVCFG_MASK),
+        SAPT => {
+            //& If satp is written with an unsupported MODE, the entire write has no effect.
+            let mode = Sapt::new_with_raw_value(value).mode().value();
+            if mode == SATP_MODE_BARE || mode == SATP_MODE_SV39 {
+                cpu.csr.write(SAPT, value);
+                // Conservatively drop the cached translations of the previous address space
+                cpu.mmu.flush_tlb(None, None);
+            }
+        }
+        _ => cpu.csr.write(addr, value),
+    }
+    Ok(())
+}
+
+/// *topi value: interrupt number in bits 27:16, priority in 7:0.
+fn top_interrupt(pending: u64) -> u64 {
+    //& When the iprio array is read-only zero, IPRIO is always 1
+    Interrupt::highest(pending).map_or(0, |i| (i as u64) << 16 | 1)
+}
+
+fn topei_file(
+    cpu: &mut Cpu,
+    instr: u32,
+    level: PrivilegeMode,
+) -> Result<&mut InterruptFile, Exception> {
+    cpu.mmu
+        .bus
+        .aia
+        .as_mut()
+        .and_then(|aia| aia.file(level))
+        .ok_or(Exception::IllegalInstruction(instr))
+}
+
+fn read_ireg(
+    cpu: &mut Cpu,
+    instr: u32,
+    level: PrivilegeMode,
+    iselect: u64,
+) -> Result<u64, Exception> {
+    if cpu.mmu.bus.aia.is_none() {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    match iselect {
+        // Major interrupt priorities are read-only zero
+        IPRIO_BASE..=IPRIO_END => Ok(0),
+        _ => topei_file(cpu, instr, level)?
+            .read_ireg(iselect)
+            .ok_or(Exception::IllegalInstruction(instr)),
+    }
+}
+
+fn write_ireg(
+    cpu: &mut Cpu,
+    instr: u32,
+    level: PrivilegeMode,
+    iselect: u64,
+    value: u64,
+) -> Result<(), Exception> {
+    if cpu.mmu.bus.aia.is_none() {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    match iselect {
+        IPRIO_BASE..=IPRIO_END => Ok(()),
+        _ => topei_file(cpu, instr, level)?
+            .write_ireg(iselect, value)
+            .ok_or(Exception::IllegalInstruction(instr)),
+    }
+}
+
+fn instr_ecall(cpu: &Cpu, instr: u32) -> Result<(), Exception> {
     match *cpu.p_mode {
         PrivilegeMode::User => Err(Exception::EnvironmentCallFromUMode),
         PrivilegeMode::Supervisor => Err(Exception::EnvironmentCallFromSMode),
         PrivilegeMode::Machine => Err(Exception::EnvironmentCallFromMMode),
-        PrivilegeMode::Reserved => Err(Exception::IllegalInstruction),
+        PrivilegeMode::Reserved => Err(Exception::IllegalInstruction(instr)),
     }
 }
 fn instr_ebreak() -> Result<(), Exception> {
@@ -145,6 +448,26 @@ fn instr_mret(cpu: &mut Cpu) {
     }
     cpu.csr.write_mstatus(&mstatus);
 }
+/* 12.2.1. Supervisor Memory-Management Fence Instruction */
+fn instr_sfence_vma(cpu: &mut Cpu, instr: u32) -> Result<(), Exception> {
+    //& Attempting to execute SFENCE.VMA in U-mode raises an illegal-instruction exception.
+    //& When TVM=1, attempts to execute an SFENCE.VMA in S-mode will raise an illegal-instruction exception.
+    let p_mode = *cpu.p_mode;
+    if p_mode == PrivilegeMode::User
+        || (p_mode == PrivilegeMode::Supervisor && cpu.csr.read_mstatus().tvm() == T)
+    {
+        return Err(Exception::IllegalInstruction(instr));
+    }
+    //& If rs1=x0, the fence orders all reads and writes made to any level of the page tables, for all virtual addresses.
+    //& If rs2=x0, the fence orders all reads and writes made to any level of the page tables, for all address spaces.
+    let rtype = RType::new_with_raw_value(instr);
+    let (rs1, rs2) = (rtype.rs1(), rtype.rs2());
+    let vaddr = (rs1.value() != 0).then(|| cpu.x_regs.read(rs1));
+    let asid = (rs2.value() != 0).then(|| cpu.x_regs.read(rs2) as u16);
+    cpu.mmu.flush_tlb(vaddr, asid);
+    Ok(())
+}
+
 fn instr_wfi(cpu: &mut Cpu) {
     //& The Wait for Interrupt instruction (WFI) informs the implementation
     //& that the current hart can be stalled until an interrupt might need servicing.
@@ -1,4 +1,6 @@
 pub mod components;
+pub mod config;
 pub mod cpu;
+pub mod dts;
 mod instructions;
 mod util;
@@ -1,10 +1,17 @@
 use argh::FromArgs;
 use cpu::*;
 
-use crate::components::system_bus::{KERNEL_REGION, SBI_REGION};
+use crate::components::system_bus::{KERNEL_OFFSET, SBI_OFFSET};
+use crate::config::{
+    AiaMode, Config, DiskConfig, MisalignedPolicy, PlicConfig, RamBacking, RamConfig,
+    SerialBackend, SysconAction, SysconConfig, TimerConfig, TlbConfig, TvalPolicy, UartConfig,
+    UnmappedPolicy, parse_size,
+};
 pub mod cpu;
 
 mod components;
+mod config;
+mod dts;
 mod instructions;
 pub mod util;
 
@@ -16,25 +23,183 @@ pub mod util;
 struct Args {
     /// sbi or binary
     #[argh(option, short = 'b')]
-    sbi: String,
+    sbi: Option<String>,
 
     /// kernel
     #[argh(option, short = 'k')]
     kernel: Option<String>,
+
+    /// interrupt controller: none (PLIC), aplic or aplic-imsic
+    #[argh(option, default = "AiaMode::None")]
+    aia: AiaMode,
+
+    /// number of interrupt sources of the PLIC, riscv,ndev in the device tree
+    #[argh(option, default = "PlicConfig::default().sources")]
+    plic_sources: u32,
+
+    /// value of xtval on exceptions: full, address or zero
+    #[argh(option, default = "TvalPolicy::Full")]
+    tval: TvalPolicy,
+
+    /// execute reserved encodings instead of trapping, for legacy binaries
+    #[argh(switch)]
+    lenient: bool,
+
+    /// misaligned loads and stores: hardware or trap
+    #[argh(option, default = "MisalignedPolicy::Hardware")]
+    misaligned: MisalignedPolicy,
+
+    /// support big-endian data accesses through mstatus.MBE/SBE/UBE
+    #[argh(switch)]
+    big_endian: bool,
+
+    /// number of sets of each TLB, 0 disables them
+    #[argh(option, default = "TlbConfig::default().sets")]
+    tlb_sets: usize,
+
+    /// associativity of the TLB sets
+    #[argh(option, default = "TlbConfig::default().ways")]
+    tlb_ways: usize,
+
+    /// share a single TLB between instruction fetches and data accesses
+    #[argh(switch)]
+    unified_tlb: bool,
+
+    /// physical address of the RAM, e.g. 0x80000000, as in the memory node of the device tree
+    #[argh(option, default = "RamConfig::default().base", from_str_fn(parse_size))]
+    ram_base: u64,
+
+    /// size of the RAM, e.g. 512M or 16G, as in the memory node of the device tree
+    #[argh(option, default = "RamConfig::default().size", from_str_fn(parse_size))]
+    ram_size: u64,
+
+    /// host memory of the RAM: sparse, file:PATH or shm:NAME
+    #[argh(option, default = "RamBacking::Sparse")]
+    ram_backing: RamBacking,
+
+    /// accesses to unmapped physical addresses: fault, ignore or stop
+    #[argh(option, default = "UnmappedPolicy::Fault")]
+    unmapped: UnmappedPolicy,
+
+    /// log every access to an unmapped physical address
+    #[argh(switch)]
+    log_unmapped: bool,
+
+    /// translate the DMA of the devices through a RISC-V IOMMU, with its node in the device tree
+    #[argh(switch)]
+    iommu: bool,
+
+    /// frequency of mtime in Hz, the timebase-frequency of the device tree
+    #[argh(option, default = "TimerConfig::default().timebase_frequency")]
+    timebase_frequency: u64,
+
+    /// syscon register write powering off the machine, OFFSET:VALUE[:MASK]
+    #[argh(option, default = "SysconConfig::default().poweroff")]
+    syscon_poweroff: SysconAction,
+
+    /// syscon register write rebooting the machine, OFFSET:VALUE[:MASK]
+    #[argh(option, default = "SysconConfig::default().reboot")]
+    syscon_reboot: SysconAction,
+
+    /// initial time of the RTC in seconds since 1970, then advancing with the emulated cycles, the host time by default
+    #[argh(option)]
+    rtc_epoch: Option<u64>,
+
+    /// host side of the console UART: stdio, null, pty, file:PATH or unix:PATH
+    #[argh(option, default = "SerialBackend::Stdio")]
+    serial: SerialBackend,
+
+    /// an additional UART, BASE:IRQ[:BACKEND], with no backend by default, repeatable
+    #[argh(option)]
+    uart: Vec<UartConfig>,
+
+    /// a virtio-blk disk backed by a raw image, PATH[,ro][,sector-size=SIZE], repeatable
+    #[argh(option)]
+    disk: Vec<DiskConfig>,
+
+    /// print the device tree of the machine as configured, for dtc, and exit
+    #[argh(switch)]
+    dump_dts: bool,
 }
 
 fn main() {
     let args: Args = argh::from_env();
 
-    let mut cpu = Cpu::new();
+    let config = Config {
+        aia: args.aia,
+        plic: PlicConfig {
+            sources: args.plic_sources,
+        },
+        tval: args.tval,
+        lenient: args.lenient,
+        misaligned: args.misaligned,
+        big_endian: args.big_endian,
+        tlb: TlbConfig {
+            sets: args.tlb_sets,
+            ways: args.tlb_ways,
+            split: !args.unified_tlb,
+        },
+        ram: RamConfig {
+            base: args.ram_base,
+            size: args.ram_size,
+            backing: args.ram_backing,
+        },
+        unmapped: args.unmapped,
+        log_unmapped: args.log_unmapped,
+        iommu: args.iommu,
+        timer: TimerConfig {
+            timebase_frequency: args.timebase_frequency,
+        },
+        syscon: SysconConfig {
+            poweroff: args.syscon_poweroff,
+            reboot: args.syscon_reboot,
+        },
+        rtc_epoch: args.rtc_epoch,
+        uarts: std::iter::once(UartConfig {
+            backend: args.serial,
+            ..UartConfig::default()
+        })
+        .chain(args.uart)
+        .collect(),
+        console_input: true,
+        disks: args.disk,
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if args.dump_dts {
+        print!("{}", dts::generate(&config));
+        return;
+    }
+    let Some(sbi) = args.sbi else {
+        eprintln!("missing the sbi or binary, -b");
+        std::process::exit(1);
+    };
+    let mut cpu = match Cpu::with_config(&config) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    let sbi = std::fs::read(args.sbi).unwrap();
-    cpu.mmu.inject(SBI_REGION, &sbi);
+    let sbi = std::fs::read(sbi).unwrap();
+    cpu.mmu.inject(config.ram.base + SBI_OFFSET, &sbi);
     args.kernel.map(|k| {
         let kernel = std::fs::read(k).unwrap();
-        cpu.mmu.inject(KERNEL_REGION, &kernel);
+        cpu.mmu.inject(config.ram.base + KERNEL_OFFSET, &kernel);
     });
 
-    cpu.run();
+    let code = cpu.run();
+    // On stderr, stdout being the console of the guest
+    match code {
+        Some(code) => eprintln!("Exited with {code}"),
+        None => eprintln!("Stopped"),
+    }
     cpu.dump_state();
+    // The terminal is restored as the console is dropped, which exit skips
+    drop(cpu);
+    // A stop on an error is reported as a failure
+    std::process::exit(code.map_or(1, |code| code as i32));
 }
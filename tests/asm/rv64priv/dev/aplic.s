@@ -0,0 +1,69 @@
+# A source set pending by software, signaled to the hart through the interrupt delivery control of the root domain
+    .equ APLIC_M, 0xc000000
+    .equ DOMAINCFG, APLIC_M + 0x0
+    .equ SOURCECFG, APLIC_M + 0x4
+    .equ SETIPNUM, APLIC_M + 0x1cdc
+    .equ SETIENUM, APLIC_M + 0x1edc
+    .equ TARGET, APLIC_M + 0x3004
+    .equ IDELIVERY, APLIC_M + 0x4000
+    .equ ITHRESHOLD, APLIC_M + 0x4008
+    .equ TOPI, APLIC_M + 0x4018
+    .equ CLAIMI, APLIC_M + 0x401c
+    .equ SOURCE, 20
+    .equ EDGE1, 4
+    .text
+    .globl _start
+_start:
+    li   t0, SOURCECFG + (SOURCE - 1) * 4
+    li   t1, EDGE1
+    sw   t1, 0(t0)
+    li   t0, TARGET + (SOURCE - 1) * 4
+    li   t1, 3
+    sw   t1, 0(t0)
+    li   t0, SETIENUM
+    li   t1, SOURCE
+    sw   t1, 0(t0)
+    li   t0, DOMAINCFG
+    li   t1, 1 << 8
+    sw   t1, 0(t0)
+    li   t0, IDELIVERY
+    li   t1, 1
+    sw   t1, 0(t0)
+    li   t0, SETIPNUM
+    li   t1, SOURCE
+    sw   t1, 0(t0)
+
+    li   t0, TOPI
+    lw   s1, 0(t0)
+    # Only the priorities under the threshold are signaled
+    li   t0, ITHRESHOLD
+    li   t1, 3
+    sw   t1, 0(t0)
+    li   t0, TOPI
+    lw   s2, 0(t0)
+    csrr t1, mip
+    li   t2, 1 << 11
+    and  s3, t1, t2
+
+    la   t0, external
+    csrw mtvec, t0
+    li   t0, 1 << 11
+    csrw mie, t0
+    li   t0, ITHRESHOLD
+    sw   zero, 0(t0)
+    csrsi mstatus, 1 << 3
+    # Not reached, the interrupt is taken first
+    j    fail
+
+external:
+    csrr s4, mcause
+    li   t0, CLAIMI
+    lw   s5, 0(t0)
+    # The claim clears the pending bit of an edge-triggered source
+    li   t0, TOPI
+    lw   s6, 0(t0)
+    call exit
+
+fail:
+    li   s4, -1
+    call exit
@@ -0,0 +1,92 @@
+# A wired interrupt forwarded by the APLIC as an MSI to the M-level interrupt file,
+# then an MSI written straight to the S-level file, both read through the AIA CSRs.
+# The CSRs go by number, for the assemblers without Smaia
+    .equ EIDELIVERY, 0x70
+    .equ EITHRESHOLD, 0x72
+    .equ EIE0, 0xc0
+    .equ APLIC_M, 0xc000000
+    .equ DOMAINCFG, APLIC_M + 0x0
+    .equ SOURCECFG, APLIC_M + 0x4
+    .equ MMSIADDRCFG, APLIC_M + 0x1bc0
+    .equ SETIPNUM, APLIC_M + 0x1cdc
+    .equ SETIENUM, APLIC_M + 0x1edc
+    .equ TARGET, APLIC_M + 0x3004
+    .equ IMSIC_M, 0x24000000
+    .equ IMSIC_S, 0x28000000
+    .equ SOURCE, 20
+    .equ EDGE1, 4
+    .equ M_EIID, 5
+    .equ S_EIID, 7
+    .text
+    .globl _start
+_start:
+    # M-level file: delivery enabled, with the identity of the source
+    li   t0, EIDELIVERY
+    csrw 0x350, t0  # miselect
+    li   t0, 1
+    csrw 0x351, t0  # mireg
+    li   t0, EIE0
+    csrw 0x350, t0  # miselect
+    li   t0, 1 << M_EIID
+    csrw 0x351, t0  # mireg
+    csrr s1, 0x351  # mireg
+
+    li   t0, MMSIADDRCFG
+    li   t1, IMSIC_M >> 12
+    sw   t1, 0(t0)
+    li   t0, SOURCECFG + (SOURCE - 1) * 4
+    li   t1, EDGE1
+    sw   t1, 0(t0)
+    li   t0, TARGET + (SOURCE - 1) * 4
+    li   t1, M_EIID
+    sw   t1, 0(t0)
+    li   t0, SETIENUM
+    li   t1, SOURCE
+    sw   t1, 0(t0)
+    # The MSI delivery mode is read-only
+    li   t0, DOMAINCFG
+    li   t1, 1 << 8
+    sw   t1, 0(t0)
+    lw   s2, 0(t0)
+
+    la   t0, external
+    csrw mtvec, t0
+    li   t0, 1 << 11
+    csrw mie, t0
+    csrsi mstatus, 1 << 3
+    li   t0, SETIPNUM
+    li   t1, SOURCE
+    sw   t1, 0(t0)
+    # Not reached, the interrupt is taken first
+    j    fail
+
+external:
+    csrr s3, mcause
+    csrr s4, 0x35c  # mtopei
+    # The write claims the identity
+    csrw 0x35c, zero  # mtopei
+    csrr s5, 0x35c  # mtopei
+
+    li   t0, EIDELIVERY
+    csrw 0x150, t0  # siselect
+    li   t0, 1
+    csrw 0x151, t0  # sireg
+    li   t0, EIE0
+    csrw 0x150, t0  # siselect
+    li   t0, 1 << S_EIID
+    csrw 0x151, t0  # sireg
+    li   t0, IMSIC_S
+    li   t1, S_EIID
+    sw   t1, 0(t0)
+    csrr s6, 0x15c  # stopei
+    # The identities from the threshold up are masked
+    li   t0, EITHRESHOLD
+    csrw 0x150, t0  # siselect
+    li   t0, S_EIID
+    csrw 0x151, t0  # sireg
+    csrr s7, 0x15c  # stopei
+    call exit
+
+fail:
+    li   s3, -1
+    call exit
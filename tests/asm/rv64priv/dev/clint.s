@@ -0,0 +1,56 @@
+# Machine software interrupt raised through msip, then a timer interrupt waking the hart from wfi
+    .equ CLINT, 0x2000000
+    .equ MSIP, 0x0
+    .equ MTIMECMP, 0x4000
+    .equ MTIME, 0xbff8
+    .text
+    .globl _start
+_start:
+    li   s0, CLINT
+    la   t0, software
+    csrw mtvec, t0
+    li   t0, 1 << 3
+    csrw mie, t0
+    csrsi mstatus, 1 << 3
+    li   t0, 1
+    sw   t0, MSIP(s0)
+    # Not reached, the interrupt is taken first
+    j    fail
+
+software:
+    csrr s1, mcause
+    csrr s2, mip
+    sw   zero, MSIP(s0)
+    csrr s3, mip
+
+    la   t0, timer
+    csrw mtvec, t0
+    li   t0, 1 << 7
+    csrw mie, t0
+    li   t1, CLINT + MTIMECMP
+    li   t2, CLINT + MTIME
+    ld   t0, 0(t2)
+    addi t0, t0, 10
+    sd   t0, 0(t1)
+    # mret back into the loop with the interrupts enabled
+    la   t0, wait
+    csrw mepc, t0
+    li   t0, 1 << 7
+    csrs mstatus, t0
+    mret
+wait:
+    wfi
+    j    wait
+
+timer:
+    csrr s4, mcause
+    # Moving mtimecmp past mtime clears the interrupt
+    li   t0, -1
+    li   t1, CLINT + MTIMECMP
+    sd   t0, 0(t1)
+    csrr s5, mip
+    call exit
+
+fail:
+    li   s1, -1
+    call exit
@@ -0,0 +1,35 @@
+# Copy 100 bytes, more than a burst, with the DMA controller
+    .equ DMAC, 0x4000000
+    .text
+    .globl _start
+_start:
+    li   t0, DMAC
+    la   t1, src
+    sd   t1, 0x00(t0)
+    la   t1, dst
+    sd   t1, 0x08(t0)
+    li   t1, 100
+    sd   t1, 0x10(t0)
+    li   t1, 1
+    sd   t1, 0x18(t0)
+
+wait:
+    ld   t1, 0x20(t0)
+    andi t1, t1, 1
+    bnez t1, wait
+
+    la   t1, dst
+    ld   s1, 0(t1)
+    ld   s2, 96(t1)
+    ld   s3, 0x20(t0)
+    ld   s4, 0x10(t0)
+
+    call exit
+
+    .balign 8
+src:
+    .dword 0x0123456789abcdef
+    .zero 88
+    .dword 0x00000000cafebabe
+dst:
+    .fill 104, 1, 0xff
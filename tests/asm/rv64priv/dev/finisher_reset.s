@@ -0,0 +1,26 @@
+# Reset the machine through the finisher, the second run finds the mark left in RAM by the first one
+    .equ TEST, 0x100000
+    .equ FINISHER_RESET, 0x7777
+    # Past the image, which is loaded again on the reset
+    .equ MARK, 0x80100000
+    .text
+    .globl _start
+_start:
+    li   t0, MARK
+    ld   s1, 0(t0)
+    bnez s1, reset
+
+    li   t1, 1
+    sd   t1, 0(t0)
+    li   t1, 0x1234
+    csrw mscratch, t1
+    li   s3, 0x5678
+    li   t0, TEST
+    li   t1, FINISHER_RESET
+    sw   t1, 0(t0)
+loop:
+    j    loop
+
+reset:
+    csrr s2, mscratch
+    call exit
@@ -0,0 +1,96 @@
+# DMA of the DMA controller through the IOMMU: blocked and reported in the fault queue while it is off,
+# then translated by a first-stage Sv39 gigapage mapping the IOVA 0 to the RAM
+    .equ IOMMU, 0x3010000
+    .equ DMAC, 0x4000000
+    .equ DMAC_ID, 1
+    .equ DRAM, 0x80000000
+    .text
+    .globl _start
+_start:
+    li   s0, IOMMU
+    li   s6, DMAC
+
+    # Fault queue of 2 records
+    la   t1, faults
+    srli t1, t1, 12
+    slli t1, t1, 10
+    sd   t1, 0x28(s0)
+    li   t1, 1
+    sw   t1, 0x4c(s0)
+
+    # The IOMMU is off at reset
+    la   t1, src
+    sd   t1, 0x00(s6)
+    la   t1, dst
+    sd   t1, 0x08(s6)
+    li   t1, 8
+    sd   t1, 0x10(s6)
+    li   t1, 1
+    sd   t1, 0x18(s6)
+    call wait
+    ld   s1, 0x20(s6)
+    la   t1, faults
+    ld   s2, 0(t1)
+    lw   s3, 0x34(s0)
+    li   t1, 4
+    sd   t1, 0x20(s6)
+
+    # Device context of the DMA controller in a single-level directory
+    la   t0, ddt
+    li   t1, DMAC_ID * 64
+    add  t0, t0, t1
+    li   t1, 1
+    sd   t1, 0(t0)
+    la   t1, root
+    srli t1, t1, 12
+    li   t2, 8
+    slli t2, t2, 60
+    or   t1, t1, t2
+    sd   t1, 24(t0)
+    # Root table mapping the first GiB of IOVAs to the RAM, with U, R, W, A and D set
+    la   t0, root
+    li   t1, (DRAM >> 12) << 10 | 0xd7
+    sd   t1, 0(t0)
+    la   t1, ddt
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 2
+    sd   t1, 0x10(s0)
+
+    la   t1, src
+    li   t2, DRAM
+    sub  t1, t1, t2
+    sd   t1, 0x00(s6)
+    la   t1, dst
+    sub  t1, t1, t2
+    sd   t1, 0x08(s6)
+    li   t1, 8
+    sd   t1, 0x10(s6)
+    li   t1, 1
+    sd   t1, 0x18(s6)
+    call wait
+    ld   s4, 0x20(s6)
+    la   t1, dst
+    ld   s5, 0(t1)
+
+    call exit
+
+wait:
+    ld   t1, 0x20(s6)
+    andi t1, t1, 1
+    bnez t1, wait
+    ret
+
+    .balign 8
+src:
+    .dword 0x0123456789abcdef
+dst:
+    .dword 0
+
+    .balign 4096
+faults:
+    .zero 4096
+ddt:
+    .zero 4096
+root:
+    .zero 4096
@@ -0,0 +1,62 @@
+# Command queue of the IOMMU: an invalidation, then an IOFENCE.C writing its data and raising fence_w_ip,
+# the queue stopping at the next command, an illegal one
+    .equ IOMMU, 0x3010000
+    .equ DATA, 0x12345678
+    .equ CMD_ILL, 1 << 10
+    .text
+    .globl _start
+_start:
+    li   s0, IOMMU
+
+    # Queue of 4 commands, enabled with its interrupt
+    la   t1, commands
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 1
+    sd   t1, 0x18(s0)
+    li   t1, 1 << 1 | 1
+    sw   t1, 0x48(s0)
+
+    la   t0, commands
+    # IOTINVAL.VMA of every address space
+    li   t1, 1
+    sd   t1, 0(t0)
+    sd   zero, 8(t0)
+    # IOFENCE.C with AV and WSI, writing the data at flag
+    li   t1, DATA << 32 | 1 << 11 | 1 << 10 | 2
+    sd   t1, 16(t0)
+    la   t1, flag
+    sd   t1, 24(t0)
+    li   t1, 2
+    sw   t1, 0x24(s0)
+1:
+    lw   t1, 0x20(s0)
+    li   t2, 2
+    bne  t1, t2, 1b
+    la   t1, flag
+    lwu  s1, 0(t1)
+    lw   s2, 0x48(s0)
+    lw   s3, 0x54(s0)
+
+    # Opcode 0 is no command
+    la   t0, commands
+    sd   zero, 32(t0)
+    sd   zero, 40(t0)
+    li   t1, 3
+    sw   t1, 0x24(s0)
+1:
+    lw   t1, 0x48(s0)
+    andi t1, t1, CMD_ILL
+    beqz t1, 1b
+    lw   s4, 0x20(s0)
+    lw   s5, 0x48(s0)
+
+    call exit
+
+    .balign 4
+flag:
+    .word 0
+
+    .balign 4096
+commands:
+    .zero 4096
@@ -0,0 +1,101 @@
+# DMA of the DMA controller translated by the second stage alone: an Sv39x4 gigapage maps
+# the guest-physical addresses from 0 to the RAM, with A and D updated by the IOMMU,
+# and the next gigapage is reported as a guest page fault
+    .equ IOMMU, 0x3010000
+    .equ DMAC, 0x4000000
+    .equ DMAC_ID, 1
+    .equ DRAM, 0x80000000
+    .equ UNMAPPED, 1 << 30
+    .text
+    .globl _start
+_start:
+    li   s0, IOMMU
+    li   s6, DMAC
+
+    # Fault queue of 4 records
+    la   t1, faults
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 1
+    sd   t1, 0x28(s0)
+    li   t1, 1
+    sw   t1, 0x4c(s0)
+
+    # Device context of the DMA controller: V and GADE, a bare first stage and an Sv39x4 second stage
+    la   t0, ddt
+    addi t0, t0, DMAC_ID * 64
+    li   t1, 1 | 1 << 7
+    sd   t1, 0(t0)
+    la   t1, groot
+    srli t1, t1, 12
+    li   t2, 8
+    slli t2, t2, 60
+    or   t1, t1, t2
+    sd   t1, 8(t0)
+    # Root table mapping the first GiB of guest-physical addresses to the RAM, with U, R and W set
+    la   t0, groot
+    li   t1, (DRAM >> 12) << 10 | 0x17
+    sd   t1, 0(t0)
+    la   t1, ddt
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 2
+    sd   t1, 0x10(s0)
+
+    li   t2, DRAM
+    la   a0, src
+    sub  a0, a0, t2
+    la   a1, dst
+    sub  a1, a1, t2
+    call copy
+    mv   s1, a0
+    la   t1, dst
+    ld   s2, 0(t1)
+    la   t1, groot
+    ld   s3, 0(t1)
+
+    li   a0, UNMAPPED
+    la   a1, dst
+    li   t2, DRAM
+    sub  a1, a1, t2
+    call copy
+    mv   s4, a0
+    la   t1, faults
+    ld   s5, 0(t1)
+    ld   s6, 16(t1)
+    ld   s7, 24(t1)
+
+    call exit
+
+# Copy 8 bytes from a0 to a1, returning the status of the transfer
+copy:
+    sd   a0, 0x00(s6)
+    sd   a1, 0x08(s6)
+    li   t1, 8
+    sd   t1, 0x10(s6)
+    li   t1, 1
+    sd   t1, 0x18(s6)
+1:
+    ld   a0, 0x20(s6)
+    andi t1, a0, 1
+    bnez t1, 1b
+    # Clear DONE and ERROR
+    li   t1, 6
+    sd   t1, 0x20(s6)
+    ret
+
+    .balign 8
+src:
+    .dword 0x0123456789abcdef
+dst:
+    .dword 0
+
+    .balign 4096
+faults:
+    .zero 4096
+ddt:
+    .zero 4096
+    # The root table of Sv39x4 spans 16KiB, aligned to its size
+    .balign 16384
+groot:
+    .zero 16384
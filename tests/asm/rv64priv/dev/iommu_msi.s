@@ -0,0 +1,93 @@
+# MSI of the DMA controller to a virtual interrupt file, redirected by the flat MSI page table
+# of its device context to the S-level interrupt file: faulting while the MSI PTE is invalid, then delivered.
+# The CSRs go by number, for the assemblers without Smaia
+    .equ IOMMU, 0x3010000
+    .equ DMAC, 0x4000000
+    .equ DMAC_ID, 1
+    .equ IMSIC_S, 0x28000000
+    # Guest-physical address of the single virtual interrupt file
+    .equ GUEST_FILE, 0x20000000
+    .equ EIP0, 0x80
+    .equ EIID, 9
+    .text
+    .globl _start
+_start:
+    li   s0, IOMMU
+    li   s6, DMAC
+
+    # Fault queue of 4 records
+    la   t1, faults
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 1
+    sd   t1, 0x28(s0)
+    li   t1, 1
+    sw   t1, 0x4c(s0)
+
+    # Device context of the DMA controller: bare stages, a flat MSI page table matching the page of the file
+    la   t0, ddt
+    addi t0, t0, DMAC_ID * 64
+    li   t1, 1
+    sd   t1, 0(t0)
+    la   t1, msipt
+    srli t1, t1, 12
+    li   t2, 1
+    slli t2, t2, 60
+    or   t1, t1, t2
+    sd   t1, 32(t0)
+    sd   zero, 40(t0)
+    li   t1, GUEST_FILE >> 12
+    sd   t1, 48(t0)
+    la   t1, ddt
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 2
+    sd   t1, 0x10(s0)
+
+    call send
+    mv   s1, a0
+    la   t1, faults
+    ld   s2, 0(t1)
+
+    # Basic-mode MSI PTE of the file 0, pointing to the S-level interrupt file
+    la   t0, msipt
+    li   t1, (IMSIC_S >> 12) << 10 | 3 << 1 | 1
+    sd   t1, 0(t0)
+    call send
+    mv   s3, a0
+    li   t0, EIP0
+    csrw 0x150, t0  # siselect
+    csrr s4, 0x151  # sireg
+
+    call exit
+
+# Write the message to the virtual interrupt file, returning the status of the transfer
+send:
+    la   t1, message
+    sd   t1, 0x00(s6)
+    li   t1, GUEST_FILE
+    sd   t1, 0x08(s6)
+    li   t1, 4
+    sd   t1, 0x10(s6)
+    li   t1, 1
+    sd   t1, 0x18(s6)
+1:
+    ld   a0, 0x20(s6)
+    andi t1, a0, 1
+    bnez t1, 1b
+    # Clear DONE and ERROR
+    li   t1, 6
+    sd   t1, 0x20(s6)
+    ret
+
+    .balign 8
+message:
+    .word EIID
+
+    .balign 4096
+faults:
+    .zero 4096
+ddt:
+    .zero 4096
+msipt:
+    .zero 4096
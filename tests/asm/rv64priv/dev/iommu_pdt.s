@@ -0,0 +1,107 @@
+# DMA of the DMA controller translated by the first stage of the process 0, as the requests carry no process id:
+# faulting while its process context is invalid, then through an Sv39 gigapage once it is valid
+    .equ IOMMU, 0x3010000
+    .equ DMAC, 0x4000000
+    .equ DMAC_ID, 1
+    .equ DRAM, 0x80000000
+    .text
+    .globl _start
+_start:
+    li   s0, IOMMU
+    li   s6, DMAC
+
+    # Fault queue of 4 records
+    la   t1, faults
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 1
+    sd   t1, 0x28(s0)
+    li   t1, 1
+    sw   t1, 0x4c(s0)
+
+    # Device context of the DMA controller: V, PDTV and DPE, with a single-level process directory
+    la   t0, ddt
+    addi t0, t0, DMAC_ID * 64
+    li   t1, 1 | 1 << 5 | 1 << 9
+    sd   t1, 0(t0)
+    la   t1, pdt
+    srli t1, t1, 12
+    li   t2, 1
+    slli t2, t2, 60
+    or   t1, t1, t2
+    sd   t1, 24(t0)
+    # Root table mapping the first GiB of IOVAs to the RAM, with U, R, W, A and D set
+    la   t0, root
+    li   t1, (DRAM >> 12) << 10 | 0xd7
+    sd   t1, 0(t0)
+    la   t1, ddt
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori  t1, t1, 2
+    sd   t1, 0x10(s0)
+
+    li   t2, DRAM
+    la   a0, src
+    sub  a0, a0, t2
+    la   a1, dst
+    sub  a1, a1, t2
+    call copy
+    mv   s1, a0
+    la   t1, faults
+    ld   s2, 0(t1)
+
+    # Process context 0: valid, with an Sv39 first stage
+    la   t0, pdt
+    li   t1, 1
+    sd   t1, 0(t0)
+    la   t1, root
+    srli t1, t1, 12
+    li   t2, 8
+    slli t2, t2, 60
+    or   t1, t1, t2
+    sd   t1, 8(t0)
+
+    li   t2, DRAM
+    la   a0, src
+    sub  a0, a0, t2
+    la   a1, dst
+    sub  a1, a1, t2
+    call copy
+    mv   s3, a0
+    la   t1, dst
+    ld   s4, 0(t1)
+
+    call exit
+
+# Copy 8 bytes from a0 to a1, returning the status of the transfer
+copy:
+    sd   a0, 0x00(s6)
+    sd   a1, 0x08(s6)
+    li   t1, 8
+    sd   t1, 0x10(s6)
+    li   t1, 1
+    sd   t1, 0x18(s6)
+1:
+    ld   a0, 0x20(s6)
+    andi t1, a0, 1
+    bnez t1, 1b
+    # Clear DONE and ERROR
+    li   t1, 6
+    sd   t1, 0x20(s6)
+    ret
+
+    .balign 8
+src:
+    .dword 0x0123456789abcdef
+dst:
+    .dword 0
+
+    .balign 4096
+faults:
+    .zero 4096
+ddt:
+    .zero 4096
+pdt:
+    .zero 4096
+root:
+    .zero 4096
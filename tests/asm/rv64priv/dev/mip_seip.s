@@ -0,0 +1,38 @@
+# A csrs of mip while the PLIC asserts the S-level external interrupt doesn't latch SEIP,
+# the interrupt signal going away with the claim
+    .equ PLIC, 0xc000000
+    .equ ENABLE_S, PLIC + 0x2080
+    .equ CONTEXT_S, PLIC + 0x201000
+    .equ RTC, 0x101000
+    .equ IRQ_RTC, 11
+    .equ SEIP, 1 << 9
+    .text
+    .globl _start
+_start:
+    li   t0, PLIC
+    li   t1, 1
+    sw   t1, IRQ_RTC * 4(t0)
+    li   t0, ENABLE_S
+    li   t1, 1 << IRQ_RTC
+    sw   t1, 0(t0)
+    # An RTC alarm already due
+    li   t0, RTC
+    li   t1, 1
+    sw   t1, 0x10(t0)
+    sw   zero, 0x0c(t0)
+    sw   zero, 0x08(t0)
+1:
+    csrr t1, mip
+    li   t2, SEIP
+    and  t1, t1, t2
+    beqz t1, 1b
+
+    csrsi mip, 1 << 1
+    li   t2, SEIP | 1 << 1
+    csrr s1, mip
+    and  s1, s1, t2
+    li   t0, CONTEXT_S
+    lw   s2, 4(t0)
+    csrr s3, mip
+    and  s3, s3, t2
+    call exit
@@ -0,0 +1,67 @@
+# Claims go by priority over the threshold, a source in service being held by its gateway until completed
+    .equ PLIC, 0xc000000
+    .equ ENABLE_M, PLIC + 0x2000
+    .equ ENABLE_S, PLIC + 0x2080
+    .equ CONTEXT_M, PLIC + 0x200000
+    .equ CONTEXT_S, PLIC + 0x201000
+    .equ RTC, 0x101000
+    .equ UART, 0x10000000
+    .equ IRQ_UART, 10
+    .equ IRQ_RTC, 11
+    .text
+    .globl _start
+_start:
+    li   t0, PLIC
+    li   t1, 2
+    sw   t1, IRQ_UART * 4(t0)
+    li   t1, 3
+    sw   t1, IRQ_RTC * 4(t0)
+    li   t0, ENABLE_M
+    li   t1, (1 << IRQ_UART) | (1 << IRQ_RTC)
+    sw   t1, 0(t0)
+    li   t0, ENABLE_S
+    li   t1, 1 << IRQ_UART
+    sw   t1, 0(t0)
+
+    # An RTC alarm already due
+    li   t0, RTC
+    li   t1, 1
+    sw   t1, 0x10(t0)
+    sw   zero, 0x0c(t0)
+    sw   zero, 0x08(t0)
+    # A character looped back to the UART
+    li   t0, UART
+    li   t1, 1
+    sb   t1, 1(t0)
+    li   t1, 0x10
+    sb   t1, 4(t0)
+    li   t1, 'p'
+    sb   t1, 0(t0)
+1:
+    lbu  t1, 5(t0)
+    andi t1, t1, 1
+    beqz t1, 1b
+
+    li   s0, CONTEXT_M
+    lw   s1, 4(s0)
+    lw   s2, 4(s0)
+    lw   s3, 4(s0)
+    # The RTC line is still high, a new request follows the completion
+    li   t1, IRQ_RTC
+    sw   t1, 4(s0)
+    lw   s4, 4(s0)
+    sw   t1, 4(s0)
+    li   t1, IRQ_UART
+    sw   t1, 4(s0)
+    # Only the RTC is over the threshold
+    li   t1, 2
+    sw   t1, 0(s0)
+    lw   s5, 4(s0)
+    lw   s6, 4(s0)
+    csrr t1, mip
+    li   t2, 1 << 11
+    and  s7, t1, t2
+    # The S-mode context only has the UART enabled
+    li   t0, CONTEXT_S
+    lw   s8, 4(t0)
+    call exit
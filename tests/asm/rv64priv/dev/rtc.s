@@ -0,0 +1,38 @@
+# An RTC alarm already due raises the external interrupt through the PLIC
+    .equ RTC, 0x101000
+    .equ PLIC, 0xc000000
+    .equ IRQ_RTC, 11
+    .text
+    .globl _start
+_start:
+    li   t0, PLIC
+    li   t1, 1
+    sw   t1, IRQ_RTC * 4(t0)
+    li   t0, PLIC + 0x2000
+    li   t1, 1 << IRQ_RTC
+    sw   t1, 0(t0)
+
+    la   t0, handler
+    csrw mtvec, t0
+    li   t0, 1 << 11
+    csrw mie, t0
+    csrsi mstatus, 1 << 3
+
+    li   s0, RTC
+    li   t1, 1
+    sw   t1, 0x10(s0)
+    sw   zero, 0x0c(s0)
+    sw   zero, 0x08(s0)
+loop:
+    wfi
+    j    loop
+
+handler:
+    csrr s1, mcause
+    lw   s2, 0x18(s0)
+    sw   zero, 0x1c(s0)
+    # The time since 1970 in nanoseconds doesn't fit in the low half
+    lw   t1, 0x00(s0)
+    lw   t1, 0x04(s0)
+    snez s3, t1
+    call exit
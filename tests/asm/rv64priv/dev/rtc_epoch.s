@@ -0,0 +1,12 @@
+# The RTC started at a fixed epoch advances with the cycles of the hart, by the same time between two reads
+    .equ RTC, 0x101000
+    .text
+    .globl _start
+_start:
+    li   s0, RTC
+    lwu  t1, 0x00(s0)
+    lwu  t2, 0x00(s0)
+    sub  s1, t2, t1
+    # Latched by the read of the low half
+    lwu  s2, 0x04(s0)
+    call exit
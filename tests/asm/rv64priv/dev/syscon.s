@@ -0,0 +1,14 @@
+# The syscon registers keep their values, and the poweroff value stops the machine
+    .equ SYSCON, 0x102000
+    .equ POWEROFF, 0x5555
+    .text
+    .globl _start
+_start:
+    li   t0, SYSCON
+    li   t1, 0xabcd
+    sw   t1, 8(t0)
+    lw   s1, 8(t0)
+    li   t1, POWEROFF
+    sw   t1, 0(t0)
+loop:
+    j    loop
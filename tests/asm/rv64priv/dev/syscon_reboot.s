@@ -0,0 +1,33 @@
+# Reboot the machine through the syscon, the second run finds the mark left in RAM by the first one
+# and powers the machine off
+    .equ SYSCON, 0x102000
+    .equ POWEROFF, 0x5555
+    .equ REBOOT, 0x7777
+    # Past the image, which is loaded again on the reset
+    .equ MARK, 0x80100000
+    .text
+    .globl _start
+_start:
+    li   t0, MARK
+    ld   s1, 0(t0)
+    bnez s1, reset
+
+    li   t1, 1
+    sd   t1, 0(t0)
+    li   t0, SYSCON
+    li   t1, 0xabcd
+    sw   t1, 8(t0)
+    li   t1, REBOOT
+    sw   t1, 0(t0)
+loop:
+    j    loop
+
+reset:
+    # The registers are back to zero
+    li   t0, SYSCON
+    lw   s2, 8(t0)
+    li   t1, POWEROFF
+    sw   t1, 0(t0)
+    # Not left, the machine is off
+    li   s3, -1
+    j    reset
@@ -0,0 +1,29 @@
+# The output of the console UART reaches the file backing it
+    .equ UART, 0x10000000
+    .equ THR, 0
+    .equ LSR, 5
+    .text
+    .globl _start
+_start:
+    li   s0, UART
+    la   s1, message
+next:
+    lbu  t1, 0(s1)
+    beqz t1, flush
+    # Until the holding register is empty
+1:
+    lbu  t2, LSR(s0)
+    andi t2, t2, 1 << 5
+    beqz t2, 1b
+    sb   t1, THR(s0)
+    addi s1, s1, 1
+    j    next
+flush:
+    # Until the last character is shifted out
+    lbu  t2, LSR(s0)
+    andi t2, t2, 1 << 6
+    beqz t2, flush
+    call exit
+
+message:
+    .string "ok\n"
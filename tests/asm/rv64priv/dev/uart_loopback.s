@@ -0,0 +1,77 @@
+# The UART looped back on itself: the divisor latch, the modem status deltas,
+# the order of the FIFO and the character timeout
+    .equ UART, 0x10000000
+    .equ RBR, 0
+    .equ THR, 0
+    .equ DLL, 0
+    .equ DLH, 1
+    .equ IER, 1
+    .equ IIR, 2
+    .equ FCR, 2
+    .equ LCR, 3
+    .equ MCR, 4
+    .equ LSR, 5
+    .equ MSR, 6
+    .text
+    .globl _start
+_start:
+    li   s0, UART
+    # 8N1 with a divisor of 3
+    li   t1, 0x80
+    sb   t1, LCR(s0)
+    li   t1, 3
+    sb   t1, DLL(s0)
+    sb   zero, DLH(s0)
+    lbu  t1, DLL(s0)
+    lbu  t2, DLH(s0)
+    slli t2, t2, 8
+    or   s7, t1, t2
+    li   t1, 0x03
+    sb   t1, LCR(s0)
+    # FIFOs enabled, with a trigger level of 4
+    li   t1, 0x47
+    sb   t1, FCR(s0)
+
+    # Entering loopback with the outputs off drops CTS, DSR and DCD
+    li   t1, 0x10
+    sb   t1, MCR(s0)
+    lbu  s1, MSR(s0)
+    lbu  s2, MSR(s0)
+    li   t1, 0x1f
+    sb   t1, MCR(s0)
+    lbu  s3, MSR(s0)
+
+    li   t1, 'a'
+    sb   t1, THR(s0)
+    li   t1, 'b'
+    sb   t1, THR(s0)
+    li   t1, 'c'
+    sb   t1, THR(s0)
+1:
+    lbu  t1, LSR(s0)
+    andi t1, t1, 0x40
+    beqz t1, 1b
+    lbu  s5, LSR(s0)
+    li   s4, 0
+    li   t3, 3
+2:
+    lbu  t1, RBR(s0)
+    slli s4, s4, 8
+    or   s4, s4, t1
+    addi t3, t3, -1
+    bnez t3, 2b
+    lbu  s6, LSR(s0)
+
+    # A character below the trigger level times out
+    li   t1, 1
+    sb   t1, IER(s0)
+    li   t1, 'd'
+    sb   t1, THR(s0)
+3:
+    lbu  t1, IIR(s0)
+    andi t2, t1, 1
+    bnez t2, 3b
+    mv   s8, t1
+    lbu  t1, RBR(s0)
+    lbu  s9, IIR(s0)
+    call exit
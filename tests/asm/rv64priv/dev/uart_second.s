@@ -0,0 +1,39 @@
+# A second UART at its own base raises its own interrupt source, the console UART staying idle
+    .equ UART0, 0x10000000
+    .equ UART1, 0x10000100
+    .equ IRQ_UART1, 13
+    .equ PLIC, 0xc000000
+    .text
+    .globl _start
+_start:
+    li   t0, PLIC
+    li   t1, 1
+    sw   t1, IRQ_UART1 * 4(t0)
+    li   t0, PLIC + 0x2000
+    li   t1, 1 << IRQ_UART1
+    sw   t1, 0(t0)
+
+    la   t0, handler
+    csrw mtvec, t0
+    li   t0, 1 << 11
+    csrw mie, t0
+    csrsi mstatus, 1 << 3
+
+    # Received data available, with the transmitter looped back
+    li   s0, UART1
+    li   t1, 1
+    sb   t1, 1(s0)
+    li   t1, 0x10
+    sb   t1, 4(s0)
+    li   t1, 'x'
+    sb   t1, 0(s0)
+loop:
+    wfi
+    j    loop
+
+handler:
+    csrr s1, mcause
+    lbu  s2, 0(s0)
+    li   t0, UART0
+    lbu  s3, 5(t0)
+    call exit
@@ -0,0 +1,147 @@
+# A virtio-blk driver: the feature negotiation, then a read of sector 1 and a write of sector 2
+    .equ VIRTIO, 0x10001000
+    .equ QUEUE, 0x80100000
+    .equ AVAIL, QUEUE + 0x100
+    .equ USED, QUEUE + 0x200
+    .equ HEADER_IN, QUEUE + 0x400
+    .equ STATUS_IN, QUEUE + 0x410
+    .equ HEADER_OUT, QUEUE + 0x420
+    .equ STATUS_OUT, QUEUE + 0x430
+    .equ DATA_IN, QUEUE + 0x1000
+    .equ DATA_OUT, QUEUE + 0x2000
+    .equ NEXT, 1
+    .equ WRITE, 2
+    .text
+    .globl _start
+_start:
+    li   s0, VIRTIO
+    lw   s1, 0x000(s0)
+    lw   s2, 0x004(s0)
+    lw   s3, 0x008(s0)
+
+    # ACKNOWLEDGE | DRIVER
+    li   t1, 3
+    sw   t1, 0x070(s0)
+    # VIRTIO_F_VERSION_1 only
+    li   t1, 1
+    sw   t1, 0x024(s0)
+    sw   t1, 0x020(s0)
+    sw   zero, 0x024(s0)
+    sw   zero, 0x020(s0)
+    li   t1, 0xb
+    sw   t1, 0x070(s0)
+    lw   s4, 0x070(s0)
+
+    sw   zero, 0x030(s0)
+    li   t1, 8
+    sw   t1, 0x038(s0)
+    li   t1, QUEUE
+    sw   t1, 0x080(s0)
+    sw   zero, 0x084(s0)
+    li   t1, AVAIL
+    sw   t1, 0x090(s0)
+    sw   zero, 0x094(s0)
+    li   t1, USED
+    sw   t1, 0x0a0(s0)
+    sw   zero, 0x0a4(s0)
+    li   t1, 1
+    sw   t1, 0x044(s0)
+    # DRIVER_OK
+    li   t1, 0xf
+    sw   t1, 0x070(s0)
+
+    # VIRTIO_BLK_T_IN of sector 1
+    li   t0, HEADER_IN
+    sw   zero, 0(t0)
+    li   t1, 1
+    sd   t1, 8(t0)
+    # VIRTIO_BLK_T_OUT of sector 2
+    li   t0, HEADER_OUT
+    li   t1, 1
+    sw   t1, 0(t0)
+    li   t1, 2
+    sd   t1, 8(t0)
+    li   t0, DATA_OUT
+    li   t1, 0x0123456789abcdef
+    li   t2, 64
+1:
+    sd   t1, 0(t0)
+    addi t0, t0, 8
+    addi t2, t2, -1
+    bnez t2, 1b
+
+    # Descriptors 0-2 for the read, 3-5 for the write
+    li   a0, QUEUE
+    li   a1, HEADER_IN
+    li   a2, 16
+    li   a3, NEXT
+    li   a4, 1
+    call desc
+    li   a1, DATA_IN
+    li   a2, 512
+    li   a3, NEXT | WRITE
+    li   a4, 2
+    call desc
+    li   a1, STATUS_IN
+    li   a2, 1
+    li   a3, WRITE
+    li   a4, 0
+    call desc
+    li   a1, HEADER_OUT
+    li   a2, 16
+    li   a3, NEXT
+    li   a4, 4
+    call desc
+    li   a1, DATA_OUT
+    li   a2, 512
+    li   a3, NEXT
+    li   a4, 5
+    call desc
+    li   a1, STATUS_OUT
+    li   a2, 1
+    li   a3, WRITE
+    li   a4, 0
+    call desc
+
+    # The read first, on its own
+    li   t0, AVAIL
+    sh   zero, 4(t0)
+    li   t1, 1
+    sh   t1, 2(t0)
+    sw   zero, 0x050(s0)
+2:
+    lw   t1, 0x060(s0)
+    beqz t1, 2b
+    sw   t1, 0x064(s0)
+    lw   s5, 0x060(s0)
+    li   t0, USED
+    lw   s6, 8(t0)
+    li   t0, STATUS_IN
+    lbu  s7, 0(t0)
+    li   t0, DATA_IN
+    ld   s8, 0(t0)
+
+    li   t0, AVAIL
+    li   t1, 3
+    sh   t1, 6(t0)
+    li   t1, 2
+    sh   t1, 2(t0)
+    sw   zero, 0x050(s0)
+    li   t0, USED
+3:
+    lhu  t1, 2(t0)
+    li   t2, 2
+    bne  t1, t2, 3b
+    li   t0, STATUS_OUT
+    lbu  s9, 0(t0)
+    call exit
+
+# Fill the descriptor at a0 with the address a1, the length a2, the flags a3 and the next a4,
+# moving a0 to the following one
+desc:
+    sd   a1, 0(a0)
+    sw   a2, 8(a0)
+    sh   a3, 12(a0)
+    sh   a4, 14(a0)
+    addi a0, a0, 16
+    ret
@@ -0,0 +1,48 @@
+# Writes to a read-only CSR and accesses from S-mode to the state not enabled by mstateen0
+# raise illegal-instruction exceptions
+    .equ STATEEN0_ENVCFG, 1 << 62
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    # Reading is allowed
+    csrr t1, mhartid
+    csrw mhartid, zero
+    mv   s2, s3
+
+    li   t0, 3 << 11
+    csrc mstatus, t0
+    li   t0, 1 << 11
+    csrs mstatus, t0
+    la   t0, supervisor
+    csrw mepc, t0
+    mret
+
+supervisor:
+    csrr s5, senvcfg
+    # Back with senvcfg enabled
+    ecall
+    csrr s6, senvcfg
+    ecall
+
+# s1: illegal-instruction exceptions, s3: the bits of the last one
+handler:
+    csrr t0, mcause
+    li   t1, 2
+    bne  t0, t1, ecall
+    addi s1, s1, 1
+    csrr s3, mtval
+    j    skip
+ecall:
+    bnez s4, done
+    li   s4, 1
+    li   t0, STATEEN0_ENVCFG
+    csrs mstateen0, t0
+skip:
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
+done:
+    call exit
@@ -0,0 +1,15 @@
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   s4, fault
+fault:
+    .word 0xffffffff
+
+handler:
+    csrr s1, mcause
+    csrr s2, mepc
+    csrr s3, mtval
+
+    call exit
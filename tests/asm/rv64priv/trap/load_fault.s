@@ -0,0 +1,16 @@
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   s4, fault
+    li   t1, 0x50000000
+fault:
+    ld   t2, 0(t1)
+
+handler:
+    csrr s1, mcause
+    csrr s2, mepc
+    csrr s3, mtval
+
+    call exit
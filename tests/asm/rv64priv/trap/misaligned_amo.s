@@ -0,0 +1,23 @@
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   t1, data
+    addi t1, t1, 4
+    la   s4, fault
+fault:
+    amoadd.d t2, t1, (t1)
+
+handler:
+    csrr s1, mcause
+    csrr s2, mepc
+    csrr s3, mtval
+    mv   s5, t1
+
+    call exit
+
+    .balign 8
+data:
+    .dword 0
+    .dword 0
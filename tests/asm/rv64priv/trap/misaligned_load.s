@@ -0,0 +1,13 @@
+    .text
+    .globl _start
+_start:
+    la   t0, data
+    ld   s1, 1(t0)
+    lw   s2, 3(t0)
+
+    call exit
+
+    .balign 8
+data:
+    .dword 0x8877665544332211
+    .dword 0x00000000000000aa
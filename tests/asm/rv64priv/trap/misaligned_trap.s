@@ -0,0 +1,34 @@
+# Misaligned loads and stores raise address-misaligned exceptions with the trap policy
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   t0, data
+    addi s8, t0, 1
+    la   s4, fault
+
+fault:
+    ld   t1, 1(t0)
+    mv   s1, a0
+    mv   s2, a2
+    mv   s3, a1
+    sw   t1, 3(t0)
+    mv   s5, a0
+    mv   s6, a1
+    # Left untouched by the store
+    ld   s7, 0(t0)
+    call exit
+
+# a0: mcause, a1: mtval, a2: mepc
+handler:
+    csrr a0, mcause
+    csrr a1, mtval
+    csrr a2, mepc
+    addi t2, a2, 4
+    csrw mepc, t2
+    mret
+
+    .balign 8
+data:
+    .dword 0x8877665544332211
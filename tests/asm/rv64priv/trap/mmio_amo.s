@@ -0,0 +1,19 @@
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    # the finisher of the test device, which doesn't support AMOs
+    li   t1, 0x100000
+    li   t2, 0x5555
+    la   s4, fault
+fault:
+    amoswap.w t3, t2, (t1)
+
+handler:
+    csrr s1, mcause
+    csrr s2, mepc
+    csrr s3, mtval
+    mv   s5, t1
+
+    call exit
@@ -0,0 +1,16 @@
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   s4, fault
+fault:
+    # branch with the reserved funct3=2
+    .word 0x00002063
+
+handler:
+    csrr s1, mcause
+    csrr s2, mepc
+    csrr s3, mtval
+
+    call exit
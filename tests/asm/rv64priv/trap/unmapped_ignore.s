@@ -0,0 +1,17 @@
+# Accesses to a hole of the memory map are ignored, the reads returning zero
+    .equ HOLE, 0x50000000
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    li   t0, HOLE
+    li   t1, -1
+    sd   t1, 0(t0)
+    ld   s1, 0(t0)
+    lw   s2, 4(t0)
+    call exit
+
+handler:
+    li   s3, -1
+    call exit
@@ -0,0 +1,18 @@
+# An access to a hole of the memory map stops the machine, as the access fault is taken
+    .equ HOLE, 0x50000000
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    li   s1, 1
+    li   t0, HOLE
+    la   s4, fault
+fault:
+    sw   zero, 0(t0)
+    li   s1, 2
+    call exit
+
+handler:
+    li   s1, 3
+    call exit
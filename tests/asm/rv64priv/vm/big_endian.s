@@ -0,0 +1,49 @@
+# Data accesses are big-endian with mstatus.MBE, then SBE in S-mode, the instruction fetches staying little-endian
+    .equ MBE, 1 << 37
+    .equ SBE, 1 << 36
+    .text
+    .globl _start
+_start:
+    la   t0, handler
+    csrw mtvec, t0
+    la   t0, data
+    li   t1, MBE
+    csrs mstatus, t1
+
+    ld   s1, 0(t0)
+    lhu  s2, 0(t0)
+    li   t1, 0x01020304
+    sw   t1, 8(t0)
+    addi t2, t0, 16
+    li   t1, 1
+    amoadd.w s3, t1, (t2)
+
+    # Back to little-endian, to look at the bytes
+    li   t1, MBE
+    csrc mstatus, t1
+    lw   s4, 8(t0)
+    lw   s5, 16(t0)
+
+    li   t1, SBE
+    csrs mstatus, t1
+    li   t1, 3 << 11
+    csrc mstatus, t1
+    li   t1, 1 << 11
+    csrs mstatus, t1
+    la   t1, supervisor
+    csrw mepc, t1
+    mret
+
+supervisor:
+    ld   s6, 0(t0)
+    ecall
+
+handler:
+    call exit
+
+    .balign 8
+data:
+    .dword 0x0011223344556677
+    .dword 0
+    .word 0x01000000
+    .word 0
@@ -0,0 +1,9 @@
+# A store to the RAM lands in the host file backing it
+    .equ MARK, 0x80100000
+    .text
+    .globl _start
+_start:
+    li   t0, MARK
+    li   t1, 0x0123456789abcdef
+    sd   t1, 0(t0)
+    call exit
@@ -0,0 +1,73 @@
+# Loads from M-mode with MPRV=1 and MPP=S are translated through
+# the page 0x0 mapped to page_a, then remapped to page_b.
+# The stale translation is used until an sfence.vma.
+    .text
+    .globl _start
+_start:
+    # root[0] -> l1, l1[0] -> l0
+    la   t0, l1
+    srli t0, t0, 12
+    slli t0, t0, 10
+    ori  t0, t0, 1
+    la   t1, root
+    sd   t0, 0(t1)
+    la   t0, l0
+    srli t0, t0, 12
+    slli t0, t0, 10
+    ori  t0, t0, 1
+    la   t1, l1
+    sd   t0, 0(t1)
+    # l0[0] -> page_a, V|R|W|A|D
+    la   a0, page_a
+    call map
+
+    # satp = Sv39 | root.ppn
+    la   t0, root
+    srli t0, t0, 12
+    li   t1, 8
+    slli t1, t1, 60
+    or   t0, t0, t1
+    csrw satp, t0
+    # MPRV=1, MPP=S
+    li   t0, 3 << 11
+    csrc mstatus, t0
+    li   t0, 1 << 11 | 1 << 17
+    csrs mstatus, t0
+
+    ld   s1, 0(zero)
+
+    # l0[0] -> page_b, with the stores untranslated
+    li   t2, 1 << 17
+    csrc mstatus, t2
+    la   a0, page_b
+    call map
+    csrs mstatus, t2
+
+    ld   s2, 0(zero)
+    sfence.vma zero, zero
+    ld   s3, 0(zero)
+
+    csrc mstatus, t2
+    call exit
+
+# Map the page at a0 in l0[0]
+map:
+    srli a0, a0, 12
+    slli a0, a0, 10
+    ori  a0, a0, 0xc7
+    la   t1, l0
+    sd   a0, 0(t1)
+    ret
+
+    .balign 4096
+root:
+    .zero 4096
+l1:
+    .zero 4096
+l0:
+    .zero 4096
+page_a:
+    .dword 0xaaaa
+    .balign 4096
+page_b:
+    .dword 0xbbbb
@@ -0,0 +1,53 @@
+use std::{fs, path::PathBuf};
+
+use risc_v::{
+    components::{devices::chardev, system_bus::SystemBus},
+    config::{Config, SerialBackend, UartConfig},
+};
+
+#[test]
+fn parse_serial_backends() {
+    let parse = |s: &str| s.parse::<SerialBackend>();
+    assert_eq!(parse("stdio"), Ok(SerialBackend::Stdio));
+    assert_eq!(parse("null"), Ok(SerialBackend::Null));
+    assert_eq!(parse("pty"), Ok(SerialBackend::Pty));
+    assert_eq!(
+        parse("file:/tmp/uart.log"),
+        Ok(SerialBackend::File(PathBuf::from("/tmp/uart.log")))
+    );
+    // Only the first colon separates the path
+    assert_eq!(
+        parse("unix:/tmp/a:b"),
+        Ok(SerialBackend::Unix(PathBuf::from("/tmp/a:b")))
+    );
+    for invalid in ["", "file:", "unix:", "tcp:localhost:4444", "stdio:"] {
+        assert!(parse(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn file_backend_appends() {
+    let path = std::env::temp_dir().join(format!("risc_v_chardev_{}.log", std::process::id()));
+    let backend = SerialBackend::File(path.clone());
+    for line in [b"one\n", b"two\n"] {
+        let mut file = chardev::open(&backend, false).unwrap();
+        line.iter().for_each(|&byte| file.write(byte));
+        // No input from a file
+        assert_eq!(file.read(), None);
+    }
+    let output = fs::read_to_string(&path).unwrap();
+    fs::remove_file(path).unwrap();
+    assert_eq!(output, "one\ntwo\n");
+}
+
+#[test]
+fn backend_in_missing_directory() {
+    let config = Config {
+        uarts: vec![UartConfig {
+            backend: SerialBackend::File("/nonexistent/risc_v_uart.log".into()),
+            ..UartConfig::default()
+        }],
+        ..Config::default()
+    };
+    assert!(SystemBus::new(&config).is_err());
+}
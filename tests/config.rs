@@ -0,0 +1,83 @@
+use risc_v::config::{AiaMode, Config, DiskConfig, PlicConfig, SerialBackend, UartConfig};
+
+fn with_uart(base: u64, irq: u32) -> Config {
+    let mut config = Config::default();
+    config.uarts.push(UartConfig {
+        base,
+        irq,
+        backend: SerialBackend::Null,
+    });
+    config
+}
+
+#[test]
+fn extra_uart() {
+    assert_eq!(with_uart(0x1001_0000, 0x14).validate(), Ok(()));
+}
+
+#[test]
+fn uart_irq_in_use() {
+    // The one of the console, then of the RTC and of a virtio transport
+    assert!(with_uart(0x1001_0000, 0x0a).validate().is_err());
+    assert!(with_uart(0x1001_0000, 0x0b).validate().is_err());
+    assert!(with_uart(0x1001_0000, 0x03).validate().is_err());
+}
+
+#[test]
+fn uart_irq_past_sources() {
+    let config = Config {
+        plic: PlicConfig { sources: 0x20 },
+        ..with_uart(0x1001_0000, 0x21)
+    };
+    assert!(config.validate().is_err());
+    // The APLIC has its own number of sources
+    let config = Config {
+        aia: AiaMode::Aplic,
+        ..config
+    };
+    assert_eq!(config.validate(), Ok(()));
+}
+
+fn with_plic_sources(sources: u32, iommu: bool) -> Config {
+    Config {
+        plic: PlicConfig { sources },
+        iommu,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn plic_sources() {
+    assert!(with_plic_sources(0, false).validate().is_err());
+    assert!(with_plic_sources(1024, false).validate().is_err());
+    assert_eq!(with_plic_sources(1023, false).validate(), Ok(()));
+    // Up to the DMA controller, then the IOMMU
+    assert!(with_plic_sources(0x0b, false).validate().is_err());
+    assert_eq!(with_plic_sources(0x0c, false).validate(), Ok(()));
+    assert!(with_plic_sources(0x23, true).validate().is_err());
+    assert_eq!(with_plic_sources(0x24, true).validate(), Ok(()));
+}
+
+#[test]
+fn uart_overlapping() {
+    // Over the console, the DMA controller, a virtio transport and the RAM
+    assert!(with_uart(0x1000_0080, 0x14).validate().is_err());
+    assert!(with_uart(0x400_0000, 0x14).validate().is_err());
+    assert!(with_uart(0x1000_1000, 0x14).validate().is_err());
+    assert!(with_uart(0x8000_0000, 0x14).validate().is_err());
+    assert!(with_uart(u64::MAX - 0x7f, 0x14).validate().is_err());
+}
+
+#[test]
+fn too_many_disks() {
+    let disk = DiskConfig {
+        path: "disk.img".into(),
+        read_only: false,
+        sector_size: 512,
+    };
+    let config = Config {
+        disks: vec![disk; 9],
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,34 @@
+use risc_v::components::devices::console::{ESCAPE, EscapeDecoder, Key};
+
+fn decode(bytes: &[u8]) -> Vec<Key> {
+    let mut decoder = EscapeDecoder::new();
+    bytes.iter().filter_map(|&b| decoder.decode(b)).collect()
+}
+
+#[test]
+fn plain_keys() {
+    assert_eq!(
+        decode(b"ls\r"),
+        [Key::Send(b'l'), Key::Send(b's'), Key::Send(b'\r')]
+    );
+}
+
+#[test]
+fn quit_and_help() {
+    assert_eq!(decode(&[ESCAPE, b'x']), [Key::Quit]);
+    assert_eq!(decode(&[ESCAPE, b'h', b'a']), [Key::Help, Key::Send(b'a')]);
+}
+
+#[test]
+fn escaped_escape() {
+    // The second Ctrl-A is sent, and doesn't start a command
+    assert_eq!(
+        decode(&[ESCAPE, ESCAPE, b'x']),
+        [Key::Send(ESCAPE), Key::Send(b'x')]
+    );
+}
+
+#[test]
+fn unknown_command() {
+    assert_eq!(decode(&[ESCAPE, b'q', b'x']), [Key::Send(b'x')]);
+}
@@ -0,0 +1,84 @@
+use risc_v::{
+    config::{AiaMode, Config, PlicConfig, RamConfig, SerialBackend, UartConfig},
+    dts,
+};
+
+fn with_uart(aia: AiaMode) -> Config {
+    let mut config = Config {
+        aia,
+        ..Config::default()
+    };
+    config.uarts.push(UartConfig {
+        base: 0x1001_0000,
+        irq: 0x14,
+        backend: SerialBackend::Null,
+    });
+    config
+}
+
+#[test]
+fn uart_plic() {
+    let tree = dts::generate(&with_uart(AiaMode::None));
+    assert!(tree.contains("uart0: serial@10000000 {"));
+    assert!(tree.contains("uart1: serial@10010000 {"));
+    assert!(tree.contains("reg = <0x0 0x10010000 0x0 0x100>;"));
+    assert!(tree.contains("interrupts = <0x14>;"));
+    assert!(tree.contains("plic@c000000 {"));
+    assert!(tree.contains("virtio_mmio@10008000 {"));
+    assert!(!tree.contains("aplic@"));
+}
+
+#[test]
+fn uart_aplic() {
+    for aia in [AiaMode::Aplic, AiaMode::AplicImsic] {
+        let tree = dts::generate(&with_uart(aia));
+        assert!(tree.contains("interrupts = <0x14 0x4>;"));
+        assert!(tree.contains("interrupt-parent = <0x6>;"));
+        assert!(!tree.contains("plic@"));
+        assert_eq!(tree.contains("imsics@"), aia == AiaMode::AplicImsic);
+    }
+}
+
+#[test]
+fn plic_sources() {
+    let tree = dts::generate(&Config {
+        plic: PlicConfig { sources: 0x60 },
+        ..Config::default()
+    });
+    assert!(tree.contains("riscv,ndev = <0x60>;"));
+}
+
+#[test]
+fn iommu() {
+    let tree = dts::generate(&Config::default());
+    assert!(!tree.contains("iommu"));
+    let tree = dts::generate(&Config {
+        iommu: true,
+        ..Config::default()
+    });
+    assert!(tree.contains("iommu: iommu@3010000 {"));
+    assert!(tree.contains("iommus = <&iommu 0x1>;"));
+    // The last virtio transport
+    assert!(tree.contains("iommus = <&iommu 0x17>;"));
+}
+
+#[test]
+fn memory() {
+    let tree = dts::generate(&Config {
+        ram: RamConfig {
+            base: 0x1_0000_0000,
+            size: 16 << 30,
+            ..RamConfig::default()
+        },
+        ..Config::default()
+    });
+    assert!(tree.contains("memory@100000000 {"));
+    assert!(tree.contains("reg = <0x1 0x0 0x4 0x0>;"));
+}
+
+#[test]
+fn balanced() {
+    let tree = dts::generate(&with_uart(AiaMode::AplicImsic));
+    assert_eq!(tree.matches('{').count(), tree.matches("};").count());
+    assert!(tree.ends_with("};\n"));
+}
@@ -14,14 +14,32 @@ pub fn load_binary(name: &str) -> Vec<u8> {
 #[macro_export]
 macro_rules! define_test {
     ($fn_name:ident, |$arg:ident| $body:block) => {
+        $crate::define_test!($fn_name, risc_v::config::Config::default(), |$arg| $body);
+    };
+    // Run with the machine configured by `$config`
+    ($fn_name:ident, $config:expr, |$arg:ident| $body:block) => {
         #[test]
         fn $fn_name() {
-            let mut cpu = Cpu::new();
+            let mut cpu = Cpu::with_config(&$config).unwrap();
 
             let bin = crate::helper::load_binary(stringify!($fn_name));
             cpu.mmu.inject(DRAM_BASE, &bin);
             cpu.run();
 
+            let $arg = cpu;
+            $body;
+        }
+    };
+    // With the exit code of the run, none when stopped on an error
+    ($fn_name:ident, $config:expr, |$arg:ident, $code:ident| $body:block) => {
+        #[test]
+        fn $fn_name() {
+            let mut cpu = Cpu::with_config(&$config).unwrap();
+
+            let bin = crate::helper::load_binary(stringify!($fn_name));
+            cpu.mmu.inject(DRAM_BASE, &bin);
+            let $code = cpu.run();
+
             let $arg = cpu;
             $body;
         }
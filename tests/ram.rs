@@ -0,0 +1,79 @@
+use risc_v::{
+    components::{devices::dram::Dram, mmu::Size, system_bus::SystemBus},
+    config::{Config, RamBacking, RamConfig, parse_size},
+};
+
+#[test]
+fn parse_sizes() {
+    assert_eq!(parse_size("4096"), Ok(4096));
+    assert_eq!(parse_size("0x80000000"), Ok(0x8000_0000));
+    assert_eq!(parse_size("4k"), Ok(4 << 10));
+    assert_eq!(parse_size("512M"), Ok(512 << 20));
+    assert_eq!(parse_size("16G"), Ok(16 << 30));
+    assert_eq!(parse_size("0x2T"), Ok(2 << 40));
+    assert!(parse_size("").is_err());
+    assert!(parse_size("0x").is_err());
+    assert!(parse_size("12Q").is_err());
+    assert!(parse_size("-1").is_err());
+    assert!(parse_size("0x1000000000000000K").is_err());
+}
+
+#[test]
+fn ram_past_address_space() {
+    let config = RamConfig {
+        base: 0xffff_ffff_0000_0000,
+        size: 8 << 30,
+        backing: RamBacking::Sparse,
+    };
+    assert!(config.end().is_err());
+    let config = RamConfig {
+        size: 4 << 30,
+        ..config
+    };
+    assert_eq!(config.end(), Ok(0));
+}
+
+#[test]
+fn sparse_pages() {
+    // Far more than the host could allocate at once
+    let mut dram = Dram::new(&RamConfig {
+        size: 1 << 40,
+        ..RamConfig::default()
+    })
+    .unwrap();
+    assert_eq!(dram.read(0x10_0000_0000, Size::DWORD).unwrap(), 0);
+
+    // Across two pages, of two chunks
+    let address = (2 << 20) - 4;
+    dram.write(address, Size::DWORD, 0x0123_4567_89ab_cdef)
+        .unwrap();
+    assert_eq!(
+        dram.read(address, Size::DWORD).unwrap(),
+        0x0123_4567_89ab_cdef
+    );
+    assert_eq!(dram.read(address + 4, Size::WORD).unwrap(), 0x0123_4567);
+    let mut bytes = [0xff; 16];
+    dram.read_bytes(address - 4, &mut bytes);
+    assert_eq!(bytes[..4], [0; 4]);
+    assert_eq!(bytes[4..12], 0x0123_4567_89ab_cdef_u64.to_le_bytes());
+    assert_eq!(bytes[12..], [0; 4]);
+
+    // The last bytes of the RAM, and past them
+    let last = (1 << 40) - 8;
+    dram.write(last, Size::DWORD, u64::MAX).unwrap();
+    assert_eq!(dram.read(last, Size::DWORD).unwrap(), u64::MAX);
+    assert!(dram.read(last + 4, Size::DWORD).is_err());
+    assert!(dram.write(1 << 40, Size::BYTE, 0).is_err());
+}
+
+#[test]
+fn backing_in_missing_directory() {
+    let config = Config {
+        ram: RamConfig {
+            backing: RamBacking::File("/nonexistent/risc_v_ram.img".into()),
+            ..RamConfig::default()
+        },
+        ..Config::default()
+    };
+    assert!(SystemBus::new(&config).is_err());
+}
@@ -0,0 +1,608 @@
+mod helper;
+use crate::helper::assert_xregs;
+use risc_v::{
+    components::{
+        csr::{MCAUSE, MEPC, MTVAL},
+        registers::XRegisters,
+        system_bus::DRAM_BASE,
+    },
+    config::{
+        AiaMode, Config, DiskConfig, MisalignedPolicy, RamBacking, RamConfig, SerialBackend,
+        UartConfig, UnmappedPolicy,
+    },
+    cpu::Cpu,
+};
+
+/// A file of its own for each run of the tests.
+fn ram_file_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("risc_v_ram_{}.img", std::process::id()))
+}
+
+/// Log of the console UART, not shared with other runs either.
+fn uart_file_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("risc_v_uart_{}.log", std::process::id()))
+}
+
+/// Image of the block device, not shared with other runs either.
+fn disk_file_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("risc_v_virtio_blk_{}.img", std::process::id()))
+}
+
+/* @Note for trap tests:
+ * s1: mcause
+ * s2: mepc
+ * s3: mtval
+ * s4: address of the faulting instruction
+ * */
+
+define_test!(illegal, |cpu| {
+    let fault = cpu.x_regs.read(XRegisters::s4);
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 2),
+            (XRegisters::s2, fault),
+            (XRegisters::s3, 0xffff_ffff),
+        ],
+    );
+});
+define_test!(load_fault, |cpu| {
+    let fault = cpu.x_regs.read(XRegisters::s4);
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 5),
+            (XRegisters::s2, fault),
+            (XRegisters::s3, 0x5000_0000),
+        ],
+    );
+});
+define_test!(reserved_branch, |cpu| {
+    let fault = cpu.x_regs.read(XRegisters::s4);
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 2),
+            (XRegisters::s2, fault),
+            (XRegisters::s3, 0x2063),
+        ],
+    );
+});
+define_test!(csr_access, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 2),
+            // csrw mhartid, zero
+            (XRegisters::s2, 0xf140_1073),
+            // csrr s5, senvcfg, then allowed by mstateen0
+            (XRegisters::s3, 0x10a0_2af3),
+            (XRegisters::s6, 0),
+        ],
+    );
+});
+define_test!(misaligned_load, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 0xaa88_7766_5544_3322),
+            (XRegisters::s2, 0x7766_5544),
+        ],
+    );
+});
+define_test!(
+    misaligned_trap,
+    Config {
+        misaligned: MisalignedPolicy::Trap,
+        ..Config::default()
+    },
+    |cpu| {
+        let fault = cpu.x_regs.read(XRegisters::s4);
+        let address = cpu.x_regs.read(XRegisters::s8);
+        assert_xregs(
+            &cpu,
+            &[
+                // Load address misaligned
+                (XRegisters::s1, 4),
+                (XRegisters::s2, fault),
+                (XRegisters::s3, address),
+                // Store address misaligned
+                (XRegisters::s5, 6),
+                (XRegisters::s6, address + 2),
+                (XRegisters::s7, 0x8877_6655_4433_2211),
+            ],
+        );
+    }
+);
+define_test!(misaligned_amo, |cpu| {
+    let fault = cpu.x_regs.read(XRegisters::s4);
+    let address = cpu.x_regs.read(XRegisters::s5);
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 7),
+            (XRegisters::s2, fault),
+            (XRegisters::s3, address),
+        ],
+    );
+});
+define_test!(mmio_amo, |cpu| {
+    let fault = cpu.x_regs.read(XRegisters::s4);
+    let address = cpu.x_regs.read(XRegisters::s5);
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 7),
+            (XRegisters::s2, fault),
+            (XRegisters::s3, address),
+        ],
+    );
+});
+define_test!(
+    unmapped_ignore,
+    Config {
+        unmapped: UnmappedPolicy::Ignore,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                (XRegisters::s1, 0),
+                (XRegisters::s2, 0),
+                // No access fault
+                (XRegisters::s3, 0),
+            ],
+        );
+    }
+);
+define_test!(
+    unmapped_stop,
+    Config {
+        unmapped: UnmappedPolicy::Stop,
+        ..Config::default()
+    },
+    |cpu| {
+        // Stopped as the access fault is taken, before the handler runs
+        let fault = cpu.x_regs.read(XRegisters::s4);
+        assert_xregs(&cpu, &[(XRegisters::s1, 1)]);
+        assert_eq!(cpu.csr.read(MCAUSE), 7);
+        assert_eq!(cpu.csr.read(MEPC), fault);
+        assert_eq!(cpu.csr.read(MTVAL), 0x5000_0000);
+    }
+);
+define_test!(sfence, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 0xaaaa),
+            (XRegisters::s2, 0xaaaa),
+            (XRegisters::s3, 0xbbbb),
+        ],
+    );
+});
+define_test!(
+    big_endian,
+    Config {
+        big_endian: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // Loads
+                (XRegisters::s1, 0x7766_5544_3322_1100),
+                (XRegisters::s2, 0x7766),
+                // The AMO reads and writes big-endian
+                (XRegisters::s3, 1),
+                // The bytes in memory, as read little-endian
+                (XRegisters::s4, 0x0403_0201),
+                (XRegisters::s5, 0x0200_0000),
+                // SBE in S-mode
+                (XRegisters::s6, 0x7766_5544_3322_1100),
+            ],
+        );
+    }
+);
+define_test!(
+    ram_file,
+    Config {
+        ram: RamConfig {
+            backing: RamBacking::File(ram_file_path()),
+            ..RamConfig::default()
+        },
+        ..Config::default()
+    },
+    |cpu| {
+        use std::os::unix::fs::FileExt;
+        drop(cpu);
+        let path = ram_file_path();
+        let mut bytes = [0; 8];
+        let file = std::fs::File::open(&path).unwrap();
+        file.read_exact_at(&mut bytes, 0x10_0000).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(bytes, 0x0123_4567_89ab_cdef_u64.to_le_bytes());
+    }
+);
+define_test!(dmac, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 0x0123_4567_89ab_cdef),
+            // The 4 bytes past the transfer are left untouched
+            (XRegisters::s2, 0xffff_ffff_cafe_babe),
+            // DONE
+            (XRegisters::s3, 2),
+            (XRegisters::s4, 0),
+        ],
+    );
+});
+define_test!(
+    iommu,
+    Config {
+        iommu: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // ERROR
+                (XRegisters::s1, 4),
+                // All inbound transactions disallowed, on an untranslated read of the device 1
+                (XRegisters::s2, 256 | (2 << 34) | (1 << 40)),
+                (XRegisters::s3, 1),
+                // DONE
+                (XRegisters::s4, 2),
+                (XRegisters::s5, 0x0123_4567_89ab_cdef),
+            ],
+        );
+    }
+);
+define_test!(
+    iommu_gstage,
+    Config {
+        iommu: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // DONE
+                (XRegisters::s1, 2),
+                (XRegisters::s2, 0x0123_4567_89ab_cdef),
+                // A and D set by the second stage
+                (XRegisters::s3, (DRAM_BASE >> 12) << 10 | 0xd7),
+                // ERROR
+                (XRegisters::s4, 4),
+                // Read guest page fault of the device 1, with the guest-physical address in iotval2
+                (XRegisters::s5, 21 | (2 << 34) | (1 << 40)),
+                (XRegisters::s6, 1 << 30),
+                (XRegisters::s7, 1 << 30),
+            ],
+        );
+    }
+);
+define_test!(
+    iommu_pdt,
+    Config {
+        iommu: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // ERROR
+                (XRegisters::s1, 4),
+                // Invalid process directory entry, on a read of the device 1
+                (XRegisters::s2, 266 | (2 << 34) | (1 << 40)),
+                // DONE
+                (XRegisters::s3, 2),
+                (XRegisters::s4, 0x0123_4567_89ab_cdef),
+            ],
+        );
+    }
+);
+define_test!(
+    iommu_msi,
+    Config {
+        aia: AiaMode::AplicImsic,
+        iommu: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // ERROR
+                (XRegisters::s1, 4),
+                // Invalid MSI PTE, on a write of the device 1
+                (XRegisters::s2, 262 | (3 << 34) | (1 << 40)),
+                // DONE
+                (XRegisters::s3, 2),
+                // The identity pending in the S-level file
+                (XRegisters::s4, 1 << 9),
+            ],
+        );
+    }
+);
+define_test!(
+    iommu_cq,
+    Config {
+        iommu: true,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                (XRegisters::s1, 0x1234_5678),
+                // cqon, fence_w_ip, cqie and cqen
+                (XRegisters::s2, 1 << 16 | 1 << 11 | 1 << 1 | 1),
+                // cip
+                (XRegisters::s3, 1),
+                // Stopped at the illegal command, with cmd_ill set
+                (XRegisters::s4, 2),
+                (XRegisters::s5, 1 << 16 | 1 << 11 | 1 << 10 | 1 << 1 | 1),
+            ],
+        );
+    }
+);
+define_test!(clint, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            // Machine software interrupt
+            (XRegisters::s1, 0x8000_0000_0000_0003),
+            (XRegisters::s2, 1 << 3),
+            (XRegisters::s3, 0),
+            // Machine timer interrupt
+            (XRegisters::s4, 0x8000_0000_0000_0007),
+            (XRegisters::s5, 0),
+        ],
+    );
+});
+define_test!(finisher_reset, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 1),
+            // The registers and CSRs are back to their reset values
+            (XRegisters::s2, 0),
+            (XRegisters::s3, 0),
+        ],
+    );
+});
+define_test!(syscon, Config::default(), |cpu, code| {
+    // Powered off, with a success
+    assert_eq!(code, Some(0));
+    assert_xregs(&cpu, &[(XRegisters::s1, 0xabcd)]);
+});
+define_test!(syscon_reboot, Config::default(), |cpu, code| {
+    assert_eq!(code, Some(0));
+    assert_xregs(
+        &cpu,
+        &[
+            (XRegisters::s1, 1),
+            // The syscon registers are back to their reset values
+            (XRegisters::s2, 0),
+            (XRegisters::s3, 0),
+        ],
+    );
+});
+define_test!(rtc, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            // Machine external interrupt
+            (XRegisters::s1, 0x8000_0000_0000_000b),
+            // The alarm fired
+            (XRegisters::s2, 0),
+            (XRegisters::s3, 1),
+        ],
+    );
+});
+define_test!(
+    uart_file,
+    Config {
+        uarts: vec![UartConfig {
+            backend: SerialBackend::File(uart_file_path()),
+            ..UartConfig::default()
+        }],
+        ..Config::default()
+    },
+    |cpu| {
+        drop(cpu);
+        let path = uart_file_path();
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(output, "ok\n");
+    }
+);
+define_test!(
+    rtc_epoch,
+    Config {
+        rtc_epoch: Some(5),
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // 10ns a cycle
+                (XRegisters::s1, 10),
+                // 5s is past the low half
+                (XRegisters::s2, 5_000_000_000 >> 32),
+            ],
+        );
+    }
+);
+define_test!(uart_loopback, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            // Deltas of CTS, DSR and DCD, cleared by the read, then set with RI
+            (XRegisters::s1, 0x0b),
+            (XRegisters::s2, 0),
+            (XRegisters::s3, 0xfb),
+            // Received in the order sent
+            (XRegisters::s4, 0x61_62_63),
+            (XRegisters::s5, 0x61),
+            (XRegisters::s6, 0x60),
+            (XRegisters::s7, 3),
+            // Character timeout, then no interrupt
+            (XRegisters::s8, 0xcc),
+            (XRegisters::s9, 0xc1),
+        ],
+    );
+});
+define_test!(
+    uart_second,
+    Config {
+        uarts: vec![
+            UartConfig::default(),
+            UartConfig {
+                base: 0x1000_0100,
+                irq: 13,
+                backend: SerialBackend::Null,
+            },
+        ],
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // Machine external interrupt
+                (XRegisters::s1, 0x8000_0000_0000_000b),
+                (XRegisters::s2, 'x' as u64),
+                // THRE and TEMT only
+                (XRegisters::s3, 0x60),
+            ],
+        );
+    }
+);
+define_test!(plic, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            // The highest priority first, then none while both are in service
+            (XRegisters::s1, 11),
+            (XRegisters::s2, 10),
+            (XRegisters::s3, 0),
+            (XRegisters::s4, 11),
+            (XRegisters::s5, 11),
+            (XRegisters::s6, 0),
+            // MEIP low, the UART being below the threshold
+            (XRegisters::s7, 0),
+            (XRegisters::s8, 10),
+        ],
+    );
+});
+define_test!(mip_seip, |cpu| {
+    assert_xregs(
+        &cpu,
+        &[
+            // SSIP set along the SEIP signal
+            (XRegisters::s1, 1 << 9 | 1 << 1),
+            (XRegisters::s2, 11),
+            // SEIP gone with the claim, not latched by the csrs
+            (XRegisters::s3, 1 << 1),
+        ],
+    );
+});
+define_test!(
+    virtio_blk,
+    {
+        // Sector n filled with n
+        let image: Vec<u8> = (0..4).flat_map(|n| [n; 512]).collect();
+        let path = disk_file_path();
+        std::fs::write(&path, image).unwrap();
+        Config {
+            disks: vec![DiskConfig {
+                path,
+                read_only: false,
+                sector_size: 512,
+            }],
+            ..Config::default()
+        }
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // "virt", version 2, block device
+                (XRegisters::s1, 0x7472_6976),
+                (XRegisters::s2, 2),
+                (XRegisters::s3, 2),
+                // FEATURES_OK kept
+                (XRegisters::s4, 0xb),
+                // Acknowledged
+                (XRegisters::s5, 0),
+                // The data and the status
+                (XRegisters::s6, 513),
+                (XRegisters::s7, 0),
+                (XRegisters::s8, 0x0101_0101_0101_0101),
+                (XRegisters::s9, 0),
+            ],
+        );
+        drop(cpu);
+        let path = disk_file_path();
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(
+            image[1024..1536]
+                .chunks(8)
+                .all(|word| word == 0x0123_4567_89ab_cdef_u64.to_le_bytes())
+        );
+    }
+);
+define_test!(
+    aplic,
+    Config {
+        aia: AiaMode::Aplic,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                // The source and its priority
+                (XRegisters::s1, 20 << 16 | 3),
+                (XRegisters::s2, 0),
+                (XRegisters::s3, 0),
+                // Machine external interrupt
+                (XRegisters::s4, 0x8000_0000_0000_000b),
+                (XRegisters::s5, 20 << 16 | 3),
+                (XRegisters::s6, 0),
+            ],
+        );
+    }
+);
+define_test!(
+    aplic_imsic,
+    Config {
+        aia: AiaMode::AplicImsic,
+        ..Config::default()
+    },
+    |cpu| {
+        assert_xregs(
+            &cpu,
+            &[
+                (XRegisters::s1, 1 << 5),
+                // The read-only bit 31, IE and DM, sign extended by the load
+                (XRegisters::s2, 0xffff_ffff_8000_0104),
+                // Machine external interrupt
+                (XRegisters::s3, 0x8000_0000_0000_000b),
+                // The identity in both the identity and priority fields
+                (XRegisters::s4, 5 << 16 | 5),
+                (XRegisters::s5, 0),
+                (XRegisters::s6, 7 << 16 | 7),
+                (XRegisters::s7, 0),
+            ],
+        );
+    }
+);
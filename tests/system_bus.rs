@@ -0,0 +1,144 @@
+use risc_v::{
+    components::{
+        devices::{dmac::DMAC_DEVICE_ID, test::Test},
+        system_bus::{PLIC_BASE, RegionError, SystemBus, UART0_BASE},
+    },
+    config::{Config, DiskConfig},
+};
+
+fn bus() -> SystemBus {
+    SystemBus::new(&Config::default()).unwrap()
+}
+
+#[test]
+fn register_overlapping_device() {
+    let mut bus = bus();
+    let result = bus.register(
+        "overlap",
+        UART0_BASE + 0x80,
+        0x1000,
+        None,
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(matches!(
+        result,
+        Err(RegionError::Overlap { other: "uart0", .. })
+    ));
+}
+
+#[test]
+fn register_overlapping_fixed_region() {
+    let mut bus = bus();
+    let result = bus.register(
+        "overlap",
+        PLIC_BASE,
+        0x1000,
+        None,
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(matches!(
+        result,
+        Err(RegionError::Overlap { other: "plic", .. })
+    ));
+}
+
+#[test]
+fn register_past_address_space() {
+    let mut bus = bus();
+    let result = bus.register(
+        "wrap",
+        u64::MAX - 0xfff,
+        0x2000,
+        None,
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(matches!(result, Err(RegionError::OutOfRange { .. })));
+}
+
+#[test]
+fn register_missing_irq() {
+    let mut bus = bus();
+    let sources = bus.interrupt_sources();
+    for irq in [0, sources + 1] {
+        let result = bus.register(
+            "irq",
+            0x3000_0000,
+            0x1000,
+            Some(irq),
+            None,
+            Box::new(Test::new()),
+        );
+        assert!(matches!(result, Err(RegionError::InvalidIrq { irq: i, .. }) if i == irq));
+    }
+    let result = bus.register(
+        "irq",
+        0x3000_0000,
+        0x1000,
+        Some(sources),
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(result.is_ok());
+}
+
+#[test]
+fn register_in_hole() {
+    let mut bus = bus();
+    let result = bus.register(
+        "hole",
+        0x3000_0000,
+        0x1000,
+        None,
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(result.is_ok());
+    assert!(bus.device::<Test>("hole").is_some());
+    // Nor over the newly registered region
+    let result = bus.register(
+        "again",
+        0x3000_0800,
+        0x1000,
+        None,
+        None,
+        Box::new(Test::new()),
+    );
+    assert!(matches!(
+        result,
+        Err(RegionError::Overlap { other: "hole", .. })
+    ));
+}
+
+#[test]
+fn register_device_id_in_use() {
+    let mut bus = bus();
+    let result = bus.register(
+        "master",
+        0x3000_0000,
+        0x1000,
+        None,
+        Some(DMAC_DEVICE_ID),
+        Box::new(Test::new()),
+    );
+    assert!(matches!(
+        result,
+        Err(RegionError::DeviceIdInUse { other: "dmac", .. })
+    ));
+    assert_eq!(bus.device_id("dmac"), Some(DMAC_DEVICE_ID));
+}
+
+#[test]
+fn missing_disk_image() {
+    let config = Config {
+        disks: vec![DiskConfig {
+            path: "/nonexistent/risc_v_disk.img".into(),
+            read_only: true,
+            sector_size: 512,
+        }],
+        ..Config::default()
+    };
+    assert!(SystemBus::new(&config).is_err());
+}